@@ -0,0 +1,26 @@
+//!
+//! 用 new_with_channel 合成一段文本, 把音频分片收集到一个缓冲区后写入 result_24k.pcm
+//!
+//! DASHSCOPE_API_KEY=sk-xxx cargo run --example collect_audio
+use qwen_tts_falsh_realtime_rs::{AudioFormat, QwenTtsRealtime};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let api_key = std::env::var("DASHSCOPE_API_KEY")?;
+    let (mut qwen_tts_realtime, mut audio_rx) =
+        QwenTtsRealtime::new_with_channel("qwen3-tts-flash-realtime", &api_key, None, None).await?;
+    qwen_tts_realtime
+        .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+        .await?;
+    qwen_tts_realtime.append_text("对吧~我就特别喜欢这种超市，").await?;
+    qwen_tts_realtime.append_text("尤其是过年的时候去逛超市。").await?;
+    qwen_tts_realtime.finish().await?;
+
+    let mut pcm = Vec::new();
+    while let Some(audio_bytes) = audio_rx.recv().await {
+        pcm.extend_from_slice(&audio_bytes);
+    }
+    println!("收到 {} 字节音频", pcm.len());
+    std::fs::write("result_24k.pcm", &pcm)?;
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use arrow_schema::ArrowError;
 use hmac::digest::InvalidLength;
+use std::time::Duration;
 use thiserror::Error;
+use tokio_tungstenite::tungstenite;
 use tokio_tungstenite::tungstenite::http;
 
 #[derive(Debug, Error)]
@@ -33,5 +35,122 @@ pub enum GenerationError {
     NoRegionInEndpointError(#[from] regex::Error),
 
     #[error("Odps 发送arrow数据失败: {0}")]
-    OdpsArrowError(#[from] ArrowError)
+    OdpsArrowError(#[from] ArrowError),
+
+    #[error("Odps 服务端返回异常, 状态码 {status}: {body}")]
+    OdpsResponseError { status: u16, body: String },
+}
+
+///
+/// 实时合成模块统一的错误类型, 按失败环节区分:
+/// - 建连: WebSocketError、InvalidUrl、InvalidHeader、MissingApiKey、ApiKeyFile、Tls、DnsFailure,
+///   服务端拒绝升级 HandshakeRejected, 鉴权 Unauthorized, 限流 RateLimited
+/// - 发送: ConnectionClosed(连接已断开)、MiddlewareRejected
+/// - 数据: SerdeJsonError(解析失败)、ProtocolViolation(数据不符合协议)
+/// - 会话: SessionClosedEarly、SessionNotConfigured、InvalidState、Timeout、ReconnectRequested、
+///   AudioSizeLimitExceeded
+#[derive(Debug, Error)]
+pub enum QwenTtsError {
+    #[error("WebSocket 错误: {0}")]
+    WebSocketError(#[from] tungstenite::Error),
+
+    #[error("serde json 转换错误: {0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+
+    #[error("事件被中间件拒绝: {0}")]
+    MiddlewareRejected(String),
+
+    #[error("IO 错误: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("会话未正常结束: {0}")]
+    SessionClosedEarly(String),
+
+    #[error("会话尚未配置, 请先调用 update_session")]
+    SessionNotConfigured,
+
+    #[error("当前状态不允许该操作: {0}")]
+    InvalidState(String),
+
+    #[error("等待超时: {0}")]
+    Timeout(String),
+
+    #[error("服务端要求重连: {0}")]
+    ReconnectRequested(String),
+
+    #[error("url 不合法: {0}")]
+    InvalidUrl(String),
+
+    #[error("未提供API Key, 请传入参数或设置环境变量 DASHSCOPE_API_KEY")]
+    MissingApiKey,
+
+    #[error("无法从文件 {path} 读取API Key: {reason}")]
+    ApiKeyFile { path: String, reason: String },
+
+    /// 主机名解析失败, 与连接被拒绝(WebSocketError 中的IO错误)区分
+    #[error("DNS 解析失败: {host}: {reason}")]
+    DnsFailure { host: String, reason: String },
+
+    #[error("音频超过上限 {limit} 字节, 会话已中止")]
+    AudioSizeLimitExceeded { limit: usize },
+
+    #[error("请求头格式错误: {0}")]
+    InvalidHeader(#[from] http::header::InvalidHeaderValue),
+
+    #[error("连接已关闭, 无法继续发送")]
+    ConnectionClosed,
+
+    #[error("服务端返回了不符合协议的数据: {0}")]
+    ProtocolViolation(String),
+
+    #[error("Generation 请求错误: {0}")]
+    GenerationError(#[from] GenerationError),
+
+    #[error("TLS 握手失败: {0}")]
+    Tls(String),
+
+    #[error("服务端拒绝 WebSocket 升级(HTTP {status}): {body}")]
+    HandshakeRejected { status: u16, body: String },
+
+    #[error("鉴权失败(HTTP {status}), 请检查API Key或workspace: {body}")]
+    Unauthorized { status: u16, body: String },
+
+    #[error("请求被限流(HTTP {status}), 建议等待: {retry_after:?}")]
+    RateLimited {
+        status: u16,
+        // 服务端Retry-After响应头给出的等待时间
+        retry_after: Option<Duration>,
+    },
+}
+
+impl QwenTtsError {
+    /// 网络抖动、握手返回5xx、连接中途断开等可以通过重试恢复的错误
+    pub fn is_transient(&self) -> bool {
+        match self {
+            QwenTtsError::WebSocketError(e) => match e {
+                tungstenite::Error::Http(response) => response.status().is_server_error(),
+                tungstenite::Error::Io(_)
+                | tungstenite::Error::ConnectionClosed
+                | tungstenite::Error::AlreadyClosed
+                | tungstenite::Error::Protocol(_) => true,
+                _ => false,
+            },
+            QwenTtsError::HandshakeRejected { status, .. } => *status >= 500,
+            QwenTtsError::SessionClosedEarly(_)
+            | QwenTtsError::RateLimited { .. }
+            | QwenTtsError::Timeout(_)
+            | QwenTtsError::ReconnectRequested(_)
+            | QwenTtsError::DnsFailure { .. }
+            | QwenTtsError::ConnectionClosed => true,
+            _ => false,
+        }
+    }
+
+    /// 服务端建议的重试等待时间
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            QwenTtsError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
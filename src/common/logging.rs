@@ -21,25 +21,72 @@ fn my_log_format(
         record.args()
     )
 }
-pub(crate) fn init_logger(level: &str) -> LoggerHandle {
+///
+/// 日志文件的可选项; compress_rotated 只在启用 log-compress feature 时提供,
+/// 开启后切割出的旧文件压缩为 .gz, 正在写入的文件仍是明文
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    directory: String,
+    #[cfg(feature = "log-compress")]
+    compress_rotated: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            directory: "logs".to_string(),
+            #[cfg(feature = "log-compress")]
+            compress_rotated: false,
+        }
+    }
+}
+
+impl LogOptions {
+    pub fn directory(mut self, directory: &str) -> Self {
+        self.directory = directory.to_string();
+        self
+    }
+
+    #[cfg(feature = "log-compress")]
+    pub fn compress_rotated(mut self, compress_rotated: bool) -> Self {
+        self.compress_rotated = compress_rotated;
+        self
+    }
+
+    // 保留最近 30 天的日志
+    fn cleanup(&self) -> Cleanup {
+        #[cfg(feature = "log-compress")]
+        if self.compress_rotated {
+            return Cleanup::KeepCompressedFiles(30);
+        }
+        Cleanup::KeepLogFiles(30)
+    }
+}
+
+fn logger(level: &str, options: &LogOptions) -> Logger {
     Logger::try_with_str(level)
         .unwrap()
         .format(my_log_format)
         .log_to_file(
             FileSpec::default()
-                .directory("logs") // 日志保存目录
+                .directory(&options.directory) // 日志保存目录
                 .basename("qwen-tts-flash-realtime-rs"), // 文件名前缀
         )
         .rotate(
             Criterion::Age(flexi_logger::Age::Day), // 每天午夜切割
             Naming::Timestamps,                     // 旧文件以时间戳命名
-            Cleanup::KeepLogFiles(30),              // 保留最近 30 天的日志
+            options.cleanup(),
         )
         .duplicate_to_stderr(Duplicate::All)
-        .start()
-        .unwrap()
 }
 
+pub fn init_logger(level: &str) -> LoggerHandle {
+    init_logger_with(level, &LogOptions::default())
+}
+
+pub fn init_logger_with(level: &str, options: &LogOptions) -> LoggerHandle {
+    logger(level, options).start().unwrap()
+}
 
 #[cfg(test)]
 mod tests {
@@ -52,4 +99,35 @@ mod tests {
         log::warn!("This is a warning message");
         log::error!("This is an error message");
     }
+
+    #[cfg(feature = "log-compress")]
+    #[test]
+    fn test_rotated_logs_compressed() {
+        let directory = std::env::temp_dir().join(format!("{}_logs", uuid::Uuid::new_v4()));
+        let options = LogOptions::default()
+            .directory(directory.to_str().unwrap())
+            .compress_rotated(true);
+        // 不安装为全局logger, 避免与其他测试冲突
+        let (logger, handle) = logger("info", &options)
+            .cleanup_in_background_thread(false)
+            .build()
+            .unwrap();
+        for _ in 0..2 {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("rotate me"))
+                    .level(log::Level::Info)
+                    .build(),
+            );
+            handle.trigger_rotation().unwrap();
+        }
+        let files = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert!(files.iter().any(|name| name.ends_with(".log.gz")), "{:?}", files);
+        // 正在写入的文件保持明文
+        assert!(files.iter().any(|name| name.ends_with("_rCURRENT.log")), "{:?}", files);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 pub mod logging;
 pub mod errors;
 
+/// 返回 (系统名, 版本), 如 ("Windows-11", "10.0.22621")、("Linux", "6.8.0")、("macOS", "14.5")
 pub fn get_platform_info() -> (String, String) {
     #[cfg(target_os = "windows")]{
         // 1. 獲取 Platform (Windows 11 10.0.22621)
@@ -19,29 +20,97 @@ pub fn get_platform_info() -> (String, String) {
         (os_name.to_string(), full_ver)
     }
     #[cfg(target_os = "linux")]{
-        let os_name = sys_info::os_type().unwrap_or("Unknown".to_string());
+        // 内核版本, 与 uname -r 一致
         let os_version = sys_info::os_release().unwrap_or("Unknown Version".to_string());
+        ("Linux".to_string(), os_version)
+    }
+    #[cfg(target_os = "macos")]{
+        let os_version = command_output("sw_vers", &["-productVersion"])
+            .unwrap_or("Unknown Version".to_string());
+        ("macOS".to_string(), os_version)
+    }
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]{
+        let os_name = command_output("uname", &["-s"]).unwrap_or(std::env::consts::OS.to_string());
+        let os_version = command_output("uname", &["-r"]).unwrap_or("Unknown Version".to_string());
         (os_name, os_version)
     }
+    #[cfg(not(any(windows, unix)))]{
+        (std::env::consts::OS.to_string(), "Unknown Version".to_string())
+    }
 }
 
-pub fn get_processor_info() -> String {
-    let cpuid = raw_cpuid::CpuId::new();
-    if let Some(fms) = cpuid.get_feature_info() {
-        let arch = std::env::consts::ARCH; // 或透過環境變數 std::env::consts::ARCH 獲取
-        let vendor = cpuid
-            .get_vendor_info()
-            .map(|v| v.to_string())
-            .unwrap_or("Unknown".to_string());
-        format!(
-            "{} Family {} Model {} Stepping {}, {}",
-            arch,
-            fms.family_id(),
-            fms.model_id(),
-            fms.stepping_id(),
-            vendor
-        )
+/// user-agent 中 platform/ 之后的部分, 如 Windows-11-10.0.22621-SP0、Linux-6.8.0、macOS-14.5
+pub fn get_platform_string() -> String {
+    let (os_name, version) = get_platform_info();
+    if cfg!(windows) {
+        format!("{}-{}-SP0", os_name, version)
     } else {
-        "Unknown Processor".to_string()
+        format!("{}-{}", os_name, version)
+    }
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    let text = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !text.is_empty()).then_some(text)
+}
+
+/// 处理器信息, 如 x86_64 Family 6 Model 154 Stepping 3, GenuineIntel;
+/// cpuid 只在 x86/x86_64 上可用, 其他架构(或读不到 cpuid)时只返回 std::env::consts::ARCH
+pub fn get_processor_info() -> String {
+    let arch = std::env::consts::ARCH;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]{
+        let cpuid = raw_cpuid::CpuId::new();
+        if let Some(fms) = cpuid.get_feature_info() {
+            let vendor = cpuid
+                .get_vendor_info()
+                .map(|v| v.to_string())
+                .unwrap_or("Unknown".to_string());
+            return format!(
+                "{} Family {} Model {} Stepping {}, {}",
+                arch,
+                fms.family_id(),
+                fms.model_id(),
+                fms.stepping_id(),
+                vendor
+            );
+        }
+    }
+    arch.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_processor_info_starts_with_arch() {
+        let processor = get_processor_info();
+        assert!(processor.starts_with(std::env::consts::ARCH), "{}", processor);
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_platform_string_windows() {
+        let platform = get_platform_string();
+        assert!(platform.starts_with("Windows-"), "{}", platform);
+        assert!(platform.ends_with("-SP0"), "{}", platform);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_platform_string_linux() {
+        let platform = get_platform_string();
+        assert!(platform.starts_with("Linux-"), "{}", platform);
+        assert!(platform.len() > "Linux-".len());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_platform_string_macos() {
+        let platform = get_platform_string();
+        assert!(platform.starts_with("macOS-"), "{}", platform);
+        assert!(platform.len() > "macOS-".len());
     }
 }
@@ -0,0 +1,151 @@
+//!
+//! ## audio_stream 使用的有界音频通道
+//! 读取任务把音频分片推入通道, 消费者跟不上导致通道写满时按 OverflowPolicy 处理
+use futures_util::Stream;
+use std::pin::Pin;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// audio_stream 默认可以缓冲的分片数
+pub const DEFAULT_AUDIO_BUFFER_CAPACITY: usize = 64;
+
+///
+/// 音频通道写满时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// 读取任务等待消费者, 背压一直传到socket; 保存文件等不能丢音频的场景使用
+    #[default]
+    Block,
+    /// 丢弃最早的分片, 读取任务不等待; 实时播放宁可跳过也不能越积越多时使用
+    DropOldest,
+    /// 立即结束该订阅(流提前结束)并记录错误, 读取任务不等待
+    Error,
+}
+
+pub(crate) type AudioReceiver<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+#[derive(Clone)]
+pub(crate) enum AudioSender<T> {
+    Block(mpsc::Sender<T>),
+    DropOldest(broadcast::Sender<T>),
+    Error(mpsc::Sender<T>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SendOutcome {
+    Delivered,
+    // 消费者已经丢弃了流
+    Closed,
+    // OverflowPolicy::Error 时通道已满
+    Overflow,
+}
+
+impl<T: Clone + Send + 'static> AudioSender<T> {
+    pub(crate) async fn send(&self, item: T) -> SendOutcome {
+        match self {
+            AudioSender::Block(tx) => match tx.send(item).await {
+                Ok(()) => SendOutcome::Delivered,
+                Err(_) => SendOutcome::Closed,
+            },
+            // broadcast 只在没有接收者时返回错误, 写满时覆盖最早的分片
+            AudioSender::DropOldest(tx) => match tx.send(item) {
+                Ok(_) => SendOutcome::Delivered,
+                Err(_) => SendOutcome::Closed,
+            },
+            AudioSender::Error(tx) => match tx.try_send(item) {
+                Ok(()) => SendOutcome::Delivered,
+                Err(mpsc::error::TrySendError::Full(_)) => SendOutcome::Overflow,
+                Err(mpsc::error::TrySendError::Closed(_)) => SendOutcome::Closed,
+            },
+        }
+    }
+}
+
+/// 按策略创建有界通道, capacity 至少为1
+pub(crate) fn audio_channel<T: Clone + Send + 'static>(
+    policy: OverflowPolicy,
+    capacity: usize,
+) -> (AudioSender<T>, AudioReceiver<T>) {
+    let capacity = capacity.max(1);
+    match policy {
+        OverflowPolicy::Block => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (AudioSender::Block(tx), Box::pin(ReceiverStream::new(rx)))
+        }
+        OverflowPolicy::Error => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (AudioSender::Error(tx), Box::pin(ReceiverStream::new(rx)))
+        }
+        OverflowPolicy::DropOldest => {
+            let (tx, rx) = broadcast::channel(capacity);
+            let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+                loop {
+                    match rx.recv().await {
+                        Ok(item) => return Some((item, rx)),
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::warn!("音频消费过慢, 丢弃最早的 {} 个分片", skipped);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            });
+            (AudioSender::DropOldest(tx), Box::pin(stream))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    async fn fill(policy: OverflowPolicy) -> (Vec<SendOutcome>, Vec<u8>) {
+        let (tx, rx) = audio_channel::<u8>(policy, 2);
+        let mut outcomes = vec![];
+        for item in 1..=4 {
+            outcomes.push(tx.send(item).await);
+        }
+        drop(tx);
+        (outcomes, rx.collect().await)
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_latest() {
+        let (outcomes, received) = fill(OverflowPolicy::DropOldest).await;
+        assert!(outcomes.iter().all(|o| *o == SendOutcome::Delivered));
+        assert_eq!(received, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_error_reports_overflow() {
+        let (outcomes, received) = fill(OverflowPolicy::Error).await;
+        assert_eq!(
+            outcomes,
+            vec![
+                SendOutcome::Delivered,
+                SendOutcome::Delivered,
+                SendOutcome::Overflow,
+                SendOutcome::Overflow
+            ]
+        );
+        assert_eq!(received, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_slow_consumer() {
+        let (tx, mut rx) = audio_channel::<u8>(OverflowPolicy::Block, 1);
+        let producer = tokio::spawn(async move {
+            for item in 1..=3 {
+                assert_eq!(tx.send(item).await, SendOutcome::Delivered);
+            }
+        });
+        let mut received = vec![];
+        while let Some(item) = rx.next().await {
+            // 慢消费者
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            received.push(item);
+        }
+        producer.await.unwrap();
+        assert_eq!(received, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,196 @@
+//!
+//! ## 审计记录
+//! 会话生命周期中的每个协议动作(建连、发送会话配置、追加文本、finish、连接结束)生成一条结构化记录,
+//! 交给 AuditSink 写入审计日志; 与调试日志、on_send 相互独立, 默认不记录原始文本
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// 审计记录对应的协议动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditAction {
+    /// 握手成功, url 为不含API Key的完整地址
+    Connected { model: String, url: String },
+    /// 发送了 session.update
+    SessionConfigured {
+        voice: String,
+        response_format: String,
+        mode: String,
+    },
+    /// 发送了 input_text_buffer.append, chars 为字符数; 只有开启 include_text 时才记录 text
+    TextAppended {
+        chars: usize,
+        bytes: usize,
+        text: Option<String>,
+    },
+    /// 发送了 input_text_buffer.commit
+    Committed,
+    /// 发送了 session.finish
+    FinishSent,
+    /// 发送了 response.cancel
+    Cancelled,
+    /// 连接结束, 每个连接只记录一次
+    Closed {
+        session_finished: bool,
+        close_code: Option<u16>,
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub timestamp: SystemTime,
+    /// 与日志和读取任务名中的 connection_id 相同, 用于串联同一连接的记录
+    pub trace_id: String,
+    pub action: AuditAction,
+}
+
+///
+/// 审计记录的接收端, 由 QwenTtsRealtimeBuilder::audit_sink 设置;
+/// 在发送方或读取任务中同步调用, 耗时的写入应自行转到其他任务
+pub trait AuditSink {
+    fn record(&self, event: AuditEvent);
+}
+
+/// 绑定了 trace_id 的审计入口, 保存在会话的共享状态中
+#[derive(Clone)]
+pub(crate) struct Auditor {
+    sink: Arc<dyn AuditSink + Send + Sync>,
+    trace_id: String,
+    include_text: bool,
+}
+
+impl Auditor {
+    pub fn new(sink: Arc<dyn AuditSink + Send + Sync>, trace_id: &str, include_text: bool) -> Self {
+        Self {
+            sink,
+            trace_id: trace_id.to_string(),
+            include_text,
+        }
+    }
+
+    pub fn record(&self, action: AuditAction) {
+        self.sink.record(AuditEvent {
+            timestamp: SystemTime::now(),
+            trace_id: self.trace_id.clone(),
+            action,
+        });
+    }
+
+    /// 按发往服务端的事件记录, 不属于会话生命周期的事件不记录
+    pub fn record_sent(&self, event: &Value) {
+        let text_of = |key: &str| event["session"][key].as_str().unwrap_or_default().to_string();
+        let action = match event["type"].as_str().unwrap_or_default() {
+            "session.update" => AuditAction::SessionConfigured {
+                voice: text_of("voice"),
+                response_format: text_of("response_format"),
+                mode: text_of("mode"),
+            },
+            "input_text_buffer.append" => {
+                let text = event["text"].as_str().unwrap_or_default();
+                AuditAction::TextAppended {
+                    chars: text.chars().count(),
+                    bytes: text.len(),
+                    text: self.include_text.then(|| text.to_string()),
+                }
+            }
+            "input_text_buffer.commit" => AuditAction::Committed,
+            "session.finish" => AuditAction::FinishSent,
+            "response.cancel" => AuditAction::Cancelled,
+            _ => return,
+        };
+        self.record(action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::mock_server::MockServerBuilder;
+    use crate::dashscope::qwen_tts_realtime::{AudioFormat, FinishOutcome, QwenTtsRealtimeBuilder};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<AuditEvent>>,
+    }
+
+    impl AuditSink for RecordingSink {
+        fn record(&self, event: AuditEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    async fn audited_session(include_text: bool) -> (Vec<AuditEvent>, String) {
+        let server = MockServerBuilder::new().spawn().await;
+        let sink = Arc::new(RecordingSink::default());
+        let mut tts = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .audit_sink(sink.clone())
+            .audit_include_text(include_text)
+            .connect()
+            .await
+            .unwrap();
+        tts.update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        tts.append_text("你好").await.unwrap();
+        tts.append_text("world").await.unwrap();
+        tts.finish().await.unwrap();
+        let outcome = tts.wait_until_finished(Duration::from_secs(5)).await;
+        assert_eq!(outcome, FinishOutcome::Finished);
+        let events = sink.events.lock().unwrap().clone();
+        (events, tts.connection_id().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_audit_sequence() {
+        let (events, connection_id) = audited_session(false).await;
+        assert!(events.iter().all(|event| event.trace_id == connection_id));
+        assert!(events.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        let actions = events.into_iter().map(|event| event.action).collect::<Vec<_>>();
+        assert!(matches!(
+            &actions[0],
+            AuditAction::Connected { model, url }
+                if model == "qwen3-tts-flash-realtime" && url.contains("model=")
+        ));
+        assert_eq!(
+            actions[1..],
+            [
+                AuditAction::SessionConfigured {
+                    voice: "Cherry".to_string(),
+                    response_format: "pcm".to_string(),
+                    mode: "server_commit".to_string(),
+                },
+                AuditAction::TextAppended {
+                    chars: 2,
+                    bytes: 6,
+                    text: None,
+                },
+                AuditAction::TextAppended {
+                    chars: 5,
+                    bytes: 5,
+                    text: None,
+                },
+                AuditAction::FinishSent,
+                AuditAction::Closed {
+                    session_finished: true,
+                    close_code: Some(1000),
+                    error: None,
+                },
+            ]
+        );
+
+        // 明确开启后才记录原始文本
+        let (events, _) = audited_session(true).await;
+        let texts = events
+            .into_iter()
+            .filter_map(|event| match event.action {
+                AuditAction::TextAppended { text, .. } => text,
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["你好", "world"]);
+    }
+}
@@ -0,0 +1,64 @@
+//!
+//! ## 实时字幕
+//! 把服务端回显的文本拼成与音频同步的完整字幕; 服务端可能逐段下发增量(delta),
+//! 也可能在响应结束时下发本次响应的完整文本(text), 两种方式可以混用:
+//! 完整文本会覆盖本次响应已经拼接的增量, 避免重复
+
+/// 带增量文本的事件
+pub(crate) const CAPTION_DELTA_EVENTS: [&str; 2] =
+    ["response.text.delta", "response.audio_transcript.delta"];
+/// 带本次响应完整文本的事件
+pub(crate) const CAPTION_DONE_EVENTS: [&str; 2] =
+    ["response.text.done", "response.audio_transcript.done"];
+
+#[derive(Debug, Default)]
+pub(crate) struct CaptionAccumulator {
+    text: String,
+    // 当前响应的文本在 text 中的起始位置
+    response_start: usize,
+}
+
+impl CaptionAccumulator {
+    pub fn push_delta(&mut self, delta: &str) {
+        self.text.push_str(delta);
+    }
+
+    /// 用完整文本替换当前响应已拼接的部分
+    pub fn replace_response(&mut self, full_text: &str) {
+        self.text.truncate(self.response_start);
+        self.text.push_str(full_text);
+    }
+
+    /// response.done 之后的文本属于下一个响应
+    pub fn end_response(&mut self) {
+        self.response_start = self.text.len();
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caption_accumulates_deltas() {
+        let mut caption = CaptionAccumulator::default();
+        for delta in ["你好", "，", "世界。"] {
+            caption.push_delta(delta);
+        }
+        assert_eq!(caption.text(), "你好，世界。");
+        // 完整文本与增量一致时不会重复
+        caption.replace_response("你好，世界。");
+        caption.end_response();
+        assert_eq!(caption.text(), "你好，世界。");
+
+        // 第二个响应只下发完整文本, 且纠正了之前的增量
+        caption.push_delta("再见");
+        caption.replace_response("再见！");
+        caption.end_response();
+        assert_eq!(caption.text(), "你好，世界。再见！");
+    }
+}
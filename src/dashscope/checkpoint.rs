@@ -0,0 +1,79 @@
+//!
+//! ## 批量合成的断点
+//! 记录已经合成完成的文本段和对应的输出文件长度, 进程重启后从断点继续,
+//! 而不是从头开始; 断点文件为JSON, 写入时先写临时文件再rename, 避免写一半时崩溃损坏断点
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// 已合成完成的文本段, 按合成顺序
+    pub completed: Vec<String>,
+    /// 已完成的文本段对应的音频在输出文件中的结束位置, 之后的字节属于未完成的段
+    pub output_offset: u64,
+}
+
+impl Checkpoint {
+    ///
+    /// 返回texts中尚未完成的部分; 断点记录的文本必须是texts的前缀, 否则说明输入已经变化
+    pub fn remaining<'a>(&self, texts: &'a [&'a str]) -> io::Result<&'a [&'a str]> {
+        let is_prefix = self.completed.len() <= texts.len()
+            && self
+                .completed
+                .iter()
+                .zip(texts.iter())
+                .all(|(done, text)| done == text);
+        if !is_prefix {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "断点中已完成的文本与本次输入不一致",
+            ));
+        }
+        Ok(&texts[self.completed.len()..])
+    }
+
+    /// 记录一段文本合成完成, 以及它写入的音频字节数
+    pub fn complete(&mut self, text: &str, bytes: u64) {
+        self.completed.push(text.to_string());
+        self.output_offset += bytes;
+    }
+}
+
+/// 保存断点, 先写入同目录下的临时文件再rename覆盖
+pub fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec(checkpoint)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// 读取断点, 文件不存在时返回空断点(从头开始)
+pub fn resume_from_checkpoint(path: &Path) -> io::Result<Checkpoint> {
+    match fs::read(path) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_roundtrip_and_remaining() {
+        let path = std::env::temp_dir().join(format!("{}_checkpoint.json", uuid::Uuid::new_v4()));
+        assert_eq!(resume_from_checkpoint(&path).unwrap(), Checkpoint::default());
+
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.complete("第一段", 6);
+        save_checkpoint(&path, &checkpoint).unwrap();
+        let restored = resume_from_checkpoint(&path).unwrap();
+        assert_eq!(restored, checkpoint);
+        assert_eq!(restored.remaining(&["第一段", "第二段"]).unwrap(), &["第二段"]);
+        assert!(restored.remaining(&["改过的第一段", "第二段"]).is_err());
+        let _ = fs::remove_file(&path);
+    }
+}
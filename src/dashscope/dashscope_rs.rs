@@ -1,16 +1,19 @@
 use crate::common::errors::GenerationError;
-use crate::common::{get_platform_info, get_processor_info};
+use crate::common::{get_platform_string, get_processor_info};
 use crate::dashscope::parameters::{
     DashScopeRequestBodyBuilder, HistoryMessage, Message, Parameters,
 };
-use futures_util::{pin_mut, StreamExt};
+use futures_util::{pin_mut, Stream, StreamExt};
 use log::{debug, info};
 use reqwest::header::HeaderMap;
 use reqwest::{Client, Response};
 use rustc_version::version;
 use serde::de::Error;
 use serde_json::{Value, json};
-use crate::dashscope::models::response_data::DashScopeResponseData;
+use crate::dashscope::models::response_data::{
+    GenerationChunk, ModelInfo, ModelListResponse,
+};
+use std::collections::VecDeque;
 
 pub struct Generation;
 
@@ -35,13 +38,14 @@ impl Generation {
             )));
         }
         let client = Client::new();
-        let builder = client.post(Self::base_url());
+        let builder = client.post(Self::request_url(parameter.api_version.as_deref()));
         let header = Self::build_request_header(
             api_key,
             parameter.stream.unwrap_or(false),
             parameter.incremental_output.unwrap_or(false),
             workspace,
             plugins,
+            parameter.minimal_user_agent,
         )
         .await?;
 
@@ -56,6 +60,89 @@ impl Generation {
         Ok(response)
     }
 
+    ///
+    /// 以SSE流式调用并逐个返回解析后的增量, 参数与 call 相同, parameter.stream 会被置为true;
+    /// 请求失败或非2xx响应时流中只有一个错误. 跨分片的半行由 SseDecoder 缓冲, 收到 data: [DONE] 时结束
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_stream(
+        model: &str,
+        prompt: Option<&str>,
+        history: Option<Vec<HistoryMessage>>,
+        api_key: &str,
+        messages: Option<Vec<Message>>,
+        plugins: Option<&str>,
+        workspace: Option<&str>,
+        mut parameter: Parameters,
+    ) -> impl Stream<Item = Result<GenerationChunk, GenerationError>> {
+        parameter.stream = Some(true);
+        let response = Self::call(
+            model, prompt, history, api_key, messages, plugins, workspace, parameter,
+        );
+        futures_util::stream::once(async move {
+            let response = response.await?;
+            if !response.status().is_success() {
+                let url = response.url().to_string();
+                return Err(GenerationError::DashScopeResponseError(format!(
+                    "请求失败, url: {}, reason: {}",
+                    url,
+                    response.text().await?
+                )));
+            }
+            Ok(response)
+        })
+        .flat_map(|response| match response {
+            Ok(response) => Self::response_stream(response).left_stream(),
+            Err(e) => futures_util::stream::iter([Err(e)]).right_stream(),
+        })
+    }
+
+    /// 把流式响应的SSE解析为 GenerationChunk, 不检查状态码
+    pub fn response_stream(
+        response: Response,
+    ) -> impl Stream<Item = Result<GenerationChunk, GenerationError>> + Send + 'static {
+        let state = (
+            Box::pin(response.bytes_stream()),
+            SseDecoder::default(),
+            VecDeque::<String>::new(),
+            false,
+        );
+        futures_util::stream::unfold(
+            state,
+            |(mut bytes_stream, mut decoder, mut pending, mut eof)| async move {
+                loop {
+                    if let Some(data) = pending.pop_front() {
+                        if data.trim() == "[DONE]" {
+                            return None;
+                        }
+                        let chunk = GenerationChunk::from_data(&data);
+                        return Some((chunk, (bytes_stream, decoder, pending, eof)));
+                    }
+                    if eof {
+                        return None;
+                    }
+                    match bytes_stream.next().await {
+                        Some(Ok(bytes)) => pending.extend(decoder.push(&bytes)),
+                        Some(Err(e)) => {
+                            eof = true;
+                            return Some((Err(e.into()), (bytes_stream, decoder, pending, eof)));
+                        }
+                        None => {
+                            eof = true;
+                            pending.extend(decoder.finish());
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    fn request_url(api_version: Option<&str>) -> String {
+        match api_version {
+            Some(api_version) => format!("{}?api_version={}", Self::base_url(), api_version),
+            None => Self::base_url().to_string(),
+        }
+    }
+
     pub async fn print_response(res: Response, stream: bool) -> Result<(), GenerationError> {
         if !stream {
             println!("{:#?}", res);
@@ -70,46 +157,31 @@ impl Generation {
                     res.text().await?
                 )));
             }
-            let mut stream = res.bytes_stream();
+            let mut stream = Box::pin(Self::response_stream(res));
             let mut is_reasoning_answer = true;
             let separator = "=".repeat(20);
             print!("\n{}思考内容{}\n", separator, separator);
             let mut usage = None;
-            while let Some(item) = stream.next().await {
-                    let chunk = item?;
-                    debug!("chunk data: {:#?}", chunk);
-                    let text = String::from_utf8_lossy(&chunk);
-
-                    // 逐行過濾包含 "data:" 的內容
-                    for line in text.lines() {
-                        if line.starts_with("data:") {
-                            let json_str = line.strip_prefix("data:").unwrap().trim(); // 去掉 "data:" 並修剪空格
-                            // 解析為 JSON Map
-                            let response_data =
-                                serde_json::from_str::<DashScopeResponseData>(json_str)?;
-                            let choice = &response_data.output.choices[0];
-                            let message = &choice.message;
-                            // 提取你需要的字段，例如推理內容
-                            if is_reasoning_answer && !&message.content.is_empty() {
-                                print!("{}", message.content); // 實現逐字輸出效果
-                            }
-
-                            if let Some(content) = &message.reasoning_content
-                                && !content.is_empty()
-                            {
-                                if is_reasoning_answer {
-                                    is_reasoning_answer = false;
-                                    print!("\n{}回复内容{}\n", separator, separator);
-                                }
-                                print!("{}", content);
-                            }
-                            if choice.finish_reason!="null" {
-                                debug!("获取 token usage 信息");
-                                usage = Some(response_data.usage);
-                            }
-                        }
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                debug!("chunk data: {:#?}", chunk);
+                if is_reasoning_answer && !chunk.content.is_empty() {
+                    print!("{}", chunk.content); // 實現逐字輸出效果
+                }
+                if let Some(content) = &chunk.reasoning_content
+                    && !content.is_empty()
+                {
+                    if is_reasoning_answer {
+                        is_reasoning_answer = false;
+                        print!("\n{}回复内容{}\n", separator, separator);
                     }
+                    print!("{}", content);
                 }
+                if chunk.finish_reason.is_some() {
+                    debug!("获取 token usage 信息");
+                    usage = chunk.usage;
+                }
+            }
             if let Some(usage) = usage {
                 print!("\n{}Token消耗{}\n", separator, separator);
                 print!("{:?}", usage)
@@ -124,6 +196,7 @@ impl Generation {
         incremental_output: bool,
         workspace: Option<&str>,
         plugins: Option<&str>,
+        minimal_user_agent: bool,
     ) -> Result<HeaderMap, GenerationError> {
         let mut headers = HeaderMap::new();
         if stream {
@@ -143,31 +216,37 @@ impl Generation {
 
         headers.insert("Authorization", format!("Bearer {}", api_key).parse()?);
         headers.insert("Content-Type", "application/json".parse()?);
-        let rust_version = format!("{}", version().unwrap());
-
-        // 1. 獲取 Platform (Windows 11 10.0.22621)
-        let (os_name, full_ver) = get_platform_info();
-        let platform = format!("{}-{}-SP0", os_name, full_ver);
-        // 2. 獲取 Processor (AMD64 Family... Stepping...)
-        let processor_info = get_processor_info();
-
         headers.insert(
             "user-agent",
-            format!(
-                "dashscope/{};rust/{};platform/{};processor/{};incremental_to_full/{}",
-                "0.1.0",
-                rust_version,
-                platform,
-                processor_info,
-                if stream && !incremental_output { 1 } else { 0 }
-            )
-            .parse()?,
+            Self::build_user_agent(stream, incremental_output, minimal_user_agent).parse()?,
         );
         #[cfg(test)]
         println!("{:#?}", headers);
         Ok(headers)
     }
 
+    /// minimal_user_agent 为true时省略 platform/processor 段, 避免上报系统版本和CPU信息
+    fn build_user_agent(stream: bool, incremental_output: bool, minimal_user_agent: bool) -> String {
+        let rust_version = format!("{}", version().unwrap());
+        let incremental_to_full = if stream && !incremental_output { 1 } else { 0 };
+        if minimal_user_agent {
+            return format!(
+                "dashscope/{};rust/{};incremental_to_full/{}",
+                "0.1.0", rust_version, incremental_to_full
+            );
+        }
+
+        // 1. 獲取 Platform (Windows-11-10.0.22621-SP0, Linux-6.8.0, macOS-14.5)
+        let platform = get_platform_string();
+        // 2. 獲取 Processor (AMD64 Family... Stepping...)
+        let processor_info = get_processor_info();
+
+        format!(
+            "dashscope/{};rust/{};platform/{};processor/{};incremental_to_full/{}",
+            "0.1.0", rust_version, platform, processor_info, incremental_to_full
+        )
+    }
+
     async fn build_request_json(
         model: &str,
         messages: Value,
@@ -194,10 +273,117 @@ impl Generation {
     }
 }
 
+///
+/// 增量解析 text/event-stream: 按字节缓冲跨分片的半行(包括被切开的多字节UTF-8字符),
+/// 同一事件的多条 data 以换行拼接, 遇到空行时输出; 注释行和 id/event 等其他字段忽略
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    data: Option<String>,
+}
+
+impl SseDecoder {
+    /// 追加一个分片, 返回其中完整结束的事件的 data
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = vec![];
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<_>>();
+            events.extend(self.line(&line[..pos]));
+        }
+        events
+    }
+
+    /// 流结束时取出最后一个没有以空行结尾的事件
+    pub fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.buffer);
+        if !line.is_empty() {
+            let _ = self.line(&line);
+        }
+        self.data.take()
+    }
+
+    fn line(&mut self, line: &[u8]) -> Option<String> {
+        let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+        if line.is_empty() {
+            return self.data.take();
+        }
+        let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+        if field == "data" {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            }
+        }
+        None
+    }
+}
+
+pub struct Models;
+
+impl Models {
+    const PAGE_SIZE: u64 = 100;
+
+    const fn base_url() -> &'static str {
+        "https://dashscope.aliyuncs.com/api/v1/models"
+    }
+
+    /// 列出当前账号可用的模型, 会自动翻页直到取完
+    pub async fn list_models(
+        api_key: &str,
+        workspace: Option<&str>,
+    ) -> Result<Vec<ModelInfo>, GenerationError> {
+        Self::list_models_from(Self::base_url(), api_key, workspace).await
+    }
+
+    pub(crate) async fn list_models_from(
+        url: &str,
+        api_key: &str,
+        workspace: Option<&str>,
+    ) -> Result<Vec<ModelInfo>, GenerationError> {
+        let client = Client::new();
+        let header =
+            Generation::build_request_header(api_key, false, false, workspace, None, false)
+                .await?;
+        let mut models = vec![];
+        let mut page_no = 1;
+        loop {
+            let page_url = format!("{}?page_no={}&page_size={}", url, page_no, Self::PAGE_SIZE);
+            let response = client.get(&page_url).headers(header.clone()).send().await?;
+            if !response.status().is_success() {
+                return Err(GenerationError::DashScopeResponseError(format!(
+                    "请求失败, url: {}, reason: {}",
+                    page_url,
+                    response.text().await?
+                )));
+            }
+            let page = serde_json::from_str::<ModelListResponse>(&response.text().await?)?;
+            let count = page.output.models.len() as u64;
+            debug!("模型列表第{}页, 共{}个", page_no, count);
+            models.extend(page.output.models);
+            // 没有total时以不满一页作为结束
+            let has_more = match page.output.total {
+                Some(total) => (models.len() as u64) < total,
+                None => count >= Self::PAGE_SIZE,
+            };
+            if count == 0 || !has_more {
+                break;
+            }
+            page_no += 1;
+        }
+        Ok(models)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::common::logging::init_logger;
+    use crate::dashscope::mock_server::spawn_http_server;
     use serde_json::{from_value, json};
 
     #[tokio::test]
@@ -225,4 +411,121 @@ mod tests {
         Generation::print_response(res, stream).await?;
         Ok(())
     }
+
+    #[test]
+    fn test_sse_decoder_across_chunk_boundaries() {
+        let body = "id:1\nevent:result\n:HTTP_STATUS/200\ndata:{\"output\":{\"choices\":[{\"finish_reason\":\"null\",\"message\":{\"content\":\"你好\",\"reasoning_content\":\"\",\"role\":\"assistant\"}}]}}\n\n\
+            id:2\r\ndata: {\"output\":{\"choices\":[{\"finish_reason\":\"stop\",\"message\":{\"content\":\"世界\",\"role\":\"assistant\"}}]},\"usage\":{\"total_tokens\":3}}\r\n\r\n\
+            data:[DONE]";
+        let expected = vec![
+            GenerationChunk {
+                content: "你好".to_string(),
+                reasoning_content: Some(String::new()),
+                ..GenerationChunk::default()
+            },
+            GenerationChunk {
+                content: "世界".to_string(),
+                finish_reason: Some("stop".to_string()),
+                usage: Some(json!({"total_tokens": 3})),
+                ..GenerationChunk::default()
+            },
+        ];
+        // 任意位置切开(包括汉字的UTF-8字节中间)结果都相同
+        for chunk_size in [1, 2, 3, 7, 64, body.len()] {
+            let mut decoder = SseDecoder::default();
+            let mut events = vec![];
+            for bytes in body.as_bytes().chunks(chunk_size) {
+                events.extend(decoder.push(bytes));
+            }
+            events.extend(decoder.finish());
+            assert_eq!(events.last().map(String::as_str), Some("[DONE]"));
+            let chunks = events[..events.len() - 1]
+                .iter()
+                .map(|data| GenerationChunk::from_data(data).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(chunks, expected, "chunk_size {}", chunk_size);
+        }
+
+        // 多行 data 以换行拼接; 服务端下发的错误事件
+        let mut decoder = SseDecoder::default();
+        assert_eq!(decoder.push(b"data:a\ndata: b\n\n"), vec!["a\nb"]);
+        assert!(matches!(
+            GenerationChunk::from_data(r#"{"code":"InvalidParameter","message":"bad"}"#),
+            Err(GenerationError::DashScopeResponseError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_response_stream_yields_chunks() {
+        let base_url = spawn_http_server(|_| {
+            let body = [
+                r#"data:{"output":{"text":"你","finish_reason":"null"}}"#,
+                "",
+                r#"data:{"output":{"text":"好","finish_reason":"stop"}}"#,
+                "",
+                "data:[DONE]",
+                "",
+                r#"data:{"output":{"text":"ignored"}}"#,
+                "",
+            ]
+            .join("\n");
+            (200, body)
+        })
+        .await;
+        let response = reqwest::get(&base_url).await.unwrap();
+        let chunks = Generation::response_stream(response)
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        let contents = chunks.iter().map(|c| c.content.as_str()).collect::<Vec<_>>();
+        assert_eq!(contents, vec!["你", "好"]);
+        assert_eq!(chunks[1].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[test]
+    fn test_minimal_user_agent() {
+        let full = Generation::build_user_agent(true, false, false);
+        assert!(full.contains(&format!(";platform/{};", get_platform_string())));
+        assert!(full.contains(";processor/"));
+        let minimal = Generation::build_user_agent(true, false, true);
+        assert!(minimal.starts_with("dashscope/0.1.0;rust/"));
+        assert!(!minimal.contains("platform/"));
+        assert!(!minimal.contains("processor/"));
+        assert!(minimal.ends_with("incremental_to_full/1"));
+    }
+
+    #[test]
+    fn test_request_url_api_version() {
+        assert_eq!(Generation::request_url(None), Generation::base_url());
+        assert_eq!(
+            Generation::request_url(Some("2025-09-01")),
+            format!("{}?api_version=2025-09-01", Generation::base_url())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_models_paginated() {
+        let base_url = spawn_http_server(|target| {
+            let body = if target.contains("page_no=1") {
+                json!({"request_id": "1", "output": {"total": 3, "models": [
+                    {"model": "qwen3-tts-flash-realtime", "type": "tts", "capabilities": ["realtime", "streaming"]},
+                    {"model": "qwen-plus", "type": "llm"},
+                ]}})
+            } else {
+                json!({"request_id": "2", "output": {"total": 3, "models": [
+                    {"model": "cosyvoice-v2", "type": "tts", "capabilities": ["streaming"]},
+                ]}})
+            };
+            (200, body.to_string())
+        })
+        .await;
+        let models = Models::list_models_from(&format!("{}/api/v1/models", base_url), "key", None)
+            .await
+            .unwrap();
+        let ids = models.iter().map(|m| m.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["qwen3-tts-flash-realtime", "qwen-plus", "cosyvoice-v2"]);
+        assert_eq!(models[0].r#type, "tts");
+        assert_eq!(models[0].capabilities, vec!["realtime", "streaming"]);
+        assert!(models[1].capabilities.is_empty());
+    }
 }
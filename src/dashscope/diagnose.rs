@@ -0,0 +1,261 @@
+//!
+//! ## 连接诊断
+//! diagnose 按 DNS 解析 -> TCP 连接 -> TLS 握手 -> WebSocket 升级 -> 鉴权 的顺序逐步建连,
+//! 在第一个失败的环节停止并记录错误, 用于区分网络、证书和API Key问题;
+//! 与 connect 使用相同的配置解析规则(参数、环境变量、默认值)
+use crate::dashscope::qwen_tts_realtime::QwenTtsRealtimeBuilder;
+use futures_util::StreamExt;
+use serde_json::Value;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::Message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticStage {
+    /// 解析配置、构造握手请求, 如缺少API Key、url不合法
+    Config,
+    Dns,
+    Tcp,
+    /// 只有 wss:// 才有这一步
+    Tls,
+    WebSocketUpgrade,
+    /// 握手被拒绝(401/403), 或握手成功后服务端没有下发 session.created
+    Auth,
+}
+
+impl fmt::Display for DiagnosticStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DiagnosticStage::Config => "配置",
+            DiagnosticStage::Dns => "DNS 解析",
+            DiagnosticStage::Tcp => "TCP 连接",
+            DiagnosticStage::Tls => "TLS 握手",
+            DiagnosticStage::WebSocketUpgrade => "WebSocket 升级",
+            DiagnosticStage::Auth => "鉴权",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticReport {
+    pub url: String,
+    /// DNS 解析得到的地址
+    pub resolved_addrs: Vec<SocketAddr>,
+    /// 按顺序通过的环节
+    pub passed: Vec<DiagnosticStage>,
+    /// 第一个失败的环节和错误信息, 全部通过时为None
+    pub failure: Option<(DiagnosticStage, String)>,
+    pub elapsed: Duration,
+}
+
+impl DiagnosticReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    pub fn failed_stage(&self) -> Option<DiagnosticStage> {
+        self.failure.as_ref().map(|(stage, _)| *stage)
+    }
+}
+
+/// 可直接贴到工单里的多行文本
+impl fmt::Display for DiagnosticReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "url: {}", self.url)?;
+        if !self.resolved_addrs.is_empty() {
+            writeln!(f, "地址: {:?}", self.resolved_addrs)?;
+        }
+        for stage in &self.passed {
+            writeln!(f, "[通过] {}", stage)?;
+        }
+        if let Some((stage, error)) = &self.failure {
+            writeln!(f, "[失败] {}: {}", stage, error)?;
+        }
+        write!(f, "耗时: {:?}", self.elapsed)
+    }
+}
+
+type StageError = (DiagnosticStage, String);
+
+///
+/// 按 builder 的配置逐步建连并返回诊断报告, 每个环节最多等待 timeout;
+/// 诊断本身不会返回错误, 失败体现在报告的 failure 中
+pub async fn diagnose(builder: &QwenTtsRealtimeBuilder, timeout: Duration) -> DiagnosticReport {
+    let started = Instant::now();
+    let mut report = DiagnosticReport {
+        url: String::new(),
+        resolved_addrs: vec![],
+        passed: vec![],
+        failure: None,
+        elapsed: Duration::ZERO,
+    };
+    if let Err(failure) = run_stages(builder, timeout, &mut report).await {
+        report.failure = Some(failure);
+    }
+    report.elapsed = started.elapsed();
+    log::info!("连接诊断结果:\n{}", report);
+    report
+}
+
+async fn run_stages(
+    builder: &QwenTtsRealtimeBuilder,
+    timeout: Duration,
+    report: &mut DiagnosticReport,
+) -> Result<(), StageError> {
+    let fail = |stage: DiagnosticStage| move |e: String| (stage, e);
+    let request = builder
+        .handshake_request()
+        .map_err(|e| (DiagnosticStage::Config, e.to_string()))?;
+    let uri = request.uri().clone();
+    report.url = uri.to_string();
+    let is_tls = uri.scheme_str() == Some("wss");
+    let host = uri
+        .host()
+        .map(|host| host.trim_matches(['[', ']']).to_string())
+        .ok_or((DiagnosticStage::Config, "url 缺少主机名".to_string()))?;
+    let port = uri.port_u16().unwrap_or(if is_tls { 443 } else { 80 });
+    report.passed.push(DiagnosticStage::Config);
+
+    let addrs = with_timeout(timeout, tokio::net::lookup_host((host.as_str(), port)))
+        .await
+        .map_err(fail(DiagnosticStage::Dns))?;
+    report.resolved_addrs = addrs.collect();
+    if report.resolved_addrs.is_empty() {
+        return Err((DiagnosticStage::Dns, "没有解析到地址".to_string()));
+    }
+    report.passed.push(DiagnosticStage::Dns);
+
+    let stream = with_timeout(timeout, TcpStream::connect(&report.resolved_addrs[..]))
+        .await
+        .map_err(fail(DiagnosticStage::Tcp))?;
+    report.passed.push(DiagnosticStage::Tcp);
+
+    // TLS 与 WebSocket 升级在同一次调用中完成, 按错误类型区分失败的环节
+    let upgrade = tokio::time::timeout(
+        timeout,
+        tokio_tungstenite::client_async_tls(request, stream),
+    );
+    let mut ws = match upgrade.await {
+        Ok(Ok((ws, _))) => ws,
+        Ok(Err(tungstenite::Error::Tls(e))) => return Err((DiagnosticStage::Tls, e.to_string())),
+        Ok(Err(e)) => {
+            if is_tls {
+                report.passed.push(DiagnosticStage::Tls);
+            }
+            if let tungstenite::Error::Http(response) = &e
+                && matches!(response.status().as_u16(), 401 | 403)
+            {
+                report.passed.push(DiagnosticStage::WebSocketUpgrade);
+                return Err((DiagnosticStage::Auth, e.to_string()));
+            }
+            return Err((DiagnosticStage::WebSocketUpgrade, e.to_string()));
+        }
+        Err(_) if is_tls => return Err((DiagnosticStage::Tls, "超时".to_string())),
+        Err(_) => return Err((DiagnosticStage::WebSocketUpgrade, "超时".to_string())),
+    };
+    if is_tls {
+        report.passed.push(DiagnosticStage::Tls);
+    }
+    report.passed.push(DiagnosticStage::WebSocketUpgrade);
+
+    // 鉴权通过后服务端下发的第一个事件是 session.created, 否则是 error 事件或直接关闭连接
+    let first = tokio::time::timeout(timeout, ws.next()).await;
+    let _ = ws.close(None).await;
+    let error = match first {
+        Ok(Some(Ok(Message::Text(text)))) => {
+            let event = serde_json::from_str::<Value>(&text).unwrap_or_default();
+            if event["type"] == "session.created" {
+                report.passed.push(DiagnosticStage::Auth);
+                return Ok(());
+            }
+            format!("首个事件不是 session.created: {}", text)
+        }
+        Ok(Some(Ok(Message::Close(frame)))) => format!("服务端关闭连接: {:?}", frame),
+        Ok(Some(Ok(other))) => format!("收到非预期的消息: {:?}", other),
+        Ok(Some(Err(e))) => e.to_string(),
+        Ok(None) => "连接已关闭".to_string(),
+        Err(_) => "等待 session.created 超时".to_string(),
+    };
+    Err((DiagnosticStage::Auth, error))
+}
+
+async fn with_timeout<T>(
+    timeout: Duration,
+    future: impl Future<Output = std::io::Result<T>>,
+) -> Result<T, String> {
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("超时".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::mock_server::{MockServerBuilder, event};
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn failed_stage(url: &str) -> Option<DiagnosticStage> {
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(url);
+        diagnose(&builder, TIMEOUT).await.failed_stage()
+    }
+
+    #[tokio::test]
+    async fn test_diagnose_identifies_failed_stage() {
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let report = diagnose(&builder, TIMEOUT).await;
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(
+            report.passed,
+            vec![
+                DiagnosticStage::Config,
+                DiagnosticStage::Dns,
+                DiagnosticStage::Tcp,
+                DiagnosticStage::WebSocketUpgrade,
+                DiagnosticStage::Auth,
+            ]
+        );
+
+        assert_eq!(
+            failed_stage("ws://nonexistent.invalid/api-ws/v1/realtime").await,
+            Some(DiagnosticStage::Dns)
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_addr = listener.local_addr().unwrap();
+        drop(listener);
+        assert_eq!(
+            failed_stage(&format!("ws://{}/api-ws/v1/realtime", closed_addr)).await,
+            Some(DiagnosticStage::Tcp)
+        );
+
+        // 明文的模拟服务端无法完成TLS握手
+        let server = MockServerBuilder::new().spawn().await;
+        assert_eq!(
+            failed_stage(&server.url.replacen("ws://", "wss://", 1)).await,
+            Some(DiagnosticStage::Tls)
+        );
+
+        let server = MockServerBuilder::new().reject(404, vec![]).spawn().await;
+        assert_eq!(
+            failed_stage(&server.url).await,
+            Some(DiagnosticStage::WebSocketUpgrade)
+        );
+
+        let server = MockServerBuilder::new().reject(401, vec![]).spawn().await;
+        assert_eq!(failed_stage(&server.url).await, Some(DiagnosticStage::Auth));
+
+        let server = MockServerBuilder::new()
+            .on_connect(vec![event("error")])
+            .spawn()
+            .await;
+        assert_eq!(failed_stage(&server.url).await, Some(DiagnosticStage::Auth));
+    }
+}
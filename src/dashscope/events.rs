@@ -0,0 +1,209 @@
+//!
+//! ## 类型化的服务端事件
+//! 把服务端下发的JSON事件解析成 QwenTtsEvent, 音频分片已经base64解码,
+//! 回调中不需要再按 type 字符串手工匹配; 不认识的事件原样放在 Unknown 中.
+//! 事件中附带的字/词时间戳单独解析为 TimestampMark
+use base64::Engine;
+use serde_json::{Value, json};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QwenTtsEvent {
+    SessionCreated { session: Value },
+    /// 解码后的音频分片
+    ResponseAudioDelta { audio: Vec<u8> },
+    ResponseDone { response: Value },
+    SessionFinished,
+    /// 其他事件, 以及 delta 无法解码的音频分片; 无法解析为JSON的消息为 Value::String
+    Unknown(Value),
+}
+
+impl QwenTtsEvent {
+    /// 无法解析为JSON的消息返回 Unknown(Value::String), 需要区分时用 try_parse
+    pub fn parse(message: &str) -> Self {
+        Self::try_parse(message)
+            .unwrap_or_else(|_| QwenTtsEvent::Unknown(Value::String(message.to_string())))
+    }
+
+    /// 与 parse 相同, 但消息不是合法JSON时返回解析错误
+    pub fn try_parse(message: &str) -> Result<Self, serde_json::Error> {
+        let v = serde_json::from_str::<Value>(message)?;
+        Ok(Self::from_value(v, message))
+    }
+
+    fn from_value(v: Value, message: &str) -> Self {
+        match v["type"].as_str() {
+            Some("session.created") => QwenTtsEvent::SessionCreated {
+                session: v["session"].clone(),
+            },
+            Some("response.audio.delta") => {
+                let audio = v["delta"]
+                    .as_str()
+                    .and_then(|delta| base64::engine::general_purpose::STANDARD.decode(delta).ok());
+                match audio {
+                    Some(audio) => QwenTtsEvent::ResponseAudioDelta { audio },
+                    None => {
+                        log::warn!("音频分片的delta无法解码: {}", message);
+                        QwenTtsEvent::Unknown(v)
+                    }
+                }
+            }
+            Some("response.done") => QwenTtsEvent::ResponseDone {
+                response: v["response"].clone(),
+            },
+            Some("session.finished") => QwenTtsEvent::SessionFinished,
+            _ => QwenTtsEvent::Unknown(v),
+        }
+    }
+
+    ///
+    /// 重新编码为JSON消息, 供 on_typed_event 的默认实现转交给 on_event;
+    /// 读取任务不经过这里, 只在直接调用 on_typed_event 时使用;
+    /// 已知事件只保留 type 和上面的字段, event_id 等其他字段不保留, Unknown 原样返回
+    pub fn to_message(&self) -> String {
+        match self {
+            QwenTtsEvent::SessionCreated { session } => {
+                json!({"type": "session.created", "session": session}).to_string()
+            }
+            QwenTtsEvent::ResponseAudioDelta { audio } => json!({
+                "type": "response.audio.delta",
+                "delta": base64::engine::general_purpose::STANDARD.encode(audio),
+            })
+            .to_string(),
+            QwenTtsEvent::ResponseDone { response } => {
+                json!({"type": "response.done", "response": response}).to_string()
+            }
+            QwenTtsEvent::SessionFinished => json!({"type": "session.finished"}).to_string(),
+            QwenTtsEvent::Unknown(Value::String(message)) => message.clone(),
+            QwenTtsEvent::Unknown(v) => v.to_string(),
+        }
+    }
+}
+
+///
+/// 字/词在音频中的起止时间(毫秒), 用于字幕与音频对齐
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampMark {
+    pub text: String,
+    pub start_ms: u32,
+    pub end_ms: u32,
+}
+
+impl TimestampMark {
+    ///
+    /// 从任意服务端事件中提取时间戳: 事件的 words 或 sentence.words 数组,
+    /// 或者事件本身带有起止时间(字边界事件); 起止时间取 begin_time/start_ms 和 end_time/end_ms,
+    /// 文本取 text 或 delta. 没有时间戳或不是JSON时返回空
+    pub fn parse_all(message: &str) -> Vec<TimestampMark> {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            return vec![];
+        };
+        let words = v["words"].as_array().or_else(|| v["sentence"]["words"].as_array());
+        match words {
+            Some(words) => words.iter().filter_map(Self::from_value).collect(),
+            None => Self::from_value(&v).into_iter().collect(),
+        }
+    }
+
+    fn from_value(v: &Value) -> Option<TimestampMark> {
+        let millis = |keys: [&str; 2]| {
+            keys.iter()
+                .find_map(|key| v[*key].as_u64())
+                .map(|ms| u32::try_from(ms).unwrap_or(u32::MAX))
+        };
+        let start_ms = millis(["begin_time", "start_ms"])?;
+        let end_ms = millis(["end_time", "end_ms"])?;
+        let text = v["text"].as_str().or_else(|| v["delta"].as_str()).unwrap_or_default();
+        Some(TimestampMark {
+            text: text.to_string(),
+            start_ms,
+            end_ms,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::mock_server::{audio_delta, event, session_created};
+
+    #[test]
+    fn test_parse_typed_events() {
+        assert!(matches!(
+            QwenTtsEvent::parse(&session_created()),
+            QwenTtsEvent::SessionCreated { session } if session["id"] == "sess_mock"
+        ));
+        assert_eq!(
+            QwenTtsEvent::parse(&audio_delta(b"pcm")),
+            QwenTtsEvent::ResponseAudioDelta {
+                audio: b"pcm".to_vec()
+            }
+        );
+        assert!(matches!(
+            QwenTtsEvent::parse(&event("response.done")),
+            QwenTtsEvent::ResponseDone { .. }
+        ));
+        assert_eq!(
+            QwenTtsEvent::parse(&event("session.finished")),
+            QwenTtsEvent::SessionFinished
+        );
+        let bad_delta = r#"{"type":"response.audio.delta","delta":"!!"}"#;
+        assert!(matches!(QwenTtsEvent::parse(bad_delta), QwenTtsEvent::Unknown(_)));
+        assert_eq!(
+            QwenTtsEvent::parse("not json"),
+            QwenTtsEvent::Unknown(Value::String("not json".to_string()))
+        );
+        assert!(QwenTtsEvent::try_parse("not json").is_err());
+        assert_eq!(
+            QwenTtsEvent::try_parse(&event("session.finished")).unwrap(),
+            QwenTtsEvent::SessionFinished
+        );
+
+        // 重新编码后再解析得到相同的事件
+        let delta = QwenTtsEvent::parse(&audio_delta(b"pcm"));
+        assert_eq!(QwenTtsEvent::parse(&delta.to_message()), delta);
+        assert_eq!(QwenTtsEvent::parse("not json").to_message(), "not json");
+    }
+
+    #[test]
+    fn test_parse_timestamp_marks() {
+        let transcript = json!({
+            "type": "response.audio_transcript.delta",
+            "delta": "你好",
+            "words": [
+                {"text": "你", "begin_time": 0, "end_time": 180},
+                {"text": "好", "begin_time": 180, "end_time": 420},
+                {"text": "缺少结束时间", "begin_time": 420},
+            ],
+        });
+        assert_eq!(
+            TimestampMark::parse_all(&transcript.to_string()),
+            vec![
+                TimestampMark {
+                    text: "你".to_string(),
+                    start_ms: 0,
+                    end_ms: 180
+                },
+                TimestampMark {
+                    text: "好".to_string(),
+                    start_ms: 180,
+                    end_ms: 420
+                },
+            ]
+        );
+        let sentence = json!({"sentence": {"words": [{"text": "hi", "start_ms": 5, "end_ms": 9}]}});
+        assert_eq!(TimestampMark::parse_all(&sentence.to_string())[0].end_ms, 9);
+        // 字边界事件本身带有起止时间
+        let boundary =
+            json!({"type": "response.word_boundary", "text": "世界", "start_ms": 420, "end_ms": 700});
+        assert_eq!(
+            TimestampMark::parse_all(&boundary.to_string()),
+            vec![TimestampMark {
+                text: "世界".to_string(),
+                start_ms: 420,
+                end_ms: 700
+            }]
+        );
+        assert!(TimestampMark::parse_all(&audio_delta(b"pcm")).is_empty());
+        assert!(TimestampMark::parse_all("not json").is_empty());
+    }
+}
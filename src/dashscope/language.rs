@@ -0,0 +1,94 @@
+//!
+//! ## 轻量的语言检测
+//! 只按文字的书写系统(汉字、假名、谚文、拉丁字母)判断, 不依赖词典,
+//! 足够给 TTS 的 language_type 提示使用; 无法判断(纯标点、数字)时返回None
+///
+/// 服务端 language_type 支持的语言, 预设之外的取值用 Other 原样发送
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Language {
+    Chinese,
+    English,
+    Japanese,
+    Korean,
+    Other(String),
+}
+
+impl Language {
+    /// session.update 中 language_type 的取值
+    pub fn as_str(&self) -> &str {
+        match self {
+            Language::Chinese => "Chinese",
+            Language::English => "English",
+            Language::Japanese => "Japanese",
+            Language::Korean => "Korean",
+            Language::Other(language) => language,
+        }
+    }
+}
+
+enum Script {
+    Han,
+    Kana,
+    Hangul,
+    Latin,
+    Other,
+}
+
+fn script(c: char) -> Script {
+    match c as u32 {
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF | 0xF900..=0xFAFF | 0x20000..=0x2A6DF => Script::Han,
+        0x3040..=0x30FF | 0x31F0..=0x31FF => Script::Kana,
+        0xAC00..=0xD7AF | 0x1100..=0x11FF | 0x3130..=0x318F => Script::Hangul,
+        0x00C0..=0x024F => Script::Latin,
+        _ if c.is_ascii_alphabetic() => Script::Latin,
+        _ => Script::Other,
+    }
+}
+
+/// 返回服务端 language_type 使用的语言名, 如 "Chinese"、"English"
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut han = 0usize;
+    let mut kana = 0usize;
+    let mut hangul = 0usize;
+    let mut latin = 0usize;
+    for c in text.chars() {
+        match script(c) {
+            Script::Han => han += 1,
+            Script::Kana => kana += 1,
+            Script::Hangul => hangul += 1,
+            Script::Latin => latin += 1,
+            Script::Other => {}
+        }
+    }
+    // 日文夹杂汉字, 出现假名即认为是日文
+    if kana > 0 {
+        return Some("Japanese");
+    }
+    if hangul > 0 && hangul >= han {
+        return Some("Korean");
+    }
+    if han == 0 && latin == 0 {
+        return None;
+    }
+    // 一个汉字的信息量大致相当于一个英文单词, 按字母数的1/4折算
+    if han * 4 >= latin {
+        Some("Chinese")
+    } else {
+        Some("English")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_language() {
+        assert_eq!(detect_language("你好，世界。"), Some("Chinese"));
+        assert_eq!(detect_language("Hello, world!"), Some("English"));
+        assert_eq!(detect_language("我今天用了 ChatGPT"), Some("Chinese"));
+        assert_eq!(detect_language("こんにちは世界"), Some("Japanese"));
+        assert_eq!(detect_language("안녕하세요"), Some("Korean"));
+        assert_eq!(detect_language("123, ..."), None);
+    }
+}
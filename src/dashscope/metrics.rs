@@ -0,0 +1,82 @@
+//!
+//! ## 会话统计
+//! 读取任务中顺带记录的指标, 不影响事件处理
+use std::time::{Duration, Instant};
+
+///
+/// response.audio.delta 到达间隔的统计, 用于观察网络/服务端抖动对播放流畅度的影响
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct JitterStats {
+    /// 参与统计的间隔数, 即分片数-1
+    pub gaps: u64,
+    pub mean: Duration,
+    /// 间隔的标准差, 即抖动
+    pub std_dev: Duration,
+    pub max: Duration,
+}
+
+///
+/// 用 Welford 算法在线计算间隔的均值和方差, 不保存历史时间戳
+#[derive(Debug, Default)]
+pub(crate) struct JitterTracker {
+    last: Option<Instant>,
+    gaps: u64,
+    // 单位为秒
+    mean: f64,
+    m2: f64,
+    max: Duration,
+}
+
+impl JitterTracker {
+    pub fn record(&mut self, at: Instant) {
+        if let Some(last) = self.last.replace(at) {
+            let gap = at.saturating_duration_since(last);
+            let x = gap.as_secs_f64();
+            self.gaps += 1;
+            let delta = x - self.mean;
+            self.mean += delta / self.gaps as f64;
+            self.m2 += delta * (x - self.mean);
+            self.max = self.max.max(gap);
+        }
+    }
+
+    /// 最近一次记录的时间
+    pub fn last(&self) -> Option<Instant> {
+        self.last
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        if self.gaps == 0 {
+            return JitterStats::default();
+        }
+        let variance = self.m2 / self.gaps as f64;
+        JitterStats {
+            gaps: self.gaps,
+            mean: Duration::from_secs_f64(self.mean),
+            std_dev: Duration::from_secs_f64(variance.max(0.0).sqrt()),
+            max: self.max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jitter_irregular_intervals() {
+        let mut tracker = JitterTracker::default();
+        assert_eq!(tracker.stats(), JitterStats::default());
+        let start = Instant::now();
+        for offset_ms in [0, 10, 40, 60] {
+            tracker.record(start + Duration::from_millis(offset_ms));
+        }
+        // 间隔 10/30/20ms, 均值20ms, 总体方差 200/3 ms²
+        let stats = tracker.stats();
+        assert_eq!(stats.gaps, 3);
+        assert_eq!(stats.max, Duration::from_millis(30));
+        assert!((stats.mean.as_secs_f64() - 0.020).abs() < 1e-9);
+        let expected_std_dev = (200.0f64 / 3.0).sqrt() / 1000.0;
+        assert!((stats.std_dev.as_secs_f64() - expected_std_dev).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,417 @@
+//!
+//! ## 单元测试用的 DashScope realtime 模拟服务端
+//! 只监听 127.0.0.1 的随机端口(ws://), 行为通过 handler 脚本化:
+//! - 握手阶段可以直接拒绝(返回指定状态码和响应头)
+//! - 连接建立后先发送 on_connect 里的消息
+//! - 每收到一个文本帧, 交给 handler 决定要回复的动作
+#![allow(dead_code, clippy::result_large_err)]
+use crate::dashscope::events::TimestampMark;
+use crate::dashscope::qwen_tts_realtime::QwenTtsRealtimeCallback;
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+pub(crate) enum MockAction {
+    Text(String),
+    Close(u16, String),
+    Sleep(Duration),
+    /// 不发送关闭帧直接断开TCP连接
+    Drop,
+}
+
+type MockHandler = Arc<dyn Fn(&Value) -> Vec<MockAction> + Send + Sync>;
+
+pub(crate) struct MockServer {
+    pub url: String,
+    // 服务端收到的所有文本帧
+    pub received: Arc<Mutex<Vec<String>>>,
+    // 最近一次握手的请求头(小写名)
+    pub headers: Arc<Mutex<Vec<(String, String)>>>,
+    // 收到的握手次数(包含被拒绝的)
+    pub connections: Arc<AtomicUsize>,
+    // 收到的 ping 帧数, pong 由 tungstenite 自动回复
+    pub pings: Arc<AtomicUsize>,
+    // 由客户端关闭或断开的连接数
+    pub client_closed: Arc<AtomicUsize>,
+}
+
+impl MockServer {
+    pub fn received_json(&self) -> Vec<Value> {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| serde_json::from_str(s).unwrap())
+            .collect()
+    }
+
+    pub fn received_types(&self) -> Vec<String> {
+        self.received_json()
+            .iter()
+            .map(|v| v["type"].as_str().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn ping_count(&self) -> usize {
+        self.pings.load(Ordering::SeqCst)
+    }
+
+    pub fn client_closed_count(&self) -> usize {
+        self.client_closed.load(Ordering::SeqCst)
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.clone())
+    }
+}
+
+pub(crate) struct MockServerBuilder {
+    reject: Option<(u16, Vec<(String, String)>)>,
+    // 只拒绝前n次握手, None表示一直拒绝
+    reject_limit: Option<usize>,
+    on_connect: Vec<String>,
+    handler: MockHandler,
+}
+
+impl MockServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            reject: None,
+            reject_limit: None,
+            on_connect: vec![session_created()],
+            handler: Arc::new(default_handler),
+        }
+    }
+
+    pub fn reject(mut self, status: u16, headers: Vec<(&str, &str)>) -> Self {
+        self.reject = Some((
+            status,
+            headers
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ));
+        self
+    }
+
+    pub fn reject_first(mut self, times: usize) -> Self {
+        self.reject_limit = Some(times);
+        self
+    }
+
+    pub fn on_connect(mut self, messages: Vec<String>) -> Self {
+        self.on_connect = messages;
+        self
+    }
+
+    pub fn handler(
+        mut self,
+        handler: impl Fn(&Value) -> Vec<MockAction> + Send + Sync + 'static,
+    ) -> Self {
+        self.handler = Arc::new(handler);
+        self
+    }
+
+    pub async fn spawn(self) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let pings = Arc::new(AtomicUsize::new(0));
+        let client_closed = Arc::new(AtomicUsize::new(0));
+        let server = MockServer {
+            url: format!("ws://{}/api-ws/v1/realtime", addr),
+            received: Arc::clone(&received),
+            headers: Arc::clone(&headers),
+            connections: Arc::clone(&connections),
+            pings: Arc::clone(&pings),
+            client_closed: Arc::clone(&client_closed),
+        };
+        let reject = self.reject;
+        let reject_limit = self.reject_limit;
+        let on_connect = self.on_connect;
+        let handler = self.handler;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = Arc::clone(&received);
+                let headers = Arc::clone(&headers);
+                let pings = Arc::clone(&pings);
+                let client_closed = Arc::clone(&client_closed);
+                let connection_index = connections.fetch_add(1, Ordering::SeqCst);
+                let reject = reject
+                    .clone()
+                    .filter(|_| reject_limit.is_none_or(|limit| connection_index < limit));
+                let on_connect = on_connect.clone();
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    let callback = |request: &Request, response: Response| {
+                        *headers.lock().unwrap() = request
+                            .headers()
+                            .iter()
+                            .map(|(k, v)| {
+                                (k.as_str().to_string(), v.to_str().unwrap_or("").to_string())
+                            })
+                            .collect();
+                        if let Some((status, extra_headers)) = reject {
+                            let mut error_response = ErrorResponse::new(Some(format!(
+                                "{{\"code\":\"mock\",\"status\":{}}}",
+                                status
+                            )));
+                            *error_response.status_mut() = StatusCode::from_u16(status).unwrap();
+                            for (k, v) in extra_headers {
+                                error_response.headers_mut().insert(
+                                    k.parse::<tokio_tungstenite::tungstenite::http::HeaderName>()
+                                        .unwrap(),
+                                    v.parse().unwrap(),
+                                );
+                            }
+                            return Err(error_response);
+                        }
+                        Ok(response)
+                    };
+                    let Ok(ws) = tokio_tungstenite::accept_hdr_async(stream, callback).await else {
+                        return;
+                    };
+                    let (mut writer, mut reader) = ws.split();
+                    for msg in on_connect {
+                        if writer.send(Message::text(msg)).await.is_err() {
+                            return;
+                        }
+                    }
+                    while let Some(Ok(msg)) = reader.next().await {
+                        if msg.is_ping() {
+                            pings.fetch_add(1, Ordering::SeqCst);
+                        }
+                        if !msg.is_text() {
+                            continue;
+                        }
+                        let text = msg.to_text().unwrap().to_string();
+                        received.lock().unwrap().push(text.clone());
+                        let Ok(event) = serde_json::from_str::<Value>(&text) else {
+                            continue;
+                        };
+                        for action in handler(&event) {
+                            let sent = match action {
+                                MockAction::Text(text) => writer.send(Message::text(text)).await,
+                                MockAction::Close(code, reason) => {
+                                    let _ = writer
+                                        .send(Message::Close(Some(CloseFrame {
+                                            code: CloseCode::from(code),
+                                            reason: reason.into(),
+                                        })))
+                                        .await;
+                                    return;
+                                }
+                                MockAction::Sleep(duration) => {
+                                    tokio::time::sleep(duration).await;
+                                    Ok(())
+                                }
+                                MockAction::Drop => return,
+                            };
+                            // 写入失败说明客户端已经断开
+                            if sent.is_err() {
+                                client_closed.fetch_add(1, Ordering::SeqCst);
+                                return;
+                            }
+                        }
+                    }
+                    client_closed.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        });
+        server
+    }
+}
+
+pub(crate) fn session_created() -> String {
+    json!({
+        "event_id": "event_mock_created",
+        "type": "session.created",
+        "session": {"id": "sess_mock", "model": "qwen3-tts-flash-realtime"},
+    })
+    .to_string()
+}
+
+pub(crate) fn audio_delta(audio: &[u8]) -> String {
+    json!({
+        "event_id": "event_mock_delta",
+        "type": "response.audio.delta",
+        "response_id": "resp_mock",
+        "delta": base64::engine::general_purpose::STANDARD.encode(audio),
+    })
+    .to_string()
+}
+
+pub(crate) fn event(event_type: &str) -> String {
+    json!({
+        "event_id": format!("event_mock_{}", event_type),
+        "type": event_type,
+    })
+    .to_string()
+}
+
+///
+/// 默认行为: 每段文本回复一个音频分片(内容为文本的utf8字节)和 response.done,
+/// session.finish 时回复 session.finished 并关闭连接
+pub(crate) fn default_handler(event: &Value) -> Vec<MockAction> {
+    match event["type"].as_str().unwrap_or_default() {
+        "session.update" => vec![MockAction::Text(
+            json!({
+                "event_id": "event_mock_updated",
+                "type": "session.updated",
+                "session": event["session"],
+            })
+            .to_string(),
+        )],
+        "input_text_buffer.append" => {
+            let text = event["text"].as_str().unwrap_or_default();
+            vec![
+                MockAction::Text(audio_delta(text.as_bytes())),
+                MockAction::Text(self::event("response.done")),
+            ]
+        }
+        "session.finish" => vec![
+            MockAction::Text(self::event("session.finished")),
+            MockAction::Close(1000, "bye".to_string()),
+        ],
+        _ => vec![],
+    }
+}
+
+///
+/// 接受TCP连接但从不响应握手的服务端, 模拟网络黑洞
+pub(crate) async fn spawn_blackhole_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut streams = vec![];
+        while let Ok((stream, _)) = listener.accept().await {
+            streams.push(stream);
+        }
+    });
+    format!("ws://{}", addr)
+}
+
+///
+/// 极简的HTTP模拟服务端, 每个请求交给handler按 path+query 返回(状态码, json body)
+pub(crate) async fn spawn_http_server(
+    handler: impl Fn(&str) -> (u16, String) + Send + Sync + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let target = request.split_whitespace().nth(1).unwrap_or("/");
+                let (status, body) = handler(target);
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+///
+/// 记录收到的事件的测试回调, finished 在读取任务结束(on_finish)时通知
+pub(crate) struct RecordingCallback {
+    pub events: Arc<Mutex<Vec<String>>>,
+    pub sent: Arc<Mutex<Vec<String>>>,
+    pub closes: Arc<Mutex<Vec<String>>>,
+    pub reconnects: Arc<Mutex<Vec<u32>>>,
+    pub timestamps: Arc<Mutex<Vec<TimestampMark>>>,
+    pub finished: Arc<tokio::sync::Notify>,
+}
+
+impl RecordingCallback {
+    pub fn new() -> Self {
+        Self {
+            events: Arc::new(Mutex::new(Vec::new())),
+            sent: Arc::new(Mutex::new(Vec::new())),
+            closes: Arc::new(Mutex::new(Vec::new())),
+            reconnects: Arc::new(Mutex::new(Vec::new())),
+            timestamps: Arc::new(Mutex::new(Vec::new())),
+            finished: Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+
+    pub fn event_types(events: &Mutex<Vec<String>>) -> Vec<String> {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| {
+                serde_json::from_str::<Value>(s).unwrap()["type"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect()
+    }
+}
+
+impl QwenTtsRealtimeCallback for RecordingCallback {
+    fn on_open(&self) {}
+
+    fn on_close(&self, close_msg: &str) {
+        self.closes.lock().unwrap().push(close_msg.to_string());
+    }
+
+    fn on_finish(&mut self, _close_msg: &str) {
+        self.finished.notify_one();
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        self.events.lock().unwrap().push(message.to_string());
+        false
+    }
+
+    fn on_send(&mut self, frame: &str) {
+        self.sent.lock().unwrap().push(frame.to_string());
+    }
+
+    fn on_reconnect(&mut self, attempt: u32) {
+        self.reconnects.lock().unwrap().push(attempt);
+    }
+
+    fn on_timestamp(&mut self, mark: TimestampMark) {
+        self.timestamps.lock().unwrap().push(mark);
+    }
+}
@@ -1,4 +1,18 @@
 mod parameters;
-mod dashscope_rs;
+pub mod dashscope_rs;
 pub mod qwen_tts_realtime;
-pub mod models;
\ No newline at end of file
+pub mod models;
+pub mod sinks;
+pub mod synthesis;
+pub mod transforms;
+pub mod audio_channel;
+pub mod language;
+pub mod self_test;
+pub mod checkpoint;
+pub mod metrics;
+pub mod diagnose;
+mod caption;
+pub mod events;
+pub mod audit;
+#[cfg(test)]
+pub(crate) mod mock_server;
\ No newline at end of file
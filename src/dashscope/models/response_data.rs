@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use crate::common::errors::GenerationError;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DashScopeResponseData {
@@ -60,3 +62,84 @@ pub struct TokensDetails {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cached_tokens: Option<i32>,
 }
+
+///
+/// 流式 Generation 的一个增量, 由SSE中一个事件的 data 解析而来;
+/// 兼容 result_format 为 message(output.choices) 和 text(output.text) 两种输出
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GenerationChunk {
+    pub content: String,
+    /// 推理模型的思考内容, 没有时为None
+    pub reasoning_content: Option<String>,
+    /// 最后一个增量为 "stop"/"length" 等, 其余为None
+    pub finish_reason: Option<String>,
+    /// 服务端在每个增量或最后一个增量中附带的 token 用量
+    pub usage: Option<Value>,
+}
+
+impl GenerationChunk {
+    /// 服务端以SSE下发的错误(带 code/message, 没有 output)转换为 DashScopeResponseError
+    pub fn from_data(data: &str) -> Result<Self, GenerationError> {
+        let v = serde_json::from_str::<Value>(data)?;
+        let output = &v["output"];
+        if output.is_null() {
+            return Err(GenerationError::DashScopeResponseError(data.to_string()));
+        }
+        let choice = &output["choices"][0];
+        let message = &choice["message"];
+        let content = message["content"]
+            .as_str()
+            .or(output["text"].as_str())
+            .unwrap_or_default()
+            .to_string();
+        let finish_reason = choice["finish_reason"]
+            .as_str()
+            .or(output["finish_reason"].as_str())
+            .filter(|reason| !reason.is_empty() && *reason != "null")
+            .map(str::to_string);
+        Ok(Self {
+            content,
+            reasoning_content: message["reasoning_content"].as_str().map(str::to_string),
+            finish_reason,
+            usage: Some(v["usage"].clone()).filter(|usage| !usage.is_null()),
+        })
+    }
+}
+
+///
+/// GET /api/v1/models 分页返回的模型列表
+/// ```json
+/// {
+///     "request_id": "...",
+///     "output": {
+///         "models": [{"model": "qwen3-tts-flash-realtime", "type": "tts", "capabilities": ["realtime"]}],
+///         "page_no": 1,
+///         "page_size": 100,
+///         "total": 1
+///     }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModelListResponse {
+    #[serde(default)]
+    pub request_id: String,
+    pub output: ModelListOutput,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModelListOutput {
+    #[serde(default)]
+    pub models: Vec<ModelInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    #[serde(alias = "model")]
+    pub id: String,
+    #[serde(default, alias = "model_type")]
+    pub r#type: String,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
@@ -176,6 +176,15 @@ pub struct Parameters {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub repetition_penalty: Option<f32>,
+
+    /// 为true时user-agent只保留 dashscope/rust 版本, 不上报 platform/processor 信息;
+    /// 只影响请求头, 不会序列化进请求体
+    #[serde(skip)]
+    pub minimal_user_agent: bool,
+
+    /// 固定接口版本, 以 api_version 查询参数附加在请求url上; None时使用服务端默认版本
+    #[serde(skip)]
+    pub api_version: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
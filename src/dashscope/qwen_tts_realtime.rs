@@ -1,37 +1,66 @@
+use crate::common::errors::QwenTtsError;
 use crate::common::logging::init_logger;
-use futures_util::stream::SplitSink;
-use futures_util::{SinkExt, StreamExt};
-use serde_json::json;
+use crate::dashscope::audit::{AuditAction, AuditSink, Auditor};
+use crate::dashscope::audio_channel::{
+    AudioReceiver, AudioSender, DEFAULT_AUDIO_BUFFER_CAPACITY, OverflowPolicy, SendOutcome,
+    audio_channel,
+};
+use crate::dashscope::caption::{CAPTION_DELTA_EVENTS, CAPTION_DONE_EVENTS, CaptionAccumulator};
+use crate::dashscope::events::{QwenTtsEvent, TimestampMark};
+use crate::dashscope::language::Language;
+use crate::dashscope::metrics::{JitterStats, JitterTracker};
+use crate::dashscope::sinks::{WavSpec, write_wav_header};
+use crate::dashscope::synthesis::{ReconnectPolicy, synthesize_to_vec_with_timeout};
+use crate::dashscope::transforms::{FrameAligner, FrameAlignment, SampleTransform, apply_pcm16};
+use futures_util::stream::{SplitSink, SplitStream};
+use base64::Engine;
+use futures_util::{FutureExt, SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify, broadcast, mpsc, oneshot, watch};
+use tokio_stream::Stream;
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::tungstenite::handshake::client::{Request, Response};
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-pub struct AudioFormat<'a> {
-    format: &'a str,
+const MP3_DEFAULT_BIT_RATE: &str = "128kbps";
+const OPUS_DEFAULT_BIT_RATE: &str = "32kbps";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFormat {
+    format: &'static str,
     sample_rate: u32,
-    channels: &'a str,
-    bit_rate: &'a str,
-    format_str: &'a str,
+    channels: &'static str,
+    bit_rate: &'static str,
+    format_str: &'static str,
 }
 
-impl<'a> AudioFormat<'a> {
-    fn new(
-        format: &'a str,
+impl AudioFormat {
+    ///
+    /// 自定义格式, 预设常量不满足时使用; 字段取值与常量一致:
+    /// channels 为 mono/stereo, bit_rate 对PCM是位深(如 16bit)、对压缩格式是码率(如 128kbps)
+    pub fn new(
+        format: &'static str,
         sample_rate: u32,
-        channels: &'a str,
-        bit_rate: &'a str,
-        format_str: &'a str,
+        channels: &'static str,
+        bit_rate: &'static str,
+        format_str: &'static str,
     ) -> Self {
         Self {
-            format: format,
-            sample_rate: sample_rate,
-            channels: channels,
-            bit_rate: bit_rate,
-            format_str: format_str,
+            format,
+            sample_rate,
+            channels,
+            bit_rate,
+            format_str,
         }
     }
     pub const PCM_24000HZ_MONO_16BIT: Self = Self {
@@ -41,189 +70,6126 @@ impl<'a> AudioFormat<'a> {
         bit_rate: "16bit",
         format_str: "pcm16",
     };
+    pub const PCM_16000HZ_MONO_16BIT: Self = Self {
+        format: "pcm",
+        sample_rate: 16000,
+        channels: "mono",
+        bit_rate: "16bit",
+        format_str: "pcm16",
+    };
+    /// 压缩格式的 bit_rate 为码率(kbps), 会随 session.update 一起发送
+    pub const MP3_16000HZ_MONO: Self = Self::compressed("mp3", 16000, MP3_DEFAULT_BIT_RATE);
+    pub const MP3_24000HZ_MONO: Self = Self::compressed("mp3", 24000, MP3_DEFAULT_BIT_RATE);
+    pub const MP3_48000HZ_MONO: Self = Self::compressed("mp3", 48000, MP3_DEFAULT_BIT_RATE);
+    pub const OPUS_24000HZ_MONO: Self = Self::compressed("opus", 24000, OPUS_DEFAULT_BIT_RATE);
+
+    const fn compressed(format: &'static str, sample_rate: u32, bit_rate: &'static str) -> Self {
+        Self {
+            format,
+            sample_rate,
+            channels: "mono",
+            bit_rate,
+            format_str: format,
+        }
+    }
+
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> &'static str {
+        self.channels
+    }
+
+    pub fn bit_rate(&self) -> &'static str {
+        self.bit_rate
+    }
+
+    /// 格式的完整标识, 如 pcm16、mp3; 只在本地使用, session.update 的 response_format
+    /// 只接受 pcm/wav/mp3/opus, 发送的是 format, 位深由 pcm 隐含为16bit
+    pub fn format_str(&self) -> &'static str {
+        self.format_str
+    }
+
+    /// channels 字段转换为声道数, mono=1, stereo=2
+    pub fn channel_count(&self) -> u16 {
+        match self.channels {
+            "stereo" => 2,
+            _ => 1,
+        }
+    }
+
+    /// bit_rate 字段(如 16bit)转换为采样位深
+    pub fn bits_per_sample(&self) -> u16 {
+        self.bit_rate
+            .trim_end_matches("bit")
+            .parse()
+            .unwrap_or(16)
+    }
+
+    /// 压缩格式的码率, bit_rate 不是 kbps 形式(PCM的位深)时为None
+    pub fn bit_rate_kbps(&self) -> Option<u32> {
+        self.bit_rate.strip_suffix("kbps")?.parse().ok()
+    }
+
+    /// 每一帧(所有声道各一个采样)的字节数
+    fn block_align(&self) -> usize {
+        self.channel_count() as usize * (self.bits_per_sample() as usize / 8)
+    }
+
+    ///
+    /// 44字节的 RIFF/WAVE 头, 声道数、采样率、位深取自本格式, data_len 为PCM数据的字节数;
+    /// 总长度未知时可以先传0, 写完后用 WavWriter 的方式回填, 或直接使用 WavWriter
+    pub fn wav_header(&self, data_len: u32) -> Vec<u8> {
+        let spec = WavSpec {
+            channels: self.channel_count(),
+            sample_rate: self.sample_rate,
+            bits_per_sample: self.bits_per_sample(),
+        };
+        let mut header = Vec::with_capacity(44);
+        write_wav_header(&mut header, spec, data_len).expect("写入Vec不会失败");
+        header
+    }
+
+    /// 时长对应的PCM字节数, 向下对齐到整帧; 仅对未压缩的PCM有意义
+    pub fn bytes_for_duration(&self, d: Duration) -> usize {
+        let frames = d.as_nanos() * self.sample_rate as u128 / 1_000_000_000;
+        frames as usize * self.block_align()
+    }
+
+    /// PCM字节数对应的时长, 不足一帧的尾部字节忽略; 仅对未压缩的PCM有意义
+    pub fn duration_for_bytes(&self, bytes: usize) -> Duration {
+        let frames = (bytes / self.block_align()) as u64;
+        Duration::from_nanos(frames * 1_000_000_000 / self.sample_rate as u64)
+    }
+}
+
+/// AudioFormat::from_str 无法识别的格式标识
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("无法识别的音频格式: {0}, 格式为 <pcm|wav|mp3|opus>_<8000|16000|24000|48000>[_mono][_16bit]")]
+pub struct ParseAudioFormatError(String);
+
+impl std::str::FromStr for AudioFormat {
+    type Err = ParseAudioFormatError;
+
+    ///
+    /// 从配置/命令行的字符串解析格式, 不区分大小写, 如 pcm_24000_mono_16bit、pcm_16000、mp3_24000;
+    /// 采样率可以带hz后缀, 声道只支持mono, 位深只支持16bit
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAudioFormatError(s.to_string());
+        let lower = s.trim().to_ascii_lowercase();
+        let mut parts = lower.split('_');
+        let format = match parts.next() {
+            Some("pcm") => "pcm",
+            Some("wav") => "wav",
+            Some("mp3") => "mp3",
+            Some("opus") => "opus",
+            _ => return Err(err()),
+        };
+        let sample_rate = match parts.next().map(|rate| rate.trim_end_matches("hz")) {
+            Some("8000") => 8000,
+            Some("16000") => 16000,
+            Some("24000") => 24000,
+            Some("48000") => 48000,
+            _ => return Err(err()),
+        };
+        let bit_rate = match format {
+            "mp3" => MP3_DEFAULT_BIT_RATE,
+            "opus" => OPUS_DEFAULT_BIT_RATE,
+            _ => "16bit",
+        };
+        let mut rest = parts.peekable();
+        rest.next_if_eq(&"mono");
+        rest.next_if(|part| *part == "16bit" || *part == bit_rate);
+        if rest.next().is_some() {
+            return Err(err());
+        }
+        Ok(Self {
+            format,
+            sample_rate,
+            channels: "mono",
+            bit_rate,
+            format_str: if format == "pcm" { "pcm16" } else { format },
+        })
+    }
 }
 
+impl std::fmt::Display for AudioFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}_{}_{}_{}",
+            self.format, self.sample_rate, self.channels, self.bit_rate
+        )
+    }
+}
+
+///
+/// Retry-After 可以是秒数, 也可以是HTTP日期(如 Wed, 21 Oct 2015 07:28:00 GMT)
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+///
+/// 从音频分片携带的元数据确认实际输出格式, 元数据可以直接放在事件上
+/// 也可以嵌套在 audio_format 字段里, 目前识别 sample_rate 和 format 两项;
+/// 以服务端为准, 返回确认后的格式以及与请求格式不一致的描述
+pub(crate) fn reconcile_audio_format(
+    requested: AudioFormat,
+    frame: &Value,
+) -> (AudioFormat, Vec<String>) {
+    let metadata = frame.get("audio_format").unwrap_or(frame);
+    let mut confirmed = requested;
+    let mut mismatches = vec![];
+    if let Some(sample_rate) = metadata.get("sample_rate").and_then(Value::as_u64)
+        && sample_rate != requested.sample_rate as u64
+    {
+        mismatches.push(format!(
+            "sample_rate: 请求 {}, 实际 {}",
+            requested.sample_rate, sample_rate
+        ));
+        confirmed.sample_rate = sample_rate as u32;
+    }
+    if let Some(format) = metadata.get("format").and_then(Value::as_str)
+        && format != requested.format
+    {
+        mismatches.push(format!("format: 请求 {}, 实际 {}", requested.format, format));
+        if let Some(known) = ["pcm", "wav", "mp3", "opus"].into_iter().find(|f| *f == format) {
+            confirmed.format = known;
+        }
+    }
+    (confirmed, mismatches)
+}
+
+///
+/// 同步回调, 在读取任务内按事件顺序直接调用
+///
+/// 回调有两种接入方式:
+/// - 同步: 实现本trait, 通过 SharedCallback 注册; 延迟最低, on_event 返回true可以终止读取,
+///   但回调内不能await, 耗时操作会直接阻塞读取任务; 读取任务默认把原始消息交给 on_event,
+///   typed_events 返回true时改为解析后调用 on_typed_event, 这时 on_event 直接返回false即可
+/// - 异步: 通过 QwenTtsRealtimeBuilder::event_channel 注册一个 mpsc::Sender<CallbackEvent>,
+///   事件推入通道后由调用方自己的异步任务消费, 适合写S3/AsyncWrite等需要await的sink;
+///   通道是有界的, 消费跟不上时读取任务会等待(背压), 丢弃Receiver会终止读取
 pub trait QwenTtsRealtimeCallback {
     fn on_open(&self);
     fn on_close(&self, close_msg: &str);
-    fn on_finish(&mut self, close_msg: &str);
+    ///
+    /// 读取任务结束时调用, 每个连接只调用一次: 正常情况下是收到 session.finished 后服务端关闭连接,
+    /// 也可能是连接异常断开(之前会先调用 on_error); 是否正常结束可以用 close_info() 区分
+    fn on_finish(&mut self, _close_msg: &str) {}
     fn on_event(&mut self, message: &str) -> bool;
+    /// 解析后的事件, 返回true终止读取; 只有 typed_events 返回true时读取任务才会调用,
+    /// 默认把事件重新编码后交给 on_event, 见 QwenTtsEvent::to_message
+    fn on_typed_event(&mut self, event: QwenTtsEvent) -> bool {
+        self.on_event(&event.to_message())
+    }
+    /// 返回true时读取任务把事件解析为 QwenTtsEvent 交给 on_typed_event;
+    /// 默认false, 原始消息直接交给 on_event, event_id、音频元数据等字段完整保留
+    fn typed_events(&self) -> bool {
+        false
+    }
+    /// 读取出错、连接被直接断开、收到无法解码的文本帧或读取任务panic时调用,
+    /// 连接结束的情况之后仍会调用 on_finish
+    fn on_error(&mut self, _error: &str) {}
+    /// 服务端推送的 session.update/session.updated 改变了会话配置(音色、格式等)时调用
+    fn on_config_changed(&self, _config: &SessionConfig) {}
+    /// 每个发往服务端的文本帧在经过中间件之后、写入连接之前调用, 与 on_event 对应;
+    /// 在发送方所在的任务中调用, 不在读取任务内
+    fn on_send(&mut self, _frame: &str) {}
+    /// with_reconnect 开启时, 连接异常断开后每次重连尝试之前调用, attempt 从1开始;
+    /// 收到 session.finished 后的正常关闭不会重连
+    fn on_reconnect(&mut self, _attempt: u32) {}
+    /// 服务端事件中附带的字/词时间戳, 在对应事件的 on_event 之前按顺序调用, 见 TimestampMark::parse_all
+    fn on_timestamp(&mut self, _mark: TimestampMark) {}
+}
+
+pub type SharedCallback = Arc<Mutex<Box<dyn QwenTtsRealtimeCallback + Sync + Send>>>;
+
+///
+/// 推送给异步消费者的事件, 与 QwenTtsRealtimeCallback 的方法一一对应
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallbackEvent {
+    Open,
+    Event(String),
+    Close(String),
+    Finish(String),
+    Error(String),
+    ConfigChanged(SessionConfig),
+    /// 发往服务端的文本帧, 与接收的 Event 来自不同任务, 两者之间的先后顺序不确定
+    Sent(String),
+    /// 第几次重连尝试
+    Reconnect(u32),
+    Timestamp(TimestampMark),
+}
+
+///
+/// recv() 拉取到的服务端事件, 连接结束前最后一项总是 Closed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    Event(Value),
+    Closed(CloseInfo),
+}
+
+///
+/// 连接结束的原因, 用于区分正常结束和连接异常断开
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CloseInfo {
+    /// 连接结束前是否收到了 session.finished
+    pub session_finished: bool,
+    /// 服务端关闭帧的状态码, 没有收到关闭帧时为None
+    pub close_code: Option<u16>,
+    pub close_reason: String,
+    /// 读取出错或连接被直接断开时的错误信息
+    pub error: Option<String>,
+    /// 服务端提示可以立即重连, 见 reconnect_hint
+    pub reconnect_hint: bool,
+}
+
+///
+/// 服务端的重连提示: 关闭码 1012(Service Restart)/1013(Try Again Later),
+/// 或关闭原因/事件中带有 "reconnect": true (顶层或 error 对象内)
+pub(crate) fn reconnect_hint(close_code: Option<u16>, payload: &Value) -> bool {
+    matches!(close_code, Some(1012) | Some(1013))
+        || payload["reconnect"] == true
+        || payload["error"]["reconnect"] == true
+}
+
+impl CloseInfo {
+    /// 收到了 session.finished 且没有读取错误
+    pub fn is_normal(&self) -> bool {
+        self.session_finished && self.error.is_none()
+    }
+}
+
+///
+/// 会话状态, 按 Connected -> Active -> Finishing -> Closed/Failed 的顺序变化,
+/// 连接在任何阶段结束都会直接变为 Closed 或 Failed; 建连在 connect/new 返回前完成, 没有单独的连接中状态
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SessionState {
+    /// 已连接, 还没有发送文本
+    #[default]
+    Connected,
+    /// 已发送文本, 合成进行中
+    Active,
+    /// 已发送 session.finish, 等待服务端结束会话
+    Finishing,
+    /// 连接已关闭且没有读取错误
+    Closed,
+    /// 读取出错、连接被直接断开或读取任务panic
+    Failed(String),
+}
+
+impl SessionState {
+    /// 读取任务仍在运行(Connected、Active、Finishing)
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self,
+            SessionState::Connected | SessionState::Active | SessionState::Finishing
+        )
+    }
+}
+
+///
+/// 会话的汇总统计, 读取任务结束后各项不再变化
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FinishSummary {
+    pub session_finished: bool,
+    /// 收到的 response.audio.delta 数
+    pub delta_count: u64,
+    /// 解码后的音频字节数
+    pub audio_bytes: u64,
+    pub jitter: JitterStats,
+}
+
+///
+/// wait_until_finished 的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishOutcome {
+    /// 收到了 session.finished, 合成完整
+    Finished,
+    /// 连接在 session.finished 之前结束, 音频可能不完整
+    ClosedEarly(CloseInfo),
+    /// 等待超时, 连接仍然没有结束
+    Timeout,
+}
+
+/// 默认的实时接口地址(北京地域)
+const DEFAULT_REALTIME_URL: &str = "wss://dashscope.aliyuncs.com/api-ws/v1/realtime";
+/// 单个 input_text_buffer.append 中文本的默认最大字节数(UTF-8), 留出JSON包装的余量
+pub const DEFAULT_MAX_APPEND_BYTES: usize = 8 * 1024;
+/// 默认每20秒发送一次 WebSocket ping, 避免空闲连接被中间网络设备或服务端断开;
+/// 心跳默认开启, 不提供单独的 keepalive_interval: 连接空闲时才发送的 keepalive 就是这里的心跳,
+/// 需要完全不发 ping 时用 QwenTtsRealtimeBuilder::keepalive(false)
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+/// 收到 session.finished 后继续接收迟到音频的默认时长
+pub const DEFAULT_FINISHED_GRACE: Duration = Duration::from_millis(200);
+/// 默认的建连超时(包含TCP连接、TLS和WebSocket握手), 避免网络黑洞时一直卡住
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// synthesize_all 从建连到收到 session.finished 的默认最长等待时间
+pub const DEFAULT_SYNTHESIZE_ALL_TIMEOUT: Duration = Duration::from_secs(300);
+/// with_reconnect 指数退避的最长等待时间
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+/// 未传入API Key时读取的环境变量
+pub const API_KEY_ENV: &str = "DASHSCOPE_API_KEY";
+/// 保存API Key的文件路径(如 Docker secrets 挂载的文件), 优先于 DASHSCOPE_API_KEY
+pub const API_KEY_FILE_ENV: &str = "DASHSCOPE_API_KEY_FILE";
+/// 未设置url时读取的环境变量
+pub const REALTIME_URL_ENV: &str = "DASHSCOPE_REALTIME_URL";
+/// 未设置 workspace 时读取的环境变量
+pub const WORKSPACE_ENV: &str = "DASHSCOPE_WORKSPACE_ID";
+
+/// 配置项的来源, 优先级 Param > File > Env > Default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSource {
+    /// 代码中显式传入
+    Param,
+    /// 从密钥文件读取, 只用于API Key
+    File,
+    /// 从环境变量读取
+    Env,
+    /// 内置默认值
+    Default,
+}
+
+///
+/// 经过参数/环境变量/默认值解析后实际生效的配置, 用于排查连错地域、用错音色等问题;
+/// API Key 只保留首尾几位
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveConfig {
+    pub model: String,
+    /// 实际连接的完整url, 含 model 等查询参数
+    pub url: String,
+    pub url_source: ValueSource,
+    /// 由url的域名推断的地域, 私有化部署等无法识别的域名为 "custom"
+    pub region: String,
+    pub api_key: String,
+    /// None 表示参数和环境变量都没有提供API Key
+    pub api_key_source: Option<ValueSource>,
+    pub workspace: Option<String>,
+    /// 以下会话配置在 update_session 之前为None
+    pub voice: Option<String>,
+    pub response_format: Option<AudioFormat>,
+    pub mode: Option<String>,
+}
+
+impl EffectiveConfig {
+    fn resolve(
+        model: &str,
+        api_key: Option<(&str, ValueSource)>,
+        url: Option<(&str, ValueSource)>,
+        workspace: Option<&str>,
+        api_version: Option<&str>,
+    ) -> Self {
+        let (base_url, url_source) = url.unwrap_or((DEFAULT_REALTIME_URL, ValueSource::Default));
+        Self {
+            model: model.to_string(),
+            // url 不合法时原样展示, connect 时才返回错误
+            url: QwenTtsRealtime::build_url(Some(base_url), model, api_version)
+                .unwrap_or_else(|_| base_url.to_string()),
+            url_source,
+            region: region_of(base_url).to_string(),
+            api_key: api_key.map(|(key, _)| redact_key(key)).unwrap_or_default(),
+            api_key_source: api_key.map(|(_, source)| source),
+            workspace: workspace.map(str::to_string),
+            voice: None,
+            response_format: None,
+            mode: None,
+        }
+    }
+}
+
+/// 参数优先, 参数为空时读取环境变量; 去掉首尾空白, 只有空白的值视为未设置
+fn resolve_value(
+    param: Option<&str>,
+    env_key: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Option<(String, ValueSource)> {
+    match param.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) => Some((v.to_string(), ValueSource::Param)),
+        None => lookup(env_key)
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .map(|v| (v, ValueSource::Env)),
+    }
+}
+
+///
+/// 读取密钥文件, 去掉首尾空白; 文件不存在、无法读取或内容为空时返回 ApiKeyFile
+fn read_api_key_file(path: &Path) -> Result<String, QwenTtsError> {
+    let key_file_error = |reason: String| QwenTtsError::ApiKeyFile {
+        path: path.display().to_string(),
+        reason,
+    };
+    let content = std::fs::read_to_string(path).map_err(|e| key_file_error(e.to_string()))?;
+    let key = content.trim();
+    if key.is_empty() {
+        return Err(key_file_error("文件内容为空".to_string()));
+    }
+    Ok(key.to_string())
+}
+
+fn region_of(url: &str) -> &'static str {
+    let host = url
+        .split("://")
+        .nth(1)
+        .unwrap_or(url)
+        .split(['/', ':', '?'])
+        .next()
+        .unwrap_or_default();
+    match host {
+        "dashscope.aliyuncs.com" => "cn-beijing",
+        "dashscope-intl.aliyuncs.com" => "ap-southeast-1",
+        _ => "custom",
+    }
+}
+
+fn redact_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "***".to_string();
+    }
+    let head: String = chars[..3].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}***{}", head, tail)
+}
+
+///
+/// 建立连接时的可选项, 由 QwenTtsRealtimeBuilder 填充
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectOptions {
+    pub minimal_user_agent: bool,
+    // 为true时从连接建立起缓存所有服务端事件, 供 recv() 拉取
+    pub pull_events: bool,
+    // 固定的接口版本, 以 api_version 查询参数附加在url上
+    pub api_version: Option<String>,
+    // 建连时DNS解析失败的重试策略, 与 with_reconnect 共用; None 时不重试
+    pub dns_retry: Option<ReconnectPolicy>,
+    // 心跳(keepalive)间隔, None 时为 DEFAULT_HEARTBEAT_INTERVAL 而不是关闭, Duration::ZERO 关闭
+    pub heartbeat_interval: Option<Duration>,
+    // 建连超时, None 时为 DEFAULT_CONNECT_TIMEOUT
+    pub connect_timeout: Option<Duration>,
+}
+
+///
+/// 会话级自动重连需要的握手参数, 由 QwenTtsRealtimeBuilder::with_reconnect 开启
+#[derive(Clone)]
+struct ReconnectTarget {
+    // 已经拼好 model 等查询参数的完整url
+    url: String,
+    api_key: String,
+    workspace: Option<String>,
+    minimal_user_agent: bool,
+    connect_timeout: Duration,
+    policy: ReconnectPolicy,
+}
+
+///
+/// 读取任务使用的回调分发, 用enum而不是trait object区分同步回调和异步通道
+#[derive(Clone)]
+pub(crate) enum CallbackDispatch {
+    Sync(SharedCallback),
+    Channel(mpsc::Sender<CallbackEvent>),
+}
+
+impl CallbackDispatch {
+    async fn on_open(&self) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_ref().on_open(),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Open).await;
+            }
+        }
+    }
+
+    /// 返回true时终止读取任务
+    async fn on_event(&self, message: &str) -> bool {
+        match self {
+            CallbackDispatch::Sync(callback) => {
+                let mut callback = callback.lock().await;
+                if callback.typed_events() {
+                    callback.on_typed_event(QwenTtsEvent::parse(message))
+                } else {
+                    callback.on_event(message)
+                }
+            }
+            CallbackDispatch::Channel(tx) => tx
+                .send(CallbackEvent::Event(message.to_string()))
+                .await
+                .is_err(),
+        }
+    }
+
+    async fn on_close(&self, close_msg: &str) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_ref().on_close(close_msg),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Close(close_msg.to_string())).await;
+            }
+        }
+    }
+
+    async fn on_finish(&self, close_msg: &str) {
+        match self {
+            CallbackDispatch::Sync(callback) => {
+                callback.lock().await.as_mut().on_finish(close_msg)
+            }
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Finish(close_msg.to_string())).await;
+            }
+        }
+    }
+
+    async fn on_config_changed(&self, config: &SessionConfig) {
+        match self {
+            CallbackDispatch::Sync(callback) => {
+                callback.lock().await.as_ref().on_config_changed(config)
+            }
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::ConfigChanged(config.clone())).await;
+            }
+        }
+    }
+
+    async fn on_send(&self, frame: &str) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_mut().on_send(frame),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Sent(frame.to_string())).await;
+            }
+        }
+    }
+
+    async fn on_reconnect(&self, attempt: u32) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_mut().on_reconnect(attempt),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Reconnect(attempt)).await;
+            }
+        }
+    }
+
+    async fn on_timestamp(&self, mark: TimestampMark) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_mut().on_timestamp(mark),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Timestamp(mark)).await;
+            }
+        }
+    }
+
+    async fn on_error(&self, error: &str) {
+        match self {
+            CallbackDispatch::Sync(callback) => callback.lock().await.as_mut().on_error(error),
+            CallbackDispatch::Channel(tx) => {
+                let _ = tx.send(CallbackEvent::Error(error.to_string())).await;
+            }
+        }
+    }
+}
+
+///
+/// 发送事件的中间件, 每个发往服务端的事件(session.update/append/finish)都会依次经过
+/// 用于日志、计量或策略控制; 可以直接修改事件内容, 返回Err时该事件不会被发送, 错误原样返回给调用方
+pub trait EventMiddleware {
+    fn before_send(&self, event: &mut Value) -> Result<(), QwenTtsError>;
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+// 读取任务在响应级重试时也需要发送事件, 所以写端是共享的
+type WsWriter = Arc<Mutex<SplitSink<WsStream, Message>>>;
+
+///
+/// finish 之后再调用 append_text 时的处理方式
+///
+/// 会话的生命周期: connect -> update_session -> append_text(可多次) -> finish -> 服务端 session.finished 并关闭连接;
+/// finish 之后当前连接不再接受文本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AfterFinishPolicy {
+    /// 返回 InvalidState
+    #[default]
+    Reject,
+    /// 等待当前会话结束, 用同一个 builder 重新建连并恢复最近一次的会话配置, 再发送这段文本;
+    /// 旧连接上的 audio_stream 订阅和 recv() 队列随旧会话结束, 需要重新订阅;
+    /// 旧会话在 connect_timeout 内没有关闭时返回 Timeout, 之后再 append 会重新等待
+    NewSession,
+}
+
+///
+/// audio_stream 交付解码音频的频率
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliveryPolicy {
+    /// 每个 response.audio.delta 交付一次, 延迟最低
+    #[default]
+    PerDelta,
+    /// 累积到至少 min_bytes 再交付, 连接结束时交付剩余部分
+    Accumulate { min_bytes: usize },
+    /// 每隔 duration 交付一次这段时间内累积的音频, 没有音频时不交付
+    Interval { duration: Duration },
+}
+
+impl DeliveryPolicy {
+    /// 按策略把 rx 中的分片合并后转发给 tx, rx 结束时把剩余音频一次性交付;
+    /// tx 不再接收时提前返回对应的结果
+    async fn deliver(
+        self,
+        mut rx: AudioReceiver<Vec<u8>>,
+        tx: AudioSender<Vec<u8>>,
+    ) -> SendOutcome {
+        let mut buffer = Vec::new();
+        match self {
+            DeliveryPolicy::PerDelta => {
+                while let Some(chunk) = rx.next().await {
+                    let outcome = tx.send(chunk).await;
+                    if outcome != SendOutcome::Delivered {
+                        return outcome;
+                    }
+                }
+            }
+            DeliveryPolicy::Accumulate { min_bytes } => {
+                while let Some(chunk) = rx.next().await {
+                    buffer.extend_from_slice(&chunk);
+                    if buffer.len() >= min_bytes {
+                        let outcome = tx.send(std::mem::take(&mut buffer)).await;
+                        if outcome != SendOutcome::Delivered {
+                            return outcome;
+                        }
+                    }
+                }
+            }
+            DeliveryPolicy::Interval { duration } => {
+                let mut ticker =
+                    tokio::time::interval_at(tokio::time::Instant::now() + duration, duration);
+                loop {
+                    tokio::select! {
+                        chunk = rx.next() => match chunk {
+                            Some(chunk) => buffer.extend_from_slice(&chunk),
+                            None => break,
+                        },
+                        _ = ticker.tick() => {
+                            if !buffer.is_empty() {
+                                let outcome = tx.send(std::mem::take(&mut buffer)).await;
+                                if outcome != SendOutcome::Delivered {
+                                    return outcome;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if buffer.is_empty() {
+            return SendOutcome::Delivered;
+        }
+        tx.send(buffer).await
+    }
+}
+
+///
+/// 一个音频分片及其订阅者, 在锁外由读取任务逐个发送
+struct AudioDispatch {
+    delta: String,
+    audio_bytes: Option<Vec<u8>>,
+    audio_streams: Vec<(u64, AudioSender<Vec<u8>>)>,
+    audio_streams_base64: Vec<(u64, AudioSender<String>)>,
+    audio_broadcast: Option<broadcast::Sender<Arc<Vec<u8>>>>,
+}
+
+/// track_event 的处理结果, 需要在锁外继续处理
+#[derive(Default)]
+struct EventEffects {
+    // 响应级重试要重新发送的事件
+    // 长文本会拆成多个 append 事件
+    retry: Vec<Value>,
+    audio: Option<AudioDispatch>,
+    // 服务端推送导致的会话配置变化
+    config_changed: Option<SessionConfig>,
+    // 需要中止会话时的原因
+    abort: Option<String>,
+}
+
+/// 客户端与读取任务共享的会话状态
+#[derive(Default)]
+struct SessionShared {
+    requested_format: Option<AudioFormat>,
+    // 收到第一个音频分片后确认的实际格式
+    confirmed_format: Option<AudioFormat>,
+    // audio_stream / audio_stream_base64 的订阅者, 读取任务结束时清空以结束流
+    audio_streams: Vec<(u64, AudioSender<Vec<u8>>)>,
+    audio_streams_base64: Vec<(u64, AudioSender<String>)>,
+    // subscribe() 的广播发送端, 第一次订阅时创建, 与 audio_streams 同时结束
+    audio_broadcast: Option<broadcast::Sender<Arc<Vec<u8>>>>,
+    next_subscriber_id: u64,
+    // OverflowPolicy::Error 下有订阅因通道写满被提前结束
+    audio_overflowed: bool,
+    reader_finished: bool,
+    session_finished: bool,
+    // 事件中出现过重连提示
+    reconnect_hint: bool,
+    // 读取任务结束后的关闭信息
+    close_info: Option<CloseInfo>,
+    state: watch::Sender<SessionState>,
+    // 音频分片的到达统计
+    delta_count: u64,
+    audio_bytes: u64,
+    jitter: JitterTracker,
+    // session.finished 时追加到音频流末尾的静音时长
+    trailing_silence: Option<Duration>,
+    // 最近一次发送的会话配置, 临时切换音色后恢复以及空闲重发时使用
+    session_config: Option<SessionConfig>,
+    // 最近一次向服务端发送事件的时间, 空闲检测用
+    last_sent: Option<Instant>,
+    // update_session_and_wait 等待中的 session.updated
+    session_updated: Option<oneshot::Sender<Value>>,
+    // recv() 的事件队列, 只有开启 pull_events 时存在
+    events: Option<mpsc::UnboundedSender<ServerEvent>>,
+    // 只作用于解码后的 16bit PCM, base64透传不受影响
+    sample_transforms: Vec<Arc<dyn SampleTransform + Send + Sync>>,
+    // 输出格式不是 16bit PCM 而跳过变换时只记录一次日志
+    transforms_skipped: bool,
+    middlewares: Vec<Arc<dyn EventMiddleware + Sync + Send>>,
+    // 响应级重试: 最多重试次数(0为关闭)、当前文本已重试次数、最近一次提交的文本
+    response_retry_max: u32,
+    response_retry_attempts: u32,
+    last_text: Option<String>,
+    // 单个 input_text_buffer.append 中文本的最大字节数, 超过时拆成多个事件
+    max_append_bytes: usize,
+    // 与读取任务使用同一个回调, 发送时调用 on_send
+    callback: Option<CallbackDispatch>,
+    // 服务端回显文本拼成的字幕
+    caption: CaptionAccumulator,
+    // 发送 session.finish 的时间
+    finish_sent: Option<Instant>,
+    // 收到 session.finished 的时间, 之后 finished_grace 内到达的音频仍然分发
+    finished_at: Option<Instant>,
+    finished_grace: Duration,
+    // 已经追加结尾音频并结束所有订阅
+    audio_finalized: bool,
+    // finish 之后超过这个时间没有收到音频时中止会话
+    finish_audio_timeout: Option<Duration>,
+    // 音频总字节数上限, 超过时中止会话
+    max_audio_bytes: Option<usize>,
+    audio_limit_exceeded: bool,
+    // 解码后音频的帧对齐, 对齐器在确认格式后的第一个分片时创建
+    frame_alignment: Option<FrameAlignment>,
+    frame_aligner: Option<FrameAligner>,
+    // 开启自动重连时的握手参数和重试策略
+    reconnect: Option<ReconnectTarget>,
+    // 最近一次 response.done 之后发送的 append/commit/finish, 重连后按顺序重放
+    unacked: Vec<Value>,
+    // cancel() 通知读取任务立即退出
+    cancel: CancellationToken,
+    // 句柄被丢弃时触发, 心跳等后台任务随之退出, 还没有 finish 的会话同时关闭连接
+    shutdown: CancellationToken,
+    // pause() 期间暂存的音频分片, resume() 后按顺序分发
+    paused: bool,
+    paused_audio: VecDeque<AudioDispatch>,
+    // resume() 通知读取任务分发暂存的分片
+    resumed: Arc<Notify>,
+    // 最近一次发出心跳 ping 的时间, 收到 pong 时计算往返时间
+    ping_sent: Option<Instant>,
+    // 设置了 audit_sink 时记录生命周期审计
+    audit: Option<Auditor>,
 }
 
 pub struct QwenTtsRealtime {
-    stream_writer: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
+    connection_id: String,
+    stream_writer: WsWriter,
+    shared: Arc<std::sync::Mutex<SessionShared>>,
+    delivery_policy: DeliveryPolicy,
+    overflow_policy: OverflowPolicy,
+    audio_buffer_capacity: usize,
+    events: Option<mpsc::UnboundedReceiver<ServerEvent>>,
+    effective: EffectiveConfig,
+    // AfterFinishPolicy::NewSession 时用于建立下一个会话
+    next_session: Option<Box<QwenTtsRealtimeBuilder>>,
+    // 与读取任务共享, cancel() 时触发
+    cancel: CancellationToken,
+    // 与后台任务共享, drop 时触发
+    shutdown: CancellationToken,
+    // append_text_deferred 暂存的文本, commit/finish 时合并成一次 append 发送
+    deferred_text: String,
+}
+
+///
+/// 句柄被丢弃(包括出错时的提前返回)后停止心跳, 避免无人使用的连接一直保活;
+/// 还没有 finish 的会话同时关闭连接, 已经 finish 的会话由读取任务收完剩余音频
+impl Drop for QwenTtsRealtime {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
 }
 
 impl QwenTtsRealtime {
+    /// 以 builder 的方式配置并建立连接, 等同于 QwenTtsRealtimeBuilder::from_env()
+    pub fn builder() -> QwenTtsRealtimeBuilder {
+        QwenTtsRealtimeBuilder::from_env()
+    }
+
+    ///
+    /// 按环境变量连接默认模型: API Key 读取 DASHSCOPE_API_KEY, workspace 读取 DASHSCOPE_WORKSPACE_ID,
+    /// url 读取 DASHSCOPE_REALTIME_URL, 取值会去掉首尾空白; 未设置API Key时返回 MissingApiKey, 不会panic
+    pub async fn from_env(callback: Option<SharedCallback>) -> Result<Self, QwenTtsError> {
+        let mut builder = QwenTtsRealtimeBuilder::from_env();
+        if let Some(callback) = callback {
+            builder = builder.callback(callback);
+        }
+        builder.connect().await
+    }
+
+    ///
+    /// 批处理/离线场景不需要回调时使用: 按环境变量建连(同 from_env), 发送全部文本后 finish,
+    /// 收到 session.finished 时返回全部解码音频; 超过 DEFAULT_SYNTHESIZE_ALL_TIMEOUT 返回 Timeout,
+    /// 需要自定义超时或保留出错前的部分音频时用 synthesize_to_vec_with_timeout
+    pub async fn synthesize_all(
+        config: &SessionConfig,
+        texts: &[&str],
+    ) -> Result<Vec<u8>, QwenTtsError> {
+        let builder = QwenTtsRealtimeBuilder::from_env();
+        synthesize_to_vec_with_timeout(&builder, config, texts, DEFAULT_SYNTHESIZE_ALL_TIMEOUT)
+            .await
+            .into_result()
+    }
+
     ///
     /// 与服务器建立连接，链接成功后需要update_session
+    ///
+    /// url 支持 wss:// 和 ws://, 私有化部署的网关可以直接使用 ws://host:port/path,
+    /// 此时不走TLS, 鉴权等请求头与wss完全一致
     pub async fn new(
         model_name: &str,
         api_key: &str,
         url: Option<&str>,
         workspace: Option<&str>,
-        callback: Option<Arc<Mutex<Box<dyn QwenTtsRealtimeCallback + Sync + Send>>>>,
-    ) -> Self {
-        let url = if let Some(url) = url {
-            format!("{}?model={}", url, model_name)
+        callback: Option<SharedCallback>,
+    ) -> Result<Self, QwenTtsError> {
+        Self::try_connect(
+            model_name,
+            api_key,
+            url,
+            workspace,
+            callback.map(CallbackDispatch::Sync),
+            ConnectOptions::default(),
+        )
+        .await
+    }
+
+    ///
+    /// 不需要回调的建连方式, 同时返回解码后音频的通道: 每个 response.audio.delta 解码后发送一次,
+    /// 收到 session.finished(或连接异常结束)后通道关闭, recv() 返回None; 通道有界, 消费跟不上时读取任务会等待
+    pub async fn new_with_channel(
+        model_name: &str,
+        api_key: &str,
+        url: Option<&str>,
+        workspace: Option<&str>,
+    ) -> Result<(Self, mpsc::Receiver<Vec<u8>>), QwenTtsError> {
+        let qwen_tts_realtime = Self::new(model_name, api_key, url, workspace, None).await?;
+        let mut audio_stream = Box::pin(qwen_tts_realtime.audio_stream());
+        let (tx, rx) = mpsc::channel(qwen_tts_realtime.audio_buffer_capacity);
+        tokio::spawn(async move {
+            while let Some(audio_bytes) = audio_stream.next().await {
+                if tx.send(audio_bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok((qwen_tts_realtime, rx))
+    }
+
+    pub(crate) async fn try_connect(
+        model_name: &str,
+        api_key: &str,
+        url: Option<&str>,
+        workspace: Option<&str>,
+        callback: Option<CallbackDispatch>,
+        options: ConnectOptions,
+    ) -> Result<Self, QwenTtsError> {
+        let effective = EffectiveConfig::resolve(
+            model_name,
+            Some((api_key, ValueSource::Param)),
+            url.map(|url| (url, ValueSource::Param)),
+            workspace,
+            options.api_version.as_deref(),
+        );
+        let url = Self::build_url(url, model_name, options.api_version.as_deref())?;
+        let request =
+            Self::build_request(&url, api_key, workspace, options.minimal_user_agent)?;
+        Self::resolve_host(&request, options.dns_retry.as_ref()).await?;
+        let connect_timeout = options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let (stream, response) = Self::connect_with_timeout(request, connect_timeout).await?;
+        log::info!("服务器响应状态码: {}", response.status());
+        response.headers().into_iter().for_each(|(name, value)| {
+            log::info!("响应头: {}: {:?}", name, value);
+        });
+
+        let (stream_writer, stream_reader) = stream.split();
+        let stream_writer = Arc::new(Mutex::new(stream_writer));
+        let mut session_shared = SessionShared {
+            max_append_bytes: DEFAULT_MAX_APPEND_BYTES,
+            finished_grace: DEFAULT_FINISHED_GRACE,
+            callback: callback.clone(),
+            ..SessionShared::default()
+        };
+        // 在读取任务启动前创建事件队列, 保证不会漏掉 session.created
+        let events = if options.pull_events {
+            let (tx, rx) = mpsc::unbounded_channel();
+            session_shared.events = Some(tx);
+            Some(rx)
         } else {
-            format!(
-                "wss://dashscope.aliyuncs.com/api-ws/v1/realtime?model={}",
-                model_name
-            )
+            None
         };
-        let ua = format!(
-            "dashscope/1.18.0; rust/{};\
-        platform/{}\
-        processor/{}",
-            option_env!("RUSTC_VERSION").unwrap_or("Unknown Rustc Version"),
-            std::env::consts::OS,
-            std::env::consts::ARCH,
+        let cancel = session_shared.cancel.clone();
+        let shutdown = session_shared.shutdown.clone();
+        let shared = Arc::new(std::sync::Mutex::new(session_shared));
+        if let Some(callback) = &callback {
+            callback.on_open().await;
+        }
+        let connection_id = Uuid::new_v4().to_string();
+        log::info!("连接建立, connection_id: {}", connection_id);
+        // 无论有没有回调都需要读取任务, 用于维护会话状态
+        Self::spawn_reader(
+            &connection_id,
+            Self::run_reader(
+                stream_reader,
+                Arc::clone(&stream_writer),
+                callback,
+                Arc::clone(&shared),
+            ),
         );
+        let heartbeat_interval = options.heartbeat_interval.unwrap_or(DEFAULT_HEARTBEAT_INTERVAL);
+        if !heartbeat_interval.is_zero() {
+            tokio::spawn(Self::heartbeat(
+                Arc::clone(&stream_writer),
+                Arc::clone(&shared),
+                heartbeat_interval,
+            ));
+        }
+        Ok(Self {
+            connection_id,
+            stream_writer,
+            shared,
+            delivery_policy: DeliveryPolicy::default(),
+            overflow_policy: OverflowPolicy::default(),
+            audio_buffer_capacity: DEFAULT_AUDIO_BUFFER_CAPACITY,
+            events,
+            effective,
+            next_session: None,
+            cancel,
+            shutdown,
+            deferred_text: String::new(),
+        })
+    }
 
-        let mut request = url.as_str().into_client_request().unwrap();
+    /// 握手请求: 鉴权、workspace 和 User-Agent 请求头
+    pub(crate) fn build_request(
+        url: &str,
+        api_key: &str,
+        workspace: Option<&str>,
+        minimal_user_agent: bool,
+    ) -> Result<Request, QwenTtsError> {
+        let ua = Self::build_user_agent(minimal_user_agent);
+        let mut request = url
+            .into_client_request()
+            .map_err(|e| QwenTtsError::InvalidUrl(format!("{}: {}", url, e)))?;
+        request.headers_mut().insert("user-agent", ua.parse()?);
         request
             .headers_mut()
-            .insert("user-agent", ua.parse().unwrap());
-        request.headers_mut().insert(
-            "Authorization",
-            format!("bearer {}", api_key).parse().unwrap(),
-        );
+            .insert("Authorization", format!("bearer {}", api_key).parse()?);
         if let Some(workspace) = workspace {
             request
                 .headers_mut()
-                .insert("X-DashScope-WorkSpace", workspace.parse().unwrap());
+                .insert("X-DashScope-WorkSpace", workspace.parse()?);
         }
-        let (stream, response) = connect_async(request).await.expect("Failed to connect");
-        log::info!("服务器响应状态码: {}", response.status());
-        response.headers().into_iter().for_each(|(name, value)| {
-            log::info!("响应头: {}: {:?}", name, value);
-        });
+        Ok(request)
+    }
 
-        let (stream_writer, mut stream_reader) = stream.split();
-        // 有回调时这里异步任务循环维持连接， 没有回调时，这个函数结束stream就自动close了
-        if let Some(callback) = callback {
-            callback.lock().await.as_ref().on_open();
-            tokio::spawn(async move {
-                let callback_clone = Arc::clone(&callback);
-                while let Some(message) = stream_reader.next().await {
-                    match message {
-                        Ok(msg) => {
-                            if msg.is_text() {
-                                log::info!("text message: {:?}", msg);
-                                let need_aborted = callback_clone
-                                    .lock()
-                                    .await
-                                    .as_mut()
-                                    .on_event(msg.to_text().unwrap());
-                                if need_aborted {
-                                    break;
-                                }
-                            } else if msg.is_close() {
-                                log::info!("close: {:?}", msg);
-                                callback_clone
-                                    .lock()
-                                    .await
-                                    .as_ref()
-                                    .on_close("Connection closed by server");
-                                break;
-                            } else {
-                                log::info!("other message: {:?}", msg);
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Error receiving message: {}", e);
-                            break;
-                        }
-                    }
-                }
-                log::info!("reader task ended");
-                callback_clone
-                    .lock()
-                    .await
-                    .as_mut()
-                    .on_finish("reader task ended");
-            });
+    ///
+    /// 在url上设置 model(和 api_version)查询参数: 保留url中已有的其他查询参数, 同名参数被覆盖,
+    /// 去掉路径末尾多余的 /; 只接受 ws/wss, url 不合法时返回 InvalidUrl
+    fn build_url(
+        url: Option<&str>,
+        model_name: &str,
+        api_version: Option<&str>,
+    ) -> Result<String, QwenTtsError> {
+        let raw = url.unwrap_or(DEFAULT_REALTIME_URL);
+        let mut url =
+            url::Url::parse(raw).map_err(|e| QwenTtsError::InvalidUrl(format!("{}: {}", raw, e)))?;
+        if !matches!(url.scheme(), "ws" | "wss") {
+            return Err(QwenTtsError::InvalidUrl(format!("{}: 只支持 ws:// 或 wss://", raw)));
+        }
+        if url.path().len() > 1 && url.path().ends_with('/') {
+            let path = url.path().trim_end_matches('/').to_string();
+            url.set_path(&path);
+        }
+        let extra = url
+            .query_pairs()
+            .filter(|(key, _)| key != "model" && key != "api_version")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        {
+            let mut query = url.query_pairs_mut();
+            query.clear().append_pair("model", model_name);
+            if let Some(api_version) = api_version {
+                query.append_pair("api_version", api_version);
+            }
+            query.extend_pairs(extra);
         }
-        Self { stream_writer }
+        Ok(url.to_string())
     }
 
-    fn _generate_event_id(&self) -> String {
-        format!("event_{}", Uuid::new_v4().to_string())
+    fn reader_task_name(connection_id: &str) -> String {
+        format!("qwen-tts-reader-{}", connection_id)
     }
 
-    /// 建立连接成功后，需要添加session conf
-    pub async fn update_session(
-        &mut self,
-        voice: &str,
-        response_format: AudioFormat<'_>,
-        mode: &str,
-    ) -> Result<(), Error> {
-        let config = json!({
-            "voice":voice,
-            "mode":mode,
-            "response_format":response_format.format,
-            "sample_rate":response_format.sample_rate,
-        });
-        let msg = json!({
-            "event_id": self._generate_event_id(),
-            "type": "session.update",
-            "session": config,
-        });
-        self.stream_writer
-            .send(Message::text(msg.to_string()))
-            .await?;
-        log::info!("send: {}", msg);
-        Ok(())
+    /// 开启 tokio-console feature 并以 tokio_unstable 编译时给读取任务命名,
+    /// 便于在 tokio-console 中按 connection_id 定位; 否则是普通的匿名任务
+    fn spawn_reader(
+        connection_id: &str,
+        reader: impl std::future::Future<Output = ()> + Send + 'static,
+    ) {
+        let task_name = Self::reader_task_name(connection_id);
+        #[cfg(all(tokio_unstable, feature = "tokio-console"))]
+        {
+            if let Err(e) = tokio::task::Builder::new().name(&task_name).spawn(reader) {
+                log::error!("启动读取任务 {} 失败: {}", task_name, e);
+            }
+        }
+        #[cfg(not(all(tokio_unstable, feature = "tokio-console")))]
+        {
+            log::debug!("启动读取任务 {}", task_name);
+            tokio::spawn(reader);
+        }
     }
 
-    pub async fn append_text(&mut self, text: &str) -> Result<(), Error> {
-        let msg = json!({
-                                "event_id": self._generate_event_id(),
-                    "type": "input_text_buffer.append",
-                    "text": text,
-        });
-        self.stream_writer
-            .send(Message::text(msg.to_string()))
-            .await?;
-        Ok(())
+    /// 实际生效的配置, 会话配置取最近一次 update_session 的值
+    pub fn effective_config(&self) -> EffectiveConfig {
+        let mut effective = self.effective.clone();
+        if let Some(config) = &self.shared.lock().unwrap().session_config {
+            effective.voice = Some(config.voice.clone());
+            effective.response_format = Some(config.response_format);
+            effective.mode = Some(config.mode.clone());
+        }
+        effective
     }
 
-    pub async fn finish(&mut self) -> Result<(), Error> {
-        let msg = json!({
-            "event_id": self._generate_event_id(),
-            "type": "session.finish"
-        });
-        self.stream_writer
-            .send(Message::text(msg.to_string()))
-            .await?;
-        Ok(())
+    ///
+    /// 到目前为止服务端回显的文本拼成的字幕, 与已收到的音频同步, 用于实时字幕/无障碍场景;
+    /// 服务端没有回显文本时为空字符串
+    pub fn caption(&self) -> String {
+        self.shared.lock().unwrap().caption.text().to_string()
+    }
+
+    /// 本次连接的标识, 与日志和读取任务名中的 connection_id 一致
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
+
+    /// minimal_user_agent 为true时只保留 dashscope/rust 版本, 省略 platform/processor 段
+    fn build_user_agent(minimal_user_agent: bool) -> String {
+        let rust_version = option_env!("RUSTC_VERSION").unwrap_or("Unknown Rustc Version");
+        if minimal_user_agent {
+            return format!("dashscope/1.18.0; rust/{}", rust_version);
+        }
+        format!(
+            "dashscope/1.18.0; rust/{}; platform/{}; processor/{}",
+            rust_version,
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+        )
+    }
+
+    ///
+    /// 建连前先解析主机名, 把DNS失败从连接被拒绝等IO错误中区分出来, 返回 DnsFailure;
+    /// retry 不为None时按其退避重试解析, 容器中DNS偶尔抖动的情况可以自行恢复
+    async fn resolve_host(
+        request: &Request,
+        retry: Option<&ReconnectPolicy>,
+    ) -> Result<(), QwenTtsError> {
+        let uri = request.uri();
+        // build_request 已经校验过url, 这里取不到主机名时交给 connect_async 报错
+        let Some(host) = uri.host().map(|host| host.trim_matches(['[', ']'])) else {
+            return Ok(());
+        };
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("wss") { 443 } else { 80 });
+        // 第一次解析不算重试
+        let max_attempts = retry.map_or(1, |policy| policy.max_attempts() + 1);
+        let mut attempt = 1;
+        let mut previous_delay = Duration::ZERO;
+        loop {
+            let reason = match tokio::net::lookup_host((host, port)).await {
+                Ok(mut addrs) => match addrs.next() {
+                    Some(_) => return Ok(()),
+                    None => "没有解析到地址".to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            let error = QwenTtsError::DnsFailure {
+                host: host.to_string(),
+                reason,
+            };
+            let Some(policy) = retry.filter(|_| attempt < max_attempts) else {
+                log::error!("{}", error);
+                return Err(error);
+            };
+            let delay = policy.delay_with_previous(attempt, &error, previous_delay);
+            previous_delay = delay;
+            log::warn!("第 {}/{} 次{}, {:?} 后重试", attempt, max_attempts, error, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// 建连超过timeout时返回 Timeout, 其余错误按 handshake_error 转换
+    async fn connect_with_timeout(
+        request: Request,
+        timeout: Duration,
+    ) -> Result<(WsStream, Response), QwenTtsError> {
+        match tokio::time::timeout(timeout, connect_async(request)).await {
+            Ok(connected) => connected.map_err(Self::handshake_error),
+            Err(_) => Err(QwenTtsError::Timeout(format!("{:?} 内没有完成建连", timeout))),
+        }
+    }
+
+    /// 握手失败时把401/403转换为鉴权错误, 429/503转换为带Retry-After的限流错误,
+    /// 其他非101状态码转换为 HandshakeRejected, TLS错误转换为 Tls
+    fn handshake_error(e: tungstenite::Error) -> QwenTtsError {
+        if let tungstenite::Error::Tls(tls_error) = &e {
+            return QwenTtsError::Tls(tls_error.to_string());
+        }
+        if let tungstenite::Error::Http(response) = &e {
+            log::error!("握手失败, 服务器响应状态码: {}", response.status());
+            response.headers().iter().for_each(|(name, value)| {
+                log::error!("响应头: {}: {:?}", name, value);
+            });
+            let status = response.status().as_u16();
+            // 服务端在响应体中给出具体的错误码和原因
+            let body = response
+                .body()
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default()
+                .to_string();
+            if status == 401 || status == 403 {
+                return QwenTtsError::Unauthorized { status, body };
+            }
+            if status == 429 || status == 503 {
+                let retry_after = response
+                    .headers()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after);
+                return QwenTtsError::RateLimited {
+                    status,
+                    retry_after,
+                };
+            }
+            return QwenTtsError::HandshakeRejected { status, body };
+        }
+        QwenTtsError::WebSocketError(e)
+    }
+
+    ///
+    /// 读取任务入口: read_loop 中的panic会被捕获并当作读取错误处理,
+    /// 保证订阅者、recv() 和回调都能感知到连接结束, 而不是静默停止
+    async fn run_reader(
+        mut stream_reader: SplitStream<WsStream>,
+        stream_writer: WsWriter,
+        callback: Option<CallbackDispatch>,
+        shared: Arc<std::sync::Mutex<SessionShared>>,
+    ) {
+        let mut close_info = loop {
+            let reader = Self::read_loop(
+                stream_reader,
+                Arc::clone(&stream_writer),
+                callback.clone(),
+                Arc::clone(&shared),
+            );
+            let close_info = match AssertUnwindSafe(reader).catch_unwind().await {
+                Ok(close_info) => close_info,
+                Err(panic) => break Self::reader_panicked(panic, &callback, &shared).await,
+            };
+            match Self::reconnect(&close_info, &stream_writer, &callback, &shared).await {
+                Some(next_reader) => stream_reader = next_reader,
+                None => break close_info,
+            }
+        };
+        log::info!("reader task ended");
+        let pending = {
+            let mut shared = shared.lock().unwrap();
+            shared.reader_finished = true;
+            // 宽限期内连接就已经关闭
+            if shared.session_finished {
+                let audio = Self::finalize_audio(&mut shared);
+                shared.paused_audio.extend(audio);
+            }
+            shared.audio_streams.clear();
+            shared.audio_streams_base64.clear();
+            shared.audio_broadcast = None;
+            shared.session_updated = None;
+            close_info.session_finished = shared.session_finished;
+            close_info.reconnect_hint |= shared.reconnect_hint;
+            shared.close_info = Some(close_info.clone());
+            shared.state.send_replace(match &close_info.error {
+                Some(error) => SessionState::Failed(error.clone()),
+                None => SessionState::Closed,
+            });
+            if let Some(audit) = &shared.audit {
+                audit.record(AuditAction::Closed {
+                    session_finished: close_info.session_finished,
+                    close_code: close_info.close_code,
+                    error: close_info.error.clone(),
+                });
+            }
+            if let Some(events) = shared.events.take() {
+                let _ = events.send(ServerEvent::Closed(close_info));
+            }
+            // 仍在暂停时由 resume() 分发
+            if shared.paused {
+                VecDeque::new()
+            } else {
+                std::mem::take(&mut shared.paused_audio)
+            }
+        };
+        for audio_dispatch in pending {
+            Self::dispatch_audio(audio_dispatch, &shared).await;
+        }
+        if let Some(callback) = &callback {
+            callback.on_finish("reader task ended").await;
+        }
+    }
+
+    async fn reader_panicked(
+        panic: Box<dyn std::any::Any + Send>,
+        callback: &Option<CallbackDispatch>,
+        shared: &std::sync::Mutex<SessionShared>,
+    ) -> CloseInfo {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        let error = format!("读取任务panic: {}", message);
+        log::error!("{}", error);
+        // panic时可能正持有锁, 清除中毒标记以便后续继续使用共享状态
+        shared.clear_poison();
+        if let Some(callback) = callback {
+            callback.on_error(&error).await;
+        }
+        CloseInfo {
+            error: Some(error),
+            ..CloseInfo::default()
+        }
+    }
+
+    ///
+    /// 连接在 session.finished 之前异常断开(读取错误、无关闭帧断开、非1000的关闭码)时,
+    /// 按 with_reconnect 的策略重新建连, 成功后重发最近一次的 session.update 并重放尚未确认的事件,
+    /// 返回新连接的读取端; 未开启重连、正常结束、被回调或 max_audio_bytes 中止时返回None.
+    /// 重连期间持有写入端的锁, 其间的发送会等待重连结束后发往新连接; 每次尝试前调用 on_reconnect
+    async fn reconnect(
+        close_info: &CloseInfo,
+        stream_writer: &WsWriter,
+        callback: &Option<CallbackDispatch>,
+        shared: &std::sync::Mutex<SessionShared>,
+    ) -> Option<SplitStream<WsStream>> {
+        let target = {
+            let shared = shared.lock().unwrap();
+            let unexpected = close_info.error.is_some()
+                || close_info.close_code.is_some_and(|code| code != 1000);
+            if !unexpected || shared.session_finished || shared.audio_limit_exceeded {
+                return None;
+            }
+            shared.reconnect.clone()?
+        };
+        let mut writer = stream_writer.lock().await;
+        let max_attempts = target.policy.max_attempts();
+        let mut attempt = 0;
+        let mut previous_delay = Duration::ZERO;
+        let stream = loop {
+            attempt += 1;
+            log::info!("连接异常断开, 第{}次重连", attempt);
+            if let Some(callback) = callback {
+                callback.on_reconnect(attempt).await;
+            }
+            let connected = match Self::build_request(
+                &target.url,
+                &target.api_key,
+                target.workspace.as_deref(),
+                target.minimal_user_agent,
+            ) {
+                Ok(request) => Self::connect_with_timeout(request, target.connect_timeout).await,
+                Err(e) => Err(e),
+            };
+            match connected {
+                Ok((stream, _)) => break stream,
+                Err(e) if attempt >= max_attempts => {
+                    log::error!("重连{}次均失败, 放弃: {}", attempt, e);
+                    return None;
+                }
+                Err(e) => {
+                    log::warn!("第{}次重连失败: {}", attempt, e);
+                    previous_delay = target.policy.delay_with_previous(attempt, &e, previous_delay);
+                    tokio::time::sleep(previous_delay).await;
+                }
+            }
+        };
+        let (next_writer, next_reader) = stream.split();
+        *writer = next_writer;
+        let replay = {
+            let shared = shared.lock().unwrap();
+            shared
+                .session_config
+                .iter()
+                .map(|config| {
+                    build_session_update_from(&format!("event_{}", Uuid::new_v4()), config)
+                })
+                .chain(shared.unacked.iter().cloned())
+                .collect::<Vec<_>>()
+        };
+        log::info!("重连成功, 重放 {} 个事件", replay.len());
+        for event in replay {
+            let sent = match Self::encode_event(shared, event).await {
+                Ok(frame) => writer.send(Message::text(frame)).await.map_err(Self::send_error),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = sent {
+                log::error!("重放事件失败: {}", e);
+                break;
+            }
+        }
+        shared.lock().unwrap().last_sent = Some(Instant::now());
+        Some(next_reader)
+    }
+
+    async fn read_loop(
+        mut stream_reader: SplitStream<WsStream>,
+        stream_writer: WsWriter,
+        callback: Option<CallbackDispatch>,
+        shared: Arc<std::sync::Mutex<SessionShared>>,
+    ) -> CloseInfo {
+        let mut close_info = CloseInfo::default();
+        let mut closed = false;
+        // 回调要求结束时仍在 session.finished 的宽限期内, 宽限期结束后再退出
+        let mut abort_after_grace = false;
+        let (cancel, shutdown, resumed) = {
+            let shared = shared.lock().unwrap();
+            (shared.cancel.clone(), shared.shutdown.clone(), Arc::clone(&shared.resumed))
+        };
+        loop {
+            // 已经 finish 的会话在句柄丢弃后继续接收剩余音频, 由服务端关闭连接
+            let finish_sent = shared.lock().unwrap().finish_sent.is_some();
+            let message = tokio::select! {
+                message = stream_reader.next() => message,
+                _ = resumed.notified() => {
+                    Self::deliver_audio(None, &shared).await;
+                    continue;
+                }
+                error = Self::finish_audio_watchdog(&shared) => {
+                    log::error!("{}", error);
+                    closed = true;
+                    let _ = stream_writer.lock().await.send(Message::Close(None)).await;
+                    if let Some(callback) = &callback {
+                        callback.on_error(&error).await;
+                    }
+                    close_info.error = Some(error);
+                    break;
+                }
+                _ = Self::finished_grace_elapsed(&shared) => {
+                    let audio = Self::finalize_audio(&mut shared.lock().unwrap());
+                    Self::deliver_audio(audio, &shared).await;
+                    if abort_after_grace {
+                        closed = true;
+                        close_info.close_reason = "aborted by callback".to_string();
+                        break;
+                    }
+                    continue;
+                }
+                _ = cancel.cancelled() => {
+                    log::info!("会话被取消, 关闭连接");
+                    closed = true;
+                    close_info.close_reason = "cancelled by client".to_string();
+                    let _ = stream_writer.lock().await.send(Message::Close(None)).await;
+                    if let Some(callback) = &callback {
+                        callback.on_close("Connection cancelled by client").await;
+                    }
+                    break;
+                }
+                _ = shutdown.cancelled(), if !finish_sent => {
+                    // 等待期间才发出的 finish, 下一轮循环会关闭这个分支
+                    if shared.lock().unwrap().finish_sent.is_some() {
+                        continue;
+                    }
+                    log::info!("句柄已丢弃且没有 finish, 关闭连接");
+                    closed = true;
+                    close_info.close_reason = "handle dropped".to_string();
+                    let _ = stream_writer.lock().await.send(Message::Close(None)).await;
+                    if let Some(callback) = &callback {
+                        callback.on_close("Connection closed: handle dropped").await;
+                    }
+                    break;
+                }
+            };
+            let Some(message) = message else {
+                break;
+            };
+            match message {
+                Ok(msg) => {
+                    if msg.is_text() {
+                        log::info!("text message: {:?}", msg);
+                        let text = match msg.to_text() {
+                            Ok(text) => text,
+                            Err(e) => {
+                                let error = format!("文本帧解码失败: {}", e);
+                                log::error!("{}", error);
+                                if let Some(callback) = &callback {
+                                    callback.on_error(&error).await;
+                                }
+                                continue;
+                            }
+                        };
+                        let effects = Self::track_event(text, &shared);
+                        if let Some(error) = effects.abort {
+                            log::error!("{}", error);
+                            closed = true;
+                            let _ = stream_writer.lock().await.send(Message::Close(None)).await;
+                            if let Some(callback) = &callback {
+                                callback.on_error(&error).await;
+                            }
+                            close_info.error = Some(error);
+                            break;
+                        }
+                        let events = shared.lock().unwrap().events.clone();
+                        if let Some(events) = events
+                            && let Ok(v) = serde_json::from_str::<Value>(text)
+                        {
+                            let _ = events.send(ServerEvent::Event(v));
+                        }
+                        if effects.audio.is_some() {
+                            Self::deliver_audio(effects.audio, &shared).await;
+                        }
+                        if let Some(config) = &effects.config_changed
+                            && let Some(callback) = &callback
+                        {
+                            callback.on_config_changed(config).await;
+                        }
+                        for retry_event in effects.retry {
+                            if let Err(e) =
+                                Self::send_via(&stream_writer, &shared, retry_event).await
+                            {
+                                log::error!("响应级重试发送失败: {}", e);
+                                break;
+                            }
+                        }
+                        // 回调在 session.finished 时要求结束后不再收到事件, 宽限期内只分发音频
+                        if let Some(callback) = callback.as_ref().filter(|_| !abort_after_grace) {
+                            for mark in TimestampMark::parse_all(text) {
+                                callback.on_timestamp(mark).await;
+                            }
+                            let need_aborted = callback.on_event(text).await;
+                            let in_grace = {
+                                let shared = shared.lock().unwrap();
+                                shared.session_finished && !shared.audio_finalized
+                            };
+                            if need_aborted && in_grace {
+                                abort_after_grace = true;
+                            } else if need_aborted {
+                                closed = true;
+                                close_info.close_reason = "aborted by callback".to_string();
+                                break;
+                            }
+                        }
+                    } else if msg.is_close() {
+                        log::info!("close: {:?}", msg);
+                        closed = true;
+                        if let Message::Close(Some(frame)) = &msg {
+                            close_info.close_code = Some(frame.code.into());
+                            close_info.close_reason = frame.reason.to_string();
+                            let reason = serde_json::from_str::<Value>(&frame.reason)
+                                .unwrap_or(Value::Null);
+                            close_info.reconnect_hint |=
+                                reconnect_hint(close_info.close_code, &reason);
+                        }
+                        if let Some(callback) = &callback {
+                            callback.on_close("Connection closed by server").await;
+                        }
+                        break;
+                    } else if msg.is_ping() {
+                        // pong 由 tungstenite 在下一次读写时自动回复
+                        log::debug!("收到服务端 ping");
+                    } else if msg.is_pong() {
+                        if let Some(sent) = shared.lock().unwrap().ping_sent.take() {
+                            log::debug!("收到 pong, 往返 {:?}", sent.elapsed());
+                        }
+                    } else {
+                        log::info!("other message: {:?}", msg);
+                    }
+                }
+                Err(e) => {
+                    log::error!("Error receiving message: {}", e);
+                    closed = true;
+                    close_info.error = Some(e.to_string());
+                    if let Some(callback) = &callback {
+                        callback.on_error(&e.to_string()).await;
+                    }
+                    break;
+                }
+            }
+        }
+        if !closed {
+            let error = "连接在没有关闭帧的情况下断开".to_string();
+            log::error!("{}", error);
+            if let Some(callback) = &callback {
+                callback.on_error(&error).await;
+            }
+            close_info.error = Some(error);
+        }
+        close_info
+    }
+
+    ///
+    /// finish 之后超过 finish_audio_timeout 没有收到音频(从发送 finish 或最近一个音频分片算起)时返回错误信息;
+    /// 未设置超时、会话已经结束时一直等待. 每次读取到消息后重新创建, 截止时间由共享状态计算
+    async fn finish_audio_watchdog(shared: &std::sync::Mutex<SessionShared>) -> String {
+        let Some(timeout) = shared.lock().unwrap().finish_audio_timeout else {
+            return std::future::pending().await;
+        };
+        loop {
+            let deadline = {
+                let shared = shared.lock().unwrap();
+                if shared.session_finished {
+                    None
+                } else {
+                    // 还没有 finish 时过一个timeout再检查
+                    Some(shared.finish_sent.map_or(Instant::now(), |sent| {
+                        shared.jitter.last().map_or(sent, |last| last.max(sent))
+                    }) + timeout)
+                }
+            };
+            let Some(deadline) = deadline else {
+                return std::future::pending().await;
+            };
+            tokio::time::sleep_until(deadline.into()).await;
+            let shared = shared.lock().unwrap();
+            let last_active = shared
+                .finish_sent
+                .map(|sent| shared.jitter.last().map_or(sent, |last| last.max(sent)));
+            if !shared.session_finished
+                && last_active.is_some_and(|last| last + timeout <= Instant::now())
+            {
+                return format!("finish 之后 {:?} 内没有收到音频, 中止会话", timeout);
+            }
+        }
+    }
+
+    /// 根据收到的事件更新共享的会话状态,
+    /// 返回需要响应级重试时要重新发送的事件、需要分发给订阅者的音频以及会话配置的变化
+    fn track_event(text: &str, shared: &std::sync::Mutex<SessionShared>) -> EventEffects {
+        let Ok(v) = serde_json::from_str::<Value>(text) else {
+            return EventEffects::default();
+        };
+        if v["type"] == "response.done" {
+            let mut shared = shared.lock().unwrap();
+            shared.caption.end_response();
+            // response.done 之前发送的文本视为已确认, 只有 session.finish 仍需重放
+            shared.unacked.retain(|event| event["type"] == "session.finish");
+            return EventEffects {
+                retry: Self::response_retry(&v, &mut shared),
+                ..EventEffects::default()
+            };
+        }
+        let event_type = v["type"].as_str().unwrap_or_default();
+        if CAPTION_DELTA_EVENTS.contains(&event_type)
+            && let Some(delta) = v["delta"].as_str()
+        {
+            shared.lock().unwrap().caption.push_delta(delta);
+            return EventEffects::default();
+        }
+        if CAPTION_DONE_EVENTS.contains(&event_type) {
+            let full_text = v["text"].as_str().or(v["transcript"].as_str());
+            if let Some(full_text) = full_text {
+                shared.lock().unwrap().caption.replace_response(full_text);
+            }
+            return EventEffects::default();
+        }
+        if reconnect_hint(None, &v) {
+            log::info!("服务端提示重连: {}", text);
+            shared.lock().unwrap().reconnect_hint = true;
+        }
+        if v["type"] == "session.finished" {
+            let mut shared = shared.lock().unwrap();
+            shared.session_finished = true;
+            shared.finished_at = Some(Instant::now());
+            // 有宽限期时由读取任务在宽限期结束后收尾
+            if !shared.finished_grace.is_zero() {
+                return EventEffects::default();
+            }
+            return EventEffects {
+                audio: Self::finalize_audio(&mut shared),
+                ..EventEffects::default()
+            };
+        }
+        if (v["type"] == "session.updated" || v["type"] == "session.update")
+            && v["session"].is_object()
+        {
+            let mut shared = shared.lock().unwrap();
+            if let Some(waiter) = shared.session_updated.take() {
+                let _ = waiter.send(v["session"].clone());
+            }
+            return EventEffects {
+                config_changed: Self::reconcile_session(&v["session"], &mut shared),
+                ..EventEffects::default()
+            };
+        }
+        if v["type"] == "response.audio.delta" {
+            let mut shared = shared.lock().unwrap();
+            shared.jitter.record(Instant::now());
+            shared.delta_count += 1;
+            if let Some(delta) = v["delta"].as_str() {
+                // base64解码后的长度, 不需要真正解码
+                let padding = delta.bytes().rev().take_while(|b| *b == b'=').count();
+                let bytes = (delta.len() / 4 * 3).saturating_sub(padding) as u64;
+                // 超过上限的分片整个丢弃, 之前的音频已经分发, 保持完整
+                if let Some(limit) = shared.max_audio_bytes
+                    && shared.audio_bytes + bytes > limit as u64
+                {
+                    shared.audio_limit_exceeded = true;
+                    return EventEffects {
+                        abort: Some(QwenTtsError::AudioSizeLimitExceeded { limit }.to_string()),
+                        ..EventEffects::default()
+                    };
+                }
+                shared.audio_bytes += bytes;
+            }
+            if shared.confirmed_format.is_none() {
+                // 未调用update_session时服务端使用默认格式
+                let requested = shared
+                    .requested_format
+                    .unwrap_or(AudioFormat::PCM_24000HZ_MONO_16BIT);
+                let (confirmed, mismatches) = reconcile_audio_format(requested, &v);
+                for mismatch in mismatches.iter() {
+                    log::warn!("音频格式与请求不一致, 以服务端为准: {}", mismatch);
+                }
+                shared.confirmed_format = Some(confirmed);
+            }
+            return EventEffects {
+                audio: Self::prepare_audio(&v, &mut shared),
+                ..EventEffects::default()
+            };
+        }
+        EventEffects::default()
+    }
+
+    /// session.finished 时的最后一个分片: 结尾静音, 加上帧对齐剩余的部分
+    ///
+    /// 追加结尾音频并结束所有订阅, 只执行一次; 之后不会再有音频, 不必等连接关闭.
+    /// 最后一个分片持有发送端的克隆, 发完即结束
+    fn finalize_audio(shared: &mut SessionShared) -> Option<AudioDispatch> {
+        if shared.audio_finalized {
+            return None;
+        }
+        shared.audio_finalized = true;
+        let audio = Self::final_audio(shared);
+        shared.audio_streams.clear();
+        shared.audio_streams_base64.clear();
+        shared.audio_broadcast = None;
+        audio
+    }
+
+    ///
+    /// 收到 session.finished 后等待 finished_grace, 期间到达的音频照常分发; 收尾之后一直等待
+    async fn finished_grace_elapsed(shared: &std::sync::Mutex<SessionShared>) {
+        let deadline = {
+            let shared = shared.lock().unwrap();
+            shared
+                .finished_at
+                .filter(|_| !shared.audio_finalized)
+                .map(|finished_at| finished_at + shared.finished_grace)
+        };
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    fn final_audio(shared: &mut SessionShared) -> Option<AudioDispatch> {
+        let mut dispatch = Self::trailing_silence(shared);
+        let Some(tail) = shared.frame_aligner.as_mut().and_then(FrameAligner::finish) else {
+            return dispatch;
+        };
+        match dispatch.as_mut() {
+            Some(dispatch) => dispatch.audio_bytes.get_or_insert_with(Vec::new).extend(tail),
+            // 对齐补齐的部分只发给解码后的订阅者, base64透传不经过对齐
+            None if Self::wants_decoded(shared) => {
+                dispatch = Some(AudioDispatch {
+                    delta: String::new(),
+                    audio_bytes: Some(tail),
+                    audio_streams: shared.audio_streams.clone(),
+                    audio_streams_base64: vec![],
+                    audio_broadcast: shared.audio_broadcast.clone(),
+                })
+            }
+            None => {}
+        }
+        dispatch
+    }
+
+    ///
+    /// 按实际格式生成结尾静音, 经由普通音频分片的路径分发; 只对PCM有效, 压缩格式无法直接拼接零样本
+    fn trailing_silence(shared: &mut SessionShared) -> Option<AudioDispatch> {
+        let duration = shared.trailing_silence?;
+        let format = shared
+            .confirmed_format
+            .or(shared.requested_format)
+            .unwrap_or(AudioFormat::PCM_24000HZ_MONO_16BIT);
+        if format.format() != "pcm" {
+            log::warn!("{} 格式不支持追加结尾静音, 已跳过", format.format());
+            return None;
+        }
+        let silence = vec![0u8; format.bytes_for_duration(duration)];
+        let delta = json!({"delta": base64::engine::general_purpose::STANDARD.encode(silence)});
+        Self::prepare_audio(&delta, shared)
+    }
+
+    ///
+    /// 以服务端返回/推送的 session 为准更新本地记录的配置和音频格式,
+    /// 保证WAV头和时长计算使用实际格式; 配置有变化时返回新配置
+    fn reconcile_session(session: &Value, shared: &mut SessionShared) -> Option<SessionConfig> {
+        let previous = shared.session_config.clone().unwrap_or_else(|| {
+            // 没有 update_session 过时以服务端默认配置为基准
+            SessionConfig::new(
+                "",
+                shared
+                    .requested_format
+                    .unwrap_or(AudioFormat::PCM_24000HZ_MONO_16BIT),
+                "server_commit",
+            )
+        });
+        let mut current = previous.clone();
+        current.merge_server_session(session);
+        if current == previous {
+            return None;
+        }
+        log::info!("服务端更新了会话配置: {:?} -> {:?}", previous, current);
+        if current.response_format != previous.response_format {
+            shared.requested_format = Some(current.response_format);
+            shared.confirmed_format = Some(current.response_format);
+        }
+        shared.session_config = Some(current.clone());
+        Some(current)
+    }
+
+    ///
+    /// response.done 的 status 为 failed 时, 在同一会话内重新提交最近一次的文本
+    ///
+    /// 只有开启了 response_retry、错误类型不是 invalid_request_error(参数错误重试也不会成功)、
+    /// 且该文本的重试次数未用完时才会重试; 收到成功的 response.done 后重试次数清零
+    fn response_retry(v: &Value, shared: &mut SessionShared) -> Vec<Value> {
+        let response = &v["response"];
+        if response["status"] != "failed" {
+            shared.response_retry_attempts = 0;
+            return vec![];
+        }
+        let error = &response["status_details"]["error"];
+        log::warn!("响应失败: {}", error);
+        if shared.response_retry_max == 0 || error["type"] == "invalid_request_error" {
+            return vec![];
+        }
+        if shared.response_retry_attempts >= shared.response_retry_max {
+            log::error!("响应级重试 {} 次后仍然失败, 放弃", shared.response_retry_attempts);
+            return vec![];
+        }
+        let Some(text) = shared.last_text.clone() else {
+            return vec![];
+        };
+        shared.response_retry_attempts += 1;
+        log::warn!(
+            "响应级重试: 第 {}/{} 次重新提交文本",
+            shared.response_retry_attempts,
+            shared.response_retry_max
+        );
+        split_for_append(&text, shared.max_append_bytes)
+            .into_iter()
+            .map(|chunk| build_append_text(&format!("event_{}", Uuid::new_v4()), chunk))
+            .collect()
+    }
+
+    /// 存在需要解码后音频的订阅者: audio_stream 或 subscribe() 的接收端
+    fn wants_decoded(shared: &SessionShared) -> bool {
+        !shared.audio_streams.is_empty()
+            || shared
+                .audio_broadcast
+                .as_ref()
+                .is_some_and(|tx| tx.receiver_count() > 0)
+    }
+
+    /// 准备要分发的音频分片, 只有存在需要解码后音频的订阅者时才做base64解码
+    fn prepare_audio(v: &Value, shared: &mut SessionShared) -> Option<AudioDispatch> {
+        let delta = v["delta"].as_str()?;
+        let wants_decoded = Self::wants_decoded(shared);
+        if !wants_decoded && shared.audio_streams_base64.is_empty() {
+            return None;
+        }
+        let mut audio_bytes = None;
+        if wants_decoded {
+            match base64::engine::general_purpose::STANDARD.decode(delta) {
+                Ok(mut decoded) => {
+                    if let Some(format) = shared.confirmed_format
+                        && !shared.sample_transforms.is_empty()
+                    {
+                        if format.format() == "pcm" && format.bits_per_sample() == 16 {
+                            apply_pcm16(&shared.sample_transforms, &mut decoded);
+                        } else if !shared.transforms_skipped {
+                            shared.transforms_skipped = true;
+                            log::warn!("输出格式 {} 不是 16bit PCM, 样本变换不生效", format);
+                        }
+                    }
+                    if let Some(alignment) = shared.frame_alignment
+                        && let Some(format) = shared.confirmed_format
+                        && format.format() == "pcm"
+                    {
+                        let aligner = shared
+                            .frame_aligner
+                            .get_or_insert_with(|| FrameAligner::new(alignment, format));
+                        decoded = aligner.push(&decoded);
+                    }
+                    // 对齐后可能不足一帧, 留到下一个分片
+                    audio_bytes = Some(decoded).filter(|decoded| !decoded.is_empty());
+                }
+                Err(e) => log::error!("音频数据base64解码失败: {}", e),
+            }
+        }
+        Some(AudioDispatch {
+            delta: delta.to_string(),
+            audio_bytes,
+            audio_streams: shared.audio_streams.clone(),
+            audio_streams_base64: shared.audio_streams_base64.clone(),
+            audio_broadcast: shared.audio_broadcast.clone(),
+        })
+    }
+
+    ///
+    /// 暂停时把分片放进队列; 未暂停时先按顺序分发队列中剩余的分片再分发新分片,
+    /// 只在读取任务中调用, 保证分片的顺序
+    async fn deliver_audio(
+        audio_dispatch: Option<AudioDispatch>,
+        shared: &std::sync::Mutex<SessionShared>,
+    ) {
+        let pending = {
+            let mut shared = shared.lock().unwrap();
+            shared.paused_audio.extend(audio_dispatch);
+            if shared.paused {
+                return;
+            }
+            std::mem::take(&mut shared.paused_audio)
+        };
+        for audio_dispatch in pending {
+            Self::dispatch_audio(audio_dispatch, shared).await;
+        }
+    }
+
+    /// 在锁外把音频分片发给各订阅者, OverflowPolicy::Block 时会在这里等待慢消费者
+    async fn dispatch_audio(audio_dispatch: AudioDispatch, shared: &std::sync::Mutex<SessionShared>) {
+        let mut ended = vec![];
+        let mut overflowed = false;
+        for (id, tx) in audio_dispatch.audio_streams_base64.iter() {
+            match tx.send(audio_dispatch.delta.clone()).await {
+                SendOutcome::Delivered => {}
+                SendOutcome::Closed => ended.push(*id),
+                SendOutcome::Overflow => {
+                    overflowed = true;
+                    ended.push(*id);
+                }
+            }
+        }
+        if let Some(audio_bytes) = audio_dispatch.audio_bytes {
+            // 广播不会等待, 落后的接收端按 broadcast 的语义收到 Lagged
+            if let Some(tx) = &audio_dispatch.audio_broadcast {
+                let _ = tx.send(Arc::new(audio_bytes.clone()));
+            }
+            for (id, tx) in audio_dispatch.audio_streams.iter() {
+                match tx.send(audio_bytes.clone()).await {
+                    SendOutcome::Delivered => {}
+                    SendOutcome::Closed => ended.push(*id),
+                    SendOutcome::Overflow => {
+                        overflowed = true;
+                        ended.push(*id);
+                    }
+                }
+            }
+        }
+        if ended.is_empty() {
+            return;
+        }
+        if overflowed {
+            log::error!("音频通道已满, 按 OverflowPolicy::Error 结束订阅");
+        }
+        let mut shared = shared.lock().unwrap();
+        shared.audio_overflowed |= overflowed;
+        shared.audio_streams.retain(|(id, _)| !ended.contains(id));
+        shared.audio_streams_base64.retain(|(id, _)| !ended.contains(id));
+    }
+
+    fn add_subscriber<T: Clone + Send + 'static>(
+        &self,
+        register: impl FnOnce(&mut SessionShared, (u64, AudioSender<T>)),
+        policy: OverflowPolicy,
+    ) -> AudioReceiver<T> {
+        let (tx, rx) = audio_channel(policy, self.audio_buffer_capacity);
+        let mut shared = self.shared.lock().unwrap();
+        if !shared.reader_finished && !shared.session_finished {
+            let id = shared.next_subscriber_id;
+            shared.next_subscriber_id += 1;
+            register(&mut shared, (id, tx));
+        }
+        rx
+    }
+
+    ///
+    /// 订阅解码后的音频(PCM等原始字节), 适合直接播放或写文件;
+    /// 只会收到订阅之后到达的分片, 收到 session.finished 或连接结束时流结束; 交付频率由 DeliveryPolicy 决定,
+    /// 消费跟不上时按 OverflowPolicy 处理
+    pub fn audio_stream(&self) -> impl Stream<Item = Vec<u8>> + Send + 'static + use<> {
+        if self.delivery_policy == DeliveryPolicy::PerDelta {
+            return self.add_subscriber(
+                |shared, subscriber| shared.audio_streams.push(subscriber),
+                self.overflow_policy,
+            );
+        }
+        // 读取任务到合并任务之间总是等待, 溢出策略作用在合并后的输出上
+        let rx = self.add_subscriber(
+            |shared, subscriber| shared.audio_streams.push(subscriber),
+            OverflowPolicy::Block,
+        );
+        let (batched_tx, batched_rx) =
+            audio_channel(self.overflow_policy, self.audio_buffer_capacity);
+        let delivery_policy = self.delivery_policy;
+        let shared = Arc::clone(&self.shared);
+        tokio::spawn(async move {
+            if delivery_policy.deliver(rx, batched_tx).await == SendOutcome::Overflow {
+                log::error!("音频通道已满, 按 OverflowPolicy::Error 结束订阅");
+                shared.lock().unwrap().audio_overflowed = true;
+            }
+        });
+        batched_rx
+    }
+
+    ///
+    /// 以 tokio broadcast 订阅解码后的音频, 用于多个听众同时收听同一路音频;
+    /// 每个接收端只收到订阅之后到达的分片, 所有接收端共享同一份数据(Arc), 不会为每个接收端复制.
+    /// 广播不会让读取任务等待: 接收端落后超过 audio_buffer_capacity 个分片时, recv() 返回
+    /// RecvError::Lagged 并跳到最新的分片; 收到 session.finished 或连接结束后返回 RecvError::Closed
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<u8>>> {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.reader_finished || shared.session_finished {
+            // 发送端随即丢弃, 接收端立即得到 Closed
+            return broadcast::channel(1).1;
+        }
+        let capacity = self.audio_buffer_capacity.max(1);
+        shared
+            .audio_broadcast
+            .get_or_insert_with(|| broadcast::channel(capacity).0)
+            .subscribe()
+    }
+
+    ///
+    /// 订阅服务端原样的base64 delta, 不做解码;
+    /// 需要把音频再以base64放进JSON转发给前端时使用, 省去一次解码和重新编码
+    pub fn audio_stream_base64(&self) -> impl Stream<Item = String> + Send + 'static + use<> {
+        self.add_subscriber(
+            |shared, subscriber| shared.audio_streams_base64.push(subscriber),
+            self.overflow_policy,
+        )
+    }
+
+    ///
+    /// 拉取下一个服务端事件, 需要在 builder 中开启 pull_events;
+    /// 连接结束时先返回 ServerEvent::Closed 说明原因, 之后返回None
+    pub async fn recv(&mut self) -> Option<ServerEvent> {
+        match self.events.as_mut() {
+            Some(events) => events.recv().await,
+            None => {
+                log::warn!("recv() 需要在 QwenTtsRealtimeBuilder 中开启 pull_events");
+                None
+            }
+        }
+    }
+
+    /// OverflowPolicy::Error 下是否有音频订阅因消费过慢被提前结束
+    pub fn audio_overflowed(&self) -> bool {
+        self.shared.lock().unwrap().audio_overflowed
+    }
+
+    /// 因音频超过 max_audio_bytes 中止了会话时返回上限
+    pub fn audio_limit_exceeded(&self) -> Option<usize> {
+        let shared = self.shared.lock().unwrap();
+        shared.max_audio_bytes.filter(|_| shared.audio_limit_exceeded)
+    }
+
+    /// 音频分片到达间隔的抖动统计
+    pub fn delta_jitter(&self) -> JitterStats {
+        self.shared.lock().unwrap().jitter.stats()
+    }
+
+    /// 当前的会话汇总, 读取任务结束(state 不再是 Connected)后即为最终结果
+    pub fn finish_summary(&self) -> FinishSummary {
+        let shared = self.shared.lock().unwrap();
+        FinishSummary {
+            session_finished: shared.session_finished,
+            delta_count: shared.delta_count,
+            audio_bytes: shared.audio_bytes,
+            jitter: shared.jitter.stats(),
+        }
+    }
+
+    /// 连接结束的原因, 读取任务结束前为None
+    pub fn close_info(&self) -> Option<CloseInfo> {
+        self.shared.lock().unwrap().close_info.clone()
+    }
+
+    ///
+    /// finish 之后等待连接结束, 最多等待 timeout;
+    /// 连接异常断开时返回 ClosedEarly 而不是一直等待 session.finished
+    pub async fn wait_until_finished(&self, timeout: Duration) -> FinishOutcome {
+        let mut state = self.watch_state();
+        let closed = state.wait_for(|state| !state.is_open());
+        if tokio::time::timeout(timeout, closed).await.is_err() {
+            return FinishOutcome::Timeout;
+        }
+        // close_info 与 state 在同一次加锁中更新, 连接结束后一定存在
+        match self.close_info().unwrap_or_default() {
+            close_info if close_info.session_finished => FinishOutcome::Finished,
+            close_info => FinishOutcome::ClosedEarly(close_info),
+        }
+    }
+
+    /// 当前连接状态
+    pub fn state(&self) -> SessionState {
+        self.shared.lock().unwrap().state.borrow().clone()
+    }
+
+    /// 读取任务仍在运行, 见 SessionState::is_open
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock().unwrap().state.borrow().is_open()
+    }
+
+    /// 已收到 session.finished; 服务端随后关闭连接, 此时 is_connected 可能仍为true
+    pub fn is_finished(&self) -> bool {
+        self.shared.lock().unwrap().session_finished
+    }
+
+    /// 订阅会话状态变化, 可以用 changed().await 或 wait_for 等待状态切换, 代替轮询 state()
+    pub fn watch_state(&self) -> watch::Receiver<SessionState> {
+        self.shared.lock().unwrap().state.subscribe()
+    }
+
+    /// 与 watch_state 相同: 订阅 Connected -> Active -> Finishing -> Closed/Failed 的状态变化
+    pub fn state_changes(&self) -> watch::Receiver<SessionState> {
+        self.watch_state()
+    }
+
+    /// 第一个音频分片到达后确认的实际输出格式, WAV头和时长计算应以此为准
+    pub fn confirmed_format(&self) -> Option<AudioFormat> {
+        self.shared.lock().unwrap().confirmed_format
+    }
+
+    fn _generate_event_id(&self) -> String {
+        format!("event_{}", Uuid::new_v4().to_string())
+    }
+
+    /// 所有事件统一从这里发出, 发送前依次经过中间件
+    async fn send_event(&mut self, event: Value) -> Result<(), QwenTtsError> {
+        Self::send_via(&self.stream_writer, &self.shared, event).await
+    }
+
+    async fn send_via(
+        stream_writer: &WsWriter,
+        shared: &std::sync::Mutex<SessionShared>,
+        event: Value,
+    ) -> Result<(), QwenTtsError> {
+        let reader_finished = shared.lock().unwrap().reader_finished;
+        if reader_finished {
+            let shared = shared.lock().unwrap();
+            return Err(match shared.max_audio_bytes {
+                Some(limit) if shared.audio_limit_exceeded => {
+                    QwenTtsError::AudioSizeLimitExceeded { limit }
+                }
+                _ => QwenTtsError::ConnectionClosed,
+            });
+        }
+        let frame = Self::encode_event(shared, event.clone()).await?;
+        let mut writer = stream_writer.lock().await;
+        writer.send(Message::text(frame)).await.map_err(Self::send_error)?;
+        // 持有写入端的锁时记录, 保证重连时不会漏掉刚发出的事件
+        let audit = {
+            let mut shared = shared.lock().unwrap();
+            shared.last_sent = Some(Instant::now());
+            let replayable =
+                ["input_text_buffer.append", "input_text_buffer.commit", "session.finish"];
+            if shared.reconnect.is_some() && replayable.iter().any(|t| event["type"] == *t) {
+                shared.unacked.push(event.clone());
+            }
+            shared.audit.clone()
+        };
+        if let Some(audit) = audit {
+            audit.record_sent(&event);
+        }
+        Ok(())
+    }
+
+    /// 依次经过中间件并通知 on_send, 返回要发送的文本帧
+    async fn encode_event(
+        shared: &std::sync::Mutex<SessionShared>,
+        mut event: Value,
+    ) -> Result<String, QwenTtsError> {
+        let (middlewares, callback) = {
+            let shared = shared.lock().unwrap();
+            (shared.middlewares.clone(), shared.callback.clone())
+        };
+        for middleware in middlewares.iter() {
+            middleware.before_send(&mut event)?;
+        }
+        let frame = event.to_string();
+        if let Some(callback) = &callback {
+            callback.on_send(&frame).await;
+        }
+        Ok(frame)
+    }
+
+    fn send_error(e: tungstenite::Error) -> QwenTtsError {
+        match e {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                QwenTtsError::ConnectionClosed
+            }
+            e => QwenTtsError::WebSocketError(e),
+        }
+    }
+
+    ///
+    /// 心跳: 超过 interval 没有发送任何事件或 ping 时发送一次 ping, 与发送事件共用 stream_writer 的锁;
+    /// 会话结束(session.finished)、读取任务结束、句柄被丢弃或发送失败(连接已关闭)时退出
+    async fn heartbeat(
+        stream_writer: WsWriter,
+        shared: Arc<std::sync::Mutex<SessionShared>>,
+        interval: Duration,
+    ) {
+        let shutdown = shared.lock().unwrap().shutdown.clone();
+        let mut deadline = Instant::now() + interval;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline.into()) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            {
+                let mut shared = shared.lock().unwrap();
+                if shared.reader_finished || shared.session_finished {
+                    return;
+                }
+                // 期间发送过事件时连接本身不空闲, 顺延到最近一次发送后的 interval
+                let last_active = shared.last_sent.map_or(deadline, |t| t + interval);
+                if last_active > Instant::now() {
+                    deadline = last_active;
+                    continue;
+                }
+                let now = Instant::now();
+                shared.ping_sent = Some(now);
+                deadline = now + interval;
+            }
+            let ping = Message::Ping(Default::default());
+            if let Err(e) = stream_writer.lock().await.send(ping).await {
+                log::info!("发送心跳失败, 心跳任务退出: {}", e);
+                return;
+            }
+        }
+    }
+
+    ///
+    /// 空闲看门狗: 超过idle没有发送任何事件时重发最近一次的 session.update,
+    /// 避免服务端因会话空闲过期导致之后的 append 失败;
+    /// 已经 finish、会话结束、读取任务结束或句柄被丢弃后退出
+    async fn idle_watchdog(
+        stream_writer: WsWriter,
+        shared: Arc<std::sync::Mutex<SessionShared>>,
+        idle: Duration,
+    ) {
+        let shutdown = shared.lock().unwrap().shutdown.clone();
+        let mut deadline = Instant::now() + idle;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline.into()) => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let config = {
+                let shared = shared.lock().unwrap();
+                if shared.reader_finished || shared.session_finished || shared.finish_sent.is_some()
+                {
+                    return;
+                }
+                let last_active = shared.last_sent.map_or(deadline, |t| t + idle);
+                if last_active > Instant::now() {
+                    deadline = last_active;
+                    continue;
+                }
+                shared.session_config.clone()
+            };
+            deadline = Instant::now() + idle;
+            // 还没有 update_session 过时没有可以重发的配置
+            let Some(config) = config else {
+                continue;
+            };
+            log::info!("会话空闲超过 {:?}, 重发 session.update", idle);
+            let msg = build_session_update_from(&format!("event_{}", Uuid::new_v4()), &config);
+            if let Err(e) = Self::send_via(&stream_writer, &shared, msg).await {
+                log::warn!("空闲重发 session.update 失败, 看门狗退出: {}", e);
+                return;
+            }
+        }
+    }
+
+    /// 建立连接成功后，需要添加session conf; voice 可以传 Voice 或音色名, 音色名不区分大小写
+    pub async fn update_session(
+        &mut self,
+        voice: impl Into<Voice>,
+        response_format: AudioFormat,
+        mode: &str,
+    ) -> Result<(), QwenTtsError> {
+        let voice = voice.into();
+        self.update_session_config(&SessionConfig::new(voice.as_str(), response_format, mode))
+            .await
+    }
+
+    /// 与 update_session 相同, 可以携带 SessionConfig 中的可选字段; 会话中可以多次调用
+    pub async fn update_session_config(&mut self, config: &SessionConfig) -> Result<(), QwenTtsError> {
+        {
+            // 在发送前记录, 避免读取任务先收到 session.updated 时误判为服务端修改了配置
+            let mut shared = self.shared.lock().unwrap();
+            shared.requested_format = Some(config.response_format);
+            shared.confirmed_format = None;
+            shared.session_config = Some(config.clone());
+        }
+        let msg = build_session_update_from(&self._generate_event_id(), config);
+        log::info!("send: {}", msg);
+        self.send_event(msg).await
+    }
+
+    ///
+    /// 与 update_session_config 相同, 但会等待服务端的 session.updated,
+    /// 并与请求的配置对比, 找出服务端没有按请求生效的字段(被忽略或被修正)
+    pub async fn update_session_and_wait(
+        &mut self,
+        config: &SessionConfig,
+        timeout: Duration,
+    ) -> Result<SessionUpdateResult, QwenTtsError> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if shared.reader_finished {
+                return Err(QwenTtsError::SessionClosedEarly(
+                    "连接已关闭, 无法等待 session.updated".to_string(),
+                ));
+            }
+            shared.session_updated = Some(tx);
+        }
+        self.update_session_config(config).await?;
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(session)) => {
+                let result = SessionUpdateResult::diff(config, &session);
+                for field in result.ignored_fields.iter() {
+                    log::warn!("session.update 字段未按请求生效: {}", field);
+                }
+                Ok(result)
+            }
+            Ok(Err(_)) => Err(QwenTtsError::SessionClosedEarly(
+                "收到 session.updated 之前连接已关闭".to_string(),
+            )),
+            Err(_) => {
+                self.shared.lock().unwrap().session_updated = None;
+                Err(QwenTtsError::Timeout(format!(
+                    "{:?} 内没有收到 session.updated",
+                    timeout
+                )))
+            }
+        }
+    }
+
+    /// 立即发送 input_text_buffer.append(超过 max_append_bytes 时拆成多个); 需要合并多段短文本时用 append_text_deferred
+    pub async fn append_text(&mut self, text: &str) -> Result<(), QwenTtsError> {
+        if self.shared.lock().unwrap().finish_sent.is_some() {
+            self.start_next_session().await?;
+        }
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.last_text = Some(text.to_string());
+            shared.response_retry_attempts = 0;
+        }
+        let max_append_bytes = self.shared.lock().unwrap().max_append_bytes;
+        for chunk in split_for_append(text, max_append_bytes) {
+            let msg = build_append_text(&self._generate_event_id(), chunk);
+            self.send_event(msg).await?;
+        }
+        self.shared.lock().unwrap().state.send_if_modified(|state| {
+            let connected = *state == SessionState::Connected;
+            if connected {
+                *state = SessionState::Active;
+            }
+            connected
+        });
+        Ok(())
+    }
+
+    ///
+    /// 用指定音色合成一段文本, 之后恢复原来的音色
+    ///
+    /// input_text_buffer.append 不支持单独指定音色, 这里通过
+    /// session.update(voice) -> append -> commit -> session.update(原配置) 模拟;
+    /// commit 模式下会立即提交这段文本, 保证它用临时音色合成.
+    /// server_commit 模式下由服务端决定何时合成, 缓冲区中尚未合成的文本可能使用恢复后的音色,
+    /// 多音色对话建议使用 commit 模式
+    pub async fn append_text_with_voice(&mut self, text: &str, voice: &str) -> Result<(), QwenTtsError> {
+        let previous = self
+            .shared
+            .lock()
+            .unwrap()
+            .session_config
+            .clone()
+            .ok_or(QwenTtsError::SessionNotConfigured)?;
+        if previous.voice == voice {
+            return self.append_text(text).await;
+        }
+        let mut config = previous.clone();
+        config.voice = voice.to_string();
+        self.update_session_config(&config).await?;
+        self.append_text(text).await?;
+        if previous.mode == "commit" {
+            self.commit().await?;
+        }
+        self.update_session_config(&previous).await
+    }
+
+    ///
+    /// 按段指定语言追加文本, 用于多语言旁白
+    ///
+    /// input_text_buffer.append 不支持单独指定语言, 这里只在语言与当前会话的 language_type
+    /// 不同时发送一次 session.update(其余配置不变) 再 append; 连续相同语言的段不会重复更新会话
+    pub async fn append_text_lang(&mut self, text: &str, lang: Language) -> Result<(), QwenTtsError> {
+        let current = self
+            .shared
+            .lock()
+            .unwrap()
+            .session_config
+            .clone()
+            .ok_or(QwenTtsError::SessionNotConfigured)?;
+        if current.language_type.as_deref() != Some(lang.as_str()) {
+            let config = current.language_type(lang.as_str());
+            self.update_session_config(&config).await?;
+        }
+        self.append_text(text).await
+    }
+
+    ///
+    /// 暂存文本, 不发送任何事件; 之后的 commit() 或 finish() 把暂存的文本合并成一次 append 发送,
+    /// 逐句产生的短文本可以减少消息数. append_text 则每次调用立即发送
+    pub fn append_text_deferred(&mut self, text: &str) {
+        self.deferred_text.push_str(text);
+    }
+
+    ///
+    /// 先把 append_text_deferred 暂存的文本合并成一次 append 发送, 再发送 input_text_buffer.commit 开始合成;
+    /// server_commit 模式下服务端自行提交, 有暂存文本时只发送合并后的 append
+    pub async fn commit(&mut self) -> Result<(), QwenTtsError> {
+        let flushed = self.flush_deferred().await?;
+        let server_commit = self
+            .shared
+            .lock()
+            .unwrap()
+            .session_config
+            .as_ref()
+            .is_some_and(|config| config.mode == "server_commit");
+        if flushed && server_commit {
+            return Ok(());
+        }
+        let msg = build_commit(&self._generate_event_id());
+        self.send_event(msg).await
+    }
+
+    /// 发送暂存的文本, 没有暂存文本时返回false
+    async fn flush_deferred(&mut self) -> Result<bool, QwenTtsError> {
+        if self.deferred_text.is_empty() {
+            return Ok(false);
+        }
+        let text = std::mem::take(&mut self.deferred_text);
+        self.append_text(&text).await?;
+        Ok(true)
+    }
+
+    ///
+    /// 中止正在进行的合成, 用于打断播放: 发送 response.cancel 后关闭连接,
+    /// 读取任务立即退出并调用 on_close, 返回时连接已经结束, 之后的音频不会再分发;
+    /// 已经 finish 或连接已结束时什么也不做, 取消之后的 finish 同样什么也不做. 被取消的会话不会自动重连
+    pub async fn cancel(&mut self) -> Result<(), QwenTtsError> {
+        {
+            let shared = self.shared.lock().unwrap();
+            if shared.finish_sent.is_some() || shared.reader_finished {
+                return Ok(());
+            }
+        }
+        let msg = build_cancel(&self._generate_event_id());
+        let sent = self.send_event(msg).await;
+        self.cancel.cancel();
+        let mut state = self.watch_state();
+        let _ = state.wait_for(|state| !state.is_open()).await;
+        sent
+    }
+
+    /// 不发送任何事件直接关闭连接, finish 之后服务端不再响应时用于收尾; 不等待读取任务结束
+    pub(crate) fn abort(&self) {
+        self.cancel.cancel();
+    }
+
+    ///
+    /// 暂停音频分发: 服务端协议没有暂停合成的事件, 这里由客户端模拟, 连接照常读取,
+    /// 音频分片暂存在内存中, 不再发给 audio_stream / audio_stream_base64 / subscribe 的订阅者,
+    /// resume() 后按原顺序立即补发. 回调(on_event 等)不受影响, 照常收到所有事件;
+    /// 暂停期间会话结束时, 订阅者的流要等 resume() 补发完才结束
+    pub fn pause(&self) {
+        self.shared.lock().unwrap().paused = true;
+    }
+
+    /// 恢复 pause() 暂停的音频分发, 先补发暂停期间暂存的分片
+    pub async fn resume(&self) {
+        let pending = {
+            let mut shared = self.shared.lock().unwrap();
+            if !shared.paused {
+                return;
+            }
+            shared.paused = false;
+            if !shared.reader_finished {
+                // 由读取任务补发, 与之后到达的分片保持顺序
+                shared.resumed.notify_one();
+                return;
+            }
+            std::mem::take(&mut shared.paused_audio)
+        };
+        for audio_dispatch in pending {
+            Self::dispatch_audio(audio_dispatch, &self.shared).await;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.lock().unwrap().paused
+    }
+
+    /// 结束会话, 之后再 append_text 的行为由 AfterFinishPolicy 决定
+    pub async fn finish(&mut self) -> Result<(), QwenTtsError> {
+        // 已经取消的会话不再发送 session.finish, 避免与 response.cancel 冲突
+        if self.cancel.is_cancelled() {
+            return Ok(());
+        }
+        self.flush_deferred().await?;
+        let msg = build_finish(&self._generate_event_id());
+        self.send_event(msg).await?;
+        let mut shared = self.shared.lock().unwrap();
+        shared.finish_sent = Some(Instant::now());
+        shared.state.send_if_modified(|state| {
+            let open = state.is_open() && *state != SessionState::Finishing;
+            if open {
+                *state = SessionState::Finishing;
+            }
+            open
+        });
+        Ok(())
+    }
+
+    /// finish 之后按 AfterFinishPolicy 拒绝, 或等待当前会话结束后换成新的会话
+    async fn start_next_session(&mut self) -> Result<(), QwenTtsError> {
+        // 建连成功之前不取走 builder, 失败后再次 append 仍然可以重试
+        let Some(builder) = self.next_session.as_deref().cloned() else {
+            return Err(QwenTtsError::InvalidState(
+                "会话已经 finish, 不能继续 append_text".to_string(),
+            ));
+        };
+        // 等待旧会话关闭同样受建连超时限制, 避免服务端一直不关闭连接时卡住
+        let timeout = builder.connect_options.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT);
+        let mut state = self.watch_state();
+        if tokio::time::timeout(timeout, state.wait_for(|state| !state.is_open()))
+            .await
+            .is_err()
+        {
+            return Err(QwenTtsError::Timeout(format!(
+                "{:?} 内上一个会话没有关闭",
+                timeout
+            )));
+        }
+        let session_config = self.shared.lock().unwrap().session_config.clone();
+        log::info!("会话已结束, 建立新的会话继续合成");
+        let mut next = builder.connect().await?;
+        if let Some(session_config) = session_config {
+            next.update_session_config(&session_config).await?;
+        }
+        *self = next;
+        Ok(())
+    }
+}
+
+///
+/// session.update 中 session 的配置, 可选字段为None时不发送
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+    pub voice: String,
+    pub response_format: AudioFormat,
+    pub mode: String,
+    /// 语言提示, 如 "Chinese"、"English"; 不设置时由服务端自动判断
+    pub language_type: Option<String>,
+    /// 说话风格/情感, 不设置时使用音色默认风格
+    pub style: Option<Style>,
+    /// 随机种子, 用于让相同文本得到相同的音频(如音频处理的快照测试);
+    /// 实时接口的文档没有声明支持该字段, 服务端可能直接忽略,
+    /// 是否生效可以用 update_session_and_wait 的 ignored_fields 确认
+    pub seed: Option<u64>,
+    /// 原样合并进 session 对象的其他参数, 用于服务端新增、本crate尚未建模的字段;
+    /// 与上面的字段同名时以上面的字段为准
+    pub extra_params: serde_json::Map<String, Value>,
+}
+
+///
+/// 音色的风格/情感预设, 以小写字符串发送, 如 "cheerful"
+///
+/// 不同音色支持的风格不同, 服务端不支持的风格会被忽略而不是报错,
+/// 可以用 update_session_and_wait 检查 ignored_fields 确认是否生效;
+/// 预设之外的取值用 Custom 原样发送
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Style {
+    /// 开心、活泼
+    Cheerful,
+    /// 严肃、正式
+    Serious,
+    /// 温柔
+    Gentle,
+    /// 悲伤
+    Sad,
+    /// 生气
+    Angry,
+    /// 激动、兴奋
+    Excited,
+    Custom(String),
+}
+
+impl Style {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Style::Cheerful => "cheerful",
+            Style::Serious => "serious",
+            Style::Gentle => "gentle",
+            Style::Sad => "sad",
+            Style::Angry => "angry",
+            Style::Excited => "excited",
+            Style::Custom(style) => style,
+        }
+    }
+}
+
+impl From<&str> for Style {
+    fn from(style: &str) -> Self {
+        match style {
+            "cheerful" => Style::Cheerful,
+            "serious" => Style::Serious,
+            "gentle" => Style::Gentle,
+            "sad" => Style::Sad,
+            "angry" => Style::Angry,
+            "excited" => Style::Excited,
+            other => Style::Custom(other.to_string()),
+        }
+    }
+}
+
+///
+/// qwen3-tts-flash-realtime 支持的音色, 以首字母大写的名字发送, 如 "Cherry";
+/// 从字符串解析时不区分大小写, 预设之外的音色(如新上线的、复刻的)用 Custom 原样发送
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Voice {
+    /// 芊悦
+    Cherry,
+    /// 晨煦
+    Ethan,
+    /// 不吃鱼
+    Nofish,
+    /// 詹妮弗
+    Jennifer,
+    /// 甜茶
+    Ryan,
+    /// 卡捷琳娜
+    Katerina,
+    /// 墨讲师
+    Elias,
+    /// 上海-阿珍
+    Jada,
+    /// 北京-晓东
+    Dylan,
+    /// 四川-晴儿
+    Sunny,
+    /// 南京-老李
+    Li,
+    /// 陕西-秦川
+    Marcus,
+    /// 闽南-阿杰
+    Roy,
+    /// 天津-李彼得
+    Peter,
+    /// 粤语-阿强
+    Rocky,
+    /// 粤语-阿清
+    Kiki,
+    /// 四川-程川
+    Eric,
+    Custom(String),
+}
+
+impl Voice {
+    const PRESETS: [Voice; 17] = [
+        Voice::Cherry,
+        Voice::Ethan,
+        Voice::Nofish,
+        Voice::Jennifer,
+        Voice::Ryan,
+        Voice::Katerina,
+        Voice::Elias,
+        Voice::Jada,
+        Voice::Dylan,
+        Voice::Sunny,
+        Voice::Li,
+        Voice::Marcus,
+        Voice::Roy,
+        Voice::Peter,
+        Voice::Rocky,
+        Voice::Kiki,
+        Voice::Eric,
+    ];
+
+    /// session.update 中 voice 的取值
+    pub fn as_str(&self) -> &str {
+        match self {
+            Voice::Cherry => "Cherry",
+            Voice::Ethan => "Ethan",
+            Voice::Nofish => "Nofish",
+            Voice::Jennifer => "Jennifer",
+            Voice::Ryan => "Ryan",
+            Voice::Katerina => "Katerina",
+            Voice::Elias => "Elias",
+            Voice::Jada => "Jada",
+            Voice::Dylan => "Dylan",
+            Voice::Sunny => "Sunny",
+            Voice::Li => "Li",
+            Voice::Marcus => "Marcus",
+            Voice::Roy => "Roy",
+            Voice::Peter => "Peter",
+            Voice::Rocky => "Rocky",
+            Voice::Kiki => "Kiki",
+            Voice::Eric => "Eric",
+            Voice::Custom(voice) => voice,
+        }
+    }
+}
+
+impl std::str::FromStr for Voice {
+    type Err = std::convert::Infallible;
+
+    /// 不区分大小写匹配预设音色, 匹配不到时为 Custom, 不会失败
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let voice = s.trim();
+        Ok(Voice::PRESETS
+            .into_iter()
+            .find(|preset| preset.as_str().eq_ignore_ascii_case(voice))
+            .unwrap_or_else(|| Voice::Custom(voice.to_string())))
+    }
+}
+
+impl From<&str> for Voice {
+    fn from(voice: &str) -> Self {
+        let Ok(voice) = voice.parse();
+        voice
+    }
+}
+
+impl From<String> for Voice {
+    fn from(voice: String) -> Self {
+        Voice::from(voice.as_str())
+    }
+}
+
+impl From<&String> for Voice {
+    fn from(voice: &String) -> Self {
+        Voice::from(voice.as_str())
+    }
+}
+
+impl SessionConfig {
+    pub fn new(voice: &str, response_format: AudioFormat, mode: &str) -> Self {
+        Self {
+            voice: voice.to_string(),
+            response_format,
+            mode: mode.to_string(),
+            language_type: None,
+            style: None,
+            seed: None,
+            extra_params: serde_json::Map::new(),
+        }
+    }
+
+    pub fn language_type(mut self, language_type: &str) -> Self {
+        self.language_type = Some(language_type.to_string());
+        self
+    }
+
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = Some(style);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// 见 extra_params, 同一个key多次设置时后设置的生效
+    pub fn extra_param(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.extra_params.insert(key.to_string(), value.into());
+        self
+    }
+
+    /// 用服务端 session 中出现的字段覆盖当前配置, 没有出现的字段保持不变
+    fn merge_server_session(&mut self, session: &Value) {
+        if let Some(voice) = session["voice"].as_str() {
+            self.voice = voice.to_string();
+        }
+        if let Some(mode) = session["mode"].as_str() {
+            self.mode = mode.to_string();
+        }
+        if let Some(sample_rate) = session["sample_rate"].as_u64() {
+            self.response_format.sample_rate = sample_rate as u32;
+        }
+        if let Some(format) = session["response_format"].as_str()
+            && let Some(known) = ["pcm", "wav", "mp3", "opus"].into_iter().find(|f| *f == format)
+        {
+            self.response_format.format = known;
+        }
+        if let Some(language_type) = session["language_type"].as_str() {
+            self.language_type = Some(language_type.to_string());
+        }
+        if let Some(style) = session["style"].as_str() {
+            self.style = Some(Style::from(style));
+        }
+        if let Some(seed) = session["seed"].as_u64() {
+            self.seed = Some(seed);
+        }
+    }
+
+    /// response_format 发送 format 而不是 format_str, 见 AudioFormat::format_str
+    fn to_json(&self) -> Value {
+        let mut config = json!({
+            "voice":self.voice,
+            "mode":self.mode,
+            "response_format":self.response_format.format,
+            "sample_rate":self.response_format.sample_rate,
+        });
+        if let Some(bit_rate) = self.response_format.bit_rate_kbps() {
+            config["bit_rate"] = json!(bit_rate);
+        }
+        if let Some(language_type) = &self.language_type {
+            config["language_type"] = json!(language_type);
+        }
+        if let Some(style) = &self.style {
+            config["style"] = json!(style.as_str());
+        }
+        if let Some(seed) = self.seed {
+            config["seed"] = json!(seed);
+        }
+        if let Some(fields) = config.as_object_mut() {
+            for (key, value) in self.extra_params.iter() {
+                fields.entry(key.as_str()).or_insert_with(|| value.clone());
+            }
+        }
+        config
+    }
+}
+
+///
+/// update_session_and_wait 的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionUpdateResult {
+    /// 服务端 session.updated 中实际生效的配置, 服务端没有返回的字段沿用请求值
+    pub applied: SessionConfig,
+    /// 请求中带了、但服务端没有返回或返回值不同的字段
+    pub ignored_fields: Vec<String>,
+}
+
+impl SessionUpdateResult {
+    fn diff(requested: &SessionConfig, session: &Value) -> Self {
+        let requested_json = requested.to_json();
+        let ignored_fields = requested_json
+            .as_object()
+            .map(|fields| {
+                fields
+                    .iter()
+                    .filter(|(key, value)| session.get(key.as_str()) != Some(value))
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut applied = requested.clone();
+        applied.merge_server_session(session);
+        // 可选字段以服务端是否返回为准
+        applied.language_type = session["language_type"].as_str().map(str::to_string);
+        applied.style = session["style"].as_str().map(Style::from);
+        applied.seed = session["seed"].as_u64();
+        Self {
+            applied,
+            ignored_fields,
+        }
+    }
+}
+
+///
+/// session.update 事件, 只负责拼JSON不做任何IO, 便于脱离连接单独测试
+pub fn build_session_update(
+    event_id: &str,
+    voice: &str,
+    response_format: AudioFormat,
+    mode: &str,
+) -> Value {
+    build_session_update_from(event_id, &SessionConfig::new(voice, response_format, mode))
+}
+
+/// 按 SessionConfig 生成 session.update 事件
+pub fn build_session_update_from(event_id: &str, config: &SessionConfig) -> Value {
+    json!({
+        "event_id": event_id,
+        "type": "session.update",
+        "session": config.to_json(),
+    })
+}
+
+/// input_text_buffer.append 事件
+pub fn build_append_text(event_id: &str, text: &str) -> Value {
+    json!({
+        "event_id": event_id,
+        "type": "input_text_buffer.append",
+        "text": text,
+    })
+}
+
+///
+/// 把超过 max_bytes 的文本拆成多段, 服务端会把同一缓冲区中的多次 append 拼接后再合成,
+/// 拆分点不会产生停顿. 只在字符边界拆分, 不会截断汉字等多字节字符,
+/// 也不会把组合符号、变体选择符、零宽连接符与前一个字符拆开(如 emoji 序列、带声调的拉丁字母)
+pub fn split_for_append(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = vec![];
+    let mut rest = text;
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while end > 0 && !(rest.is_char_boundary(end) && is_split_point(rest, end)) {
+            end -= 1;
+        }
+        if end == 0 {
+            // 单个字符(或组合序列)就超过上限, 只能整体发送
+            end = rest
+                .char_indices()
+                .map(|(i, _)| i)
+                .find(|&i| i > 0 && is_split_point(rest, i))
+                .unwrap_or(rest.len());
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    if !rest.is_empty() || chunks.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// at 处是否可以拆分: 后一个字符不是组合符号, 前一个字符不是零宽连接符
+fn is_split_point(text: &str, at: usize) -> bool {
+    let joins_next = text[..at].ends_with('\u{200D}');
+    let attaches_prev = text[at..].chars().next().is_some_and(|c| {
+        matches!(c as u32,
+            0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x20D0..=0x20FF | 0xFE00..=0xFE0F
+            | 0xFE20..=0xFE2F | 0x1F3FB..=0x1F3FF | 0xE0100..=0xE01EF | 0x200D)
+    });
+    !joins_next && !attaches_prev
+}
+
+/// input_text_buffer.commit 事件
+pub fn build_commit(event_id: &str) -> Value {
+    json!({
+        "event_id": event_id,
+        "type": "input_text_buffer.commit"
+    })
+}
+
+/// response.cancel 事件, 取消正在进行的响应
+pub fn build_cancel(event_id: &str) -> Value {
+    json!({
+        "event_id": event_id,
+        "type": "response.cancel"
+    })
+}
+
+/// session.finish 事件
+pub fn build_finish(event_id: &str) -> Value {
+    json!({
+        "event_id": event_id,
+        "type": "session.finish"
+    })
+}
+
+///
+/// QwenTtsRealtime 的配置, 代替 new 的位置参数; 未设置的字段使用默认值:
+/// 模型 qwen3-tts-flash-realtime, url 为 DASHSCOPE_REALTIME_URL 或北京地域的默认地址,
+/// API Key 为 DASHSCOPE_API_KEY, workspace 为 DASHSCOPE_WORKSPACE_ID. 用 QwenTtsRealtime::builder() 或 from_env() 创建, connect() 建立连接
+#[derive(Clone)]
+pub struct QwenTtsRealtimeBuilder {
+    model_name: String,
+    // None 或空字符串时读取密钥文件, 再读取环境变量 DASHSCOPE_API_KEY
+    api_key: Option<String>,
+    // None 时读取环境变量 DASHSCOPE_API_KEY_FILE
+    api_key_file: Option<PathBuf>,
+    url: Option<String>,
+    workspace: Option<String>,
+    callback: Option<CallbackDispatch>,
+    middlewares: Vec<Arc<dyn EventMiddleware + Sync + Send>>,
+    connect_options: ConnectOptions,
+    delivery_policy: DeliveryPolicy,
+    overflow_policy: OverflowPolicy,
+    audio_buffer_capacity: usize,
+    sample_transforms: Vec<Arc<dyn SampleTransform + Send + Sync>>,
+    response_retry: u32,
+    after_finish: AfterFinishPolicy,
+    idle_resend: Option<Duration>,
+    finish_audio_timeout: Option<Duration>,
+    finished_grace: Duration,
+    pad_trailing_silence: Option<Duration>,
+    max_audio_bytes: Option<usize>,
+    frame_alignment: Option<FrameAlignment>,
+    reconnect: Option<ReconnectPolicy>,
+    max_append_bytes: usize,
+    audit_sink: Option<Arc<dyn AuditSink + Send + Sync>>,
+    audit_include_text: bool,
+}
+
+impl QwenTtsRealtimeBuilder {
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            model_name: "qwen3-tts-flash-realtime".to_string(),
+            api_key: Some(api_key.to_string()),
+            api_key_file: None,
+            url: None,
+            workspace: None,
+            callback: None,
+            middlewares: vec![],
+            connect_options: ConnectOptions::default(),
+            delivery_policy: DeliveryPolicy::default(),
+            overflow_policy: OverflowPolicy::default(),
+            audio_buffer_capacity: DEFAULT_AUDIO_BUFFER_CAPACITY,
+            sample_transforms: vec![],
+            response_retry: 0,
+            after_finish: AfterFinishPolicy::default(),
+            idle_resend: None,
+            finish_audio_timeout: None,
+            finished_grace: DEFAULT_FINISHED_GRACE,
+            pad_trailing_silence: None,
+            max_audio_bytes: None,
+            frame_alignment: None,
+            reconnect: None,
+            max_append_bytes: DEFAULT_MAX_APPEND_BYTES,
+            audit_sink: None,
+            audit_include_text: false,
+        }
+    }
+
+    /// API Key 从 DASHSCOPE_API_KEY_FILE 指向的文件或环境变量 DASHSCOPE_API_KEY 读取,
+    /// url、workspace 未设置时读取 DASHSCOPE_REALTIME_URL、DASHSCOPE_WORKSPACE_ID
+    pub fn from_env() -> Self {
+        Self {
+            api_key: None,
+            ..Self::new("")
+        }
+    }
+
+    /// 覆盖 new/from_env 时的API Key, 传空字符串时改为读取环境变量 DASHSCOPE_API_KEY
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    ///
+    /// 从文件读取API Key(去掉首尾空白), 覆盖环境变量 DASHSCOPE_API_KEY_FILE, 适合 Docker secrets 等挂载的密钥;
+    /// 优先级: api_key/new 传入的值 > 密钥文件 > 环境变量 DASHSCOPE_API_KEY.
+    /// 文件无法读取时 connect 返回 ApiKeyFile
+    pub fn api_key_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.api_key_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn model(mut self, model_name: &str) -> Self {
+        self.model_name = model_name.to_string();
+        self
+    }
+
+    pub fn url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_string());
+        self
+    }
+
+    pub fn workspace(mut self, workspace: &str) -> Self {
+        self.workspace = Some(workspace.to_string());
+        self
+    }
+
+    pub fn callback(mut self, callback: SharedCallback) -> Self {
+        self.callback = Some(CallbackDispatch::Sync(callback));
+        self
+    }
+
+    /// 以异步通道代替同步回调接收事件, 与 callback 互斥, 后设置的生效
+    pub fn event_channel(mut self, tx: mpsc::Sender<CallbackEvent>) -> Self {
+        self.callback = Some(CallbackDispatch::Channel(tx));
+        self
+    }
+
+    /// 按添加顺序组成中间件链
+    pub fn middleware(mut self, middleware: Arc<dyn EventMiddleware + Sync + Send>) -> Self {
+        self.middlewares.push(middleware);
+        self
+    }
+
+    /// 为true时握手的user-agent不包含 platform/processor 信息, 默认false与官方SDK保持一致
+    pub fn minimal_user_agent(mut self, minimal_user_agent: bool) -> Self {
+        self.connect_options.minimal_user_agent = minimal_user_agent;
+        self
+    }
+
+    ///
+    /// 固定接口版本, 以 api_version 查询参数附加在握手url上, 避免服务端默认版本变化带来的不兼容;
+    /// 默认不设置, 使用服务端默认版本
+    pub fn api_version(mut self, api_version: &str) -> Self {
+        self.connect_options.api_version = Some(api_version.to_string());
+        self
+    }
+
+    /// 心跳 ping 的间隔, 默认 DEFAULT_HEARTBEAT_INTERVAL(20秒), Duration::ZERO 关闭心跳;
+    /// 只在连接空闲(interval 内没有发送事件)时发送
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.connect_options.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// 开关心跳(keepalive), 开启时使用默认间隔; 需要指定间隔时用 heartbeat_interval.
+    /// 心跳默认已开启, keepalive(false) 恢复不发 ping 的行为
+    pub fn keepalive(mut self, enabled: bool) -> Self {
+        self.connect_options.heartbeat_interval = Some(if enabled {
+            DEFAULT_HEARTBEAT_INTERVAL
+        } else {
+            Duration::ZERO
+        });
+        self
+    }
+
+    /// 为true时缓存所有服务端事件, 通过 QwenTtsRealtime::recv 拉取; 不消费时事件会一直堆积
+    pub fn pull_events(mut self, pull_events: bool) -> Self {
+        self.connect_options.pull_events = pull_events;
+        self
+    }
+
+    /// audio_stream 交付音频的频率, 默认 PerDelta
+    pub fn delivery_policy(mut self, delivery_policy: DeliveryPolicy) -> Self {
+        self.delivery_policy = delivery_policy;
+        self
+    }
+
+    /// audio_stream / audio_stream_base64 每个订阅最多缓冲的分片数
+    pub fn audio_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.audio_buffer_capacity = capacity;
+        self
+    }
+
+    /// 音频缓冲写满时的处理方式, 默认 Block
+    pub fn overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// 在 audio_stream 的解码路径上按添加顺序处理 16bit PCM 样本,
+    /// 其他位深和压缩格式不做处理, 只记录一条warn日志
+    pub fn sample_transform(mut self, transform: Arc<dyn SampleTransform + Send + Sync>) -> Self {
+        self.sample_transforms.push(transform);
+        self
+    }
+
+    ///
+    /// 响应级重试: response.done 返回 failed 时在同一会话内重新提交最近一次的文本, 最多 max_attempts 次;
+    /// 默认0不重试. 与重连不同, 不会新建连接; 错误类型为 invalid_request_error 时不重试
+    pub fn response_retry(mut self, max_attempts: u32) -> Self {
+        self.response_retry = max_attempts;
+        self
+    }
+
+    ///
+    /// 会话空闲(没有发送任何事件)超过idle时自动重发最近一次的 session.update, 默认关闭;
+    /// 用于交互场景下用户长时间不输入导致服务端会话过期. idle应小于服务端的过期时间
+    pub fn idle_session_resend(mut self, idle: Duration) -> Self {
+        self.idle_resend = Some(idle);
+        self
+    }
+
+    /// 建连超时, 默认 DEFAULT_CONNECT_TIMEOUT(10秒), 超时返回 QwenTtsError::Timeout; 同样作用于自动重连
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_options.connect_timeout = Some(timeout);
+        self
+    }
+
+    ///
+    /// finish 之后超过timeout没有收到音频时调用 on_error 并关闭连接, 会话以 Failed 结束, 默认关闭;
+    /// 用于服务端在 finish 之后不再响应的情况. 每收到一个音频分片重新计时
+    pub fn finish_audio_timeout(mut self, timeout: Duration) -> Self {
+        self.finish_audio_timeout = Some(timeout);
+        self
+    }
+
+    ///
+    /// 收到 session.finished 后继续接收音频的时长, 默认 DEFAULT_FINISHED_GRACE(200毫秒);
+    /// 服务端可能在 session.finished 前后才发出最后一个分片, 宽限期结束或连接关闭后音频流才结束,
+    /// 回调在 session.finished 时返回true也会等宽限期结束再退出. Duration::ZERO 时立即结束
+    pub fn finished_grace(mut self, grace: Duration) -> Self {
+        self.finished_grace = grace;
+        self
+    }
+
+    /// finish 之后再 append_text 的处理方式, 默认拒绝
+    pub fn after_finish(mut self, policy: AfterFinishPolicy) -> Self {
+        self.after_finish = policy;
+        self
+    }
+
+    ///
+    /// 收到 session.finished 时在 audio_stream / audio_stream_base64 末尾追加一段静音(全零PCM样本),
+    /// 避免部分播放器在流突然结束时吞掉最后一个音节; 默认关闭, 只对pcm格式生效
+    pub fn pad_trailing_silence(mut self, duration: Duration) -> Self {
+        self.pad_trailing_silence = Some(duration);
+        self
+    }
+
+    ///
+    /// 单个 input_text_buffer.append 中文本的最大字节数, 默认 DEFAULT_MAX_APPEND_BYTES;
+    /// append_text 的文本超过时自动拆成多个事件连续发送, 服务端拼接后合成, 不会在拆分点停顿
+    pub fn max_append_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_append_bytes = max_bytes.max(1);
+        self
+    }
+
+    ///
+    /// audio_stream 输出的PCM按编码器帧长对齐, 最后不足一帧的部分按 AlignMode 补零或丢弃;
+    /// 只作用于解码后的PCM, base64透传和回调收到的原始事件不受影响.
+    /// 连接在 session.finished 之前断开时, 尚未凑满一帧的部分会被丢弃
+    pub fn align_frames(mut self, alignment: FrameAlignment) -> Self {
+        self.frame_alignment = Some(alignment);
+        self
+    }
+
+    ///
+    /// 会话收到的音频总字节数(解码后)上限, 默认不限制; 超过时丢弃超出的分片并中止会话,
+    /// 之前已经分发的音频保持不变, 之后的发送返回 QwenTtsError::AudioSizeLimitExceeded
+    pub fn max_audio_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_audio_bytes = Some(max_bytes);
+        self
+    }
+
+    ///
+    /// 连接在 session.finished 之前异常断开时自动重连, 最多重试 max_retries 次(0为关闭, 默认关闭),
+    /// 第一次立即重连, 之后从 backoff 开始指数退避(带 Jitter::Full 抖动); 重连后重发最近一次的 session.update,
+    /// 并重放最近一次 response.done 之后发送的 append/commit/finish.
+    /// 断开时正在合成的文本会整段重新合成, 之前已收到的那部分音频会重复;
+    /// 正常收到 session.finished、被回调中止或超过 max_audio_bytes 时不会重连;
+    /// connect 时DNS解析失败同样按此重试解析, 全部失败返回 DnsFailure
+    pub fn with_reconnect(mut self, max_retries: u32, backoff: Duration) -> Self {
+        self.reconnect = (max_retries > 0).then(|| {
+            ReconnectPolicy::new(max_retries).backoff(backoff, MAX_RECONNECT_BACKOFF.max(backoff))
+        });
+        self
+    }
+
+    ///
+    /// 记录会话生命周期的审计事件(建连、会话配置、追加文本、finish、连接结束), 见 AuditSink;
+    /// 默认只记录文本的长度, 需要原始文本时开启 audit_include_text
+    pub fn audit_sink(mut self, sink: Arc<dyn AuditSink + Send + Sync>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// 审计记录中包含追加的原始文本, 默认关闭
+    pub fn audit_include_text(mut self, include_text: bool) -> Self {
+        self.audit_include_text = include_text;
+        self
+    }
+
+    /// 与 with_reconnect 相同, 可以指定退避上限和抖动方式(默认 Jitter::Full)
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = (policy.max_attempts() > 0).then_some(policy);
+        self
+    }
+
+    /// 按当前环境变量解析出的配置, 与 connect 使用的完全一致
+    pub fn effective_config(&self) -> EffectiveConfig {
+        self.effective_config_with(|key| std::env::var(key).ok())
+    }
+
+    fn effective_config_with(&self, lookup: impl Fn(&str) -> Option<String>) -> EffectiveConfig {
+        // 只用于展示, 密钥文件无法读取时视为没有API Key, connect 时才返回错误
+        let api_key = self.resolve_api_key(&lookup).unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            None
+        });
+        let url = resolve_value(self.url.as_deref(), REALTIME_URL_ENV, &lookup);
+        let workspace = self.resolve_workspace(&lookup);
+        self.resolved(&api_key, &url, workspace.as_deref())
+    }
+
+    /// API Key 按 参数 > 密钥文件 > 环境变量 的顺序解析;
+    /// 指定了密钥文件但无法读取时返回错误, 不会回退到环境变量
+    fn resolve_api_key(
+        &self,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<(String, ValueSource)>, QwenTtsError> {
+        if let Some(api_key) = self.api_key.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+            return Ok(Some((api_key.to_string(), ValueSource::Param)));
+        }
+        let key_file = self.api_key_file.clone().or_else(|| {
+            resolve_value(None, API_KEY_FILE_ENV, lookup).map(|(path, _)| PathBuf::from(path))
+        });
+        if let Some(key_file) = key_file {
+            return read_api_key_file(&key_file).map(|key| Some((key, ValueSource::File)));
+        }
+        Ok(resolve_value(None, API_KEY_ENV, lookup))
+    }
+
+    /// workspace 未设置时读取环境变量 DASHSCOPE_WORKSPACE_ID
+    fn resolve_workspace(&self, lookup: &impl Fn(&str) -> Option<String>) -> Option<String> {
+        resolve_value(self.workspace.as_deref(), WORKSPACE_ENV, lookup).map(|(v, _)| v)
+    }
+
+    fn resolved(
+        &self,
+        api_key: &Option<(String, ValueSource)>,
+        url: &Option<(String, ValueSource)>,
+        workspace: Option<&str>,
+    ) -> EffectiveConfig {
+        EffectiveConfig::resolve(
+            &self.model_name,
+            api_key.as_ref().map(|(key, source)| (key.as_str(), *source)),
+            url.as_ref().map(|(url, source)| (url.as_str(), *source)),
+            workspace,
+            self.connect_options.api_version.as_deref(),
+        )
+    }
+
+    /// 按 connect 的规则解析API Key和url并构造握手请求, 不建立连接
+    pub(crate) fn handshake_request(&self) -> Result<Request, QwenTtsError> {
+        let lookup = |key: &str| std::env::var(key).ok();
+        let (api_key, _) = self.resolve_api_key(&lookup)?.ok_or(QwenTtsError::MissingApiKey)?;
+        let url = resolve_value(self.url.as_deref(), REALTIME_URL_ENV, &lookup);
+        let url = QwenTtsRealtime::build_url(
+            url.as_ref().map(|(url, _)| url.as_str()),
+            &self.model_name,
+            self.connect_options.api_version.as_deref(),
+        )?;
+        QwenTtsRealtime::build_request(
+            &url,
+            &api_key,
+            self.resolve_workspace(&lookup).as_deref(),
+            self.connect_options.minimal_user_agent,
+        )
+    }
+
+    /// 与 connect 相同
+    pub async fn build(self) -> Result<QwenTtsRealtime, QwenTtsError> {
+        self.connect().await
+    }
+
+    pub async fn connect(self) -> Result<QwenTtsRealtime, QwenTtsError> {
+        let next_session =
+            (self.after_finish == AfterFinishPolicy::NewSession).then(|| Box::new(self.clone()));
+        let lookup = |key: &str| std::env::var(key).ok();
+        let api_key = self.resolve_api_key(&lookup)?;
+        let url = resolve_value(self.url.as_deref(), REALTIME_URL_ENV, &lookup);
+        let workspace = self.resolve_workspace(&lookup);
+        let effective = self.resolved(&api_key, &url, workspace.as_deref());
+        let (api_key, _) = api_key.ok_or(QwenTtsError::MissingApiKey)?;
+        let full_url = QwenTtsRealtime::build_url(
+            url.as_ref().map(|(url, _)| url.as_str()),
+            &self.model_name,
+            self.connect_options.api_version.as_deref(),
+        )?;
+        let reconnect = self.reconnect.map(|policy| ReconnectTarget {
+            url: full_url,
+            api_key: api_key.clone(),
+            workspace: workspace.clone(),
+            minimal_user_agent: self.connect_options.minimal_user_agent,
+            connect_timeout: self
+                .connect_options
+                .connect_timeout
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            policy,
+        });
+        let mut qwen_tts_realtime = QwenTtsRealtime::try_connect(
+            self.model_name.as_str(),
+            api_key.as_str(),
+            url.as_ref().map(|(url, _)| url.as_str()),
+            workspace.as_deref(),
+            self.callback,
+            ConnectOptions {
+                dns_retry: self.reconnect,
+                ..self.connect_options
+            },
+        )
+        .await?;
+        qwen_tts_realtime.effective = effective;
+        qwen_tts_realtime.next_session = next_session;
+        qwen_tts_realtime.delivery_policy = self.delivery_policy;
+        qwen_tts_realtime.overflow_policy = self.overflow_policy;
+        qwen_tts_realtime.audio_buffer_capacity = self.audio_buffer_capacity;
+        {
+            let mut shared = qwen_tts_realtime.shared.lock().unwrap();
+            shared.middlewares = self.middlewares;
+            shared.sample_transforms = self.sample_transforms;
+            shared.response_retry_max = self.response_retry;
+            shared.trailing_silence = self.pad_trailing_silence;
+            shared.max_append_bytes = self.max_append_bytes;
+            shared.max_audio_bytes = self.max_audio_bytes;
+            shared.finish_audio_timeout = self.finish_audio_timeout;
+            shared.finished_grace = self.finished_grace;
+            shared.frame_alignment = self.frame_alignment;
+            shared.reconnect = reconnect;
+            shared.audit = self.audit_sink.map(|sink| {
+                Auditor::new(sink, &qwen_tts_realtime.connection_id, self.audit_include_text)
+            });
+        }
+        let audit = qwen_tts_realtime.shared.lock().unwrap().audit.clone();
+        if let Some(audit) = audit {
+            audit.record(AuditAction::Connected {
+                model: qwen_tts_realtime.effective.model.clone(),
+                url: qwen_tts_realtime.effective.url.clone(),
+            });
+        }
+        if let Some(idle) = self.idle_resend {
+            tokio::spawn(QwenTtsRealtime::idle_watchdog(
+                Arc::clone(&qwen_tts_realtime.stream_writer),
+                Arc::clone(&qwen_tts_realtime.shared),
+                idle,
+            ));
+        }
+        Ok(qwen_tts_realtime)
+    }
+}
+
+/// 与 from_env 相同
+impl Default for QwenTtsRealtimeBuilder {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// verify_credentials 等待 session.created 的最长时间, 超时则以握手成功为准
+const VERIFY_CREATED_TIMEOUT: Duration = Duration::from_secs(5);
+
+///
+/// 只等待 session.created 的探测回调
+struct ProbeCallback {
+    created: Option<oneshot::Sender<()>>,
+}
+
+impl QwenTtsRealtimeCallback for ProbeCallback {
+    fn on_open(&self) {}
+
+    fn on_close(&self, close_msg: &str) {
+        log::info!("verify_credentials connection closed: {}", close_msg);
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            return false;
+        };
+        if v["type"] == "session.created" {
+            if let Some(created) = self.created.take() {
+                let _ = created.send(());
+            }
+            return true;
+        }
+        false
+    }
+}
+
+///
+/// 轻量的鉴权预检: 建立连接并等待 session.created 后立即关闭, 不会开始合成
+///
+/// API Key 无效时返回 QwenTtsError::Unauthorized, 适合CLI启动前快速检查
+pub async fn verify_credentials(api_key: &str) -> Result<(), QwenTtsError> {
+    verify_credentials_with_url(api_key, None).await
+}
+
+pub(crate) async fn verify_credentials_with_url(
+    api_key: &str,
+    url: Option<&str>,
+) -> Result<(), QwenTtsError> {
+    let (created_tx, created_rx) = oneshot::channel();
+    let callback: SharedCallback = Arc::new(Mutex::new(Box::new(ProbeCallback {
+        created: Some(created_tx),
+    })));
+    let qwen_tts_realtime = QwenTtsRealtime::try_connect(
+        "qwen3-tts-flash-realtime",
+        api_key,
+        url,
+        None,
+        Some(CallbackDispatch::Sync(callback)),
+        ConnectOptions::default(),
+    )
+    .await?;
+    match tokio::time::timeout(VERIFY_CREATED_TIMEOUT, created_rx).await {
+        Ok(Ok(())) => log::info!("verify_credentials: 收到session.created, 鉴权通过"),
+        Ok(Err(_)) => {
+            return Err(QwenTtsError::SessionClosedEarly(
+                "连接在收到session.created之前断开".to_string(),
+            ));
+        }
+        Err(_) => log::warn!("verify_credentials: 等待session.created超时, 以握手成功为准"),
+    }
+    if let Err(e) = qwen_tts_realtime.stream_writer.lock().await.close().await {
+        log::warn!("verify_credentials: 关闭连接失败: {}", e);
+    }
+    Ok(())
+}
+
+///
+/// 初始化日志并按 QwenTtsRealtime::from_env 连接;
+/// 未设置环境变量时返回 MissingApiKey, 日志中的API Key会脱敏
+pub async fn prepare_qwen_tts_realtime(
+    callback: Option<SharedCallback>,
+) -> Result<QwenTtsRealtime, QwenTtsError> {
+    init_logger("info");
+    let qwen_tts_realtime = QwenTtsRealtime::from_env(callback).await?;
+    log::info!("api_key: {}", qwen_tts_realtime.effective_config().api_key);
+    Ok(qwen_tts_realtime)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::transforms::{AlignMode, GainTransform};
+    use crate::dashscope::mock_server::{
+        MockAction, MockServerBuilder, RecordingCallback, audio_delta, default_handler, event,
+        spawn_blackhole_server,
+    };
+
+    struct TraceMiddleware;
+
+    impl EventMiddleware for TraceMiddleware {
+        fn before_send(&self, event: &mut Value) -> Result<(), QwenTtsError> {
+            event["trace_id"] = json!("trace_test");
+            Ok(())
+        }
+    }
+
+    struct RejectFinishMiddleware;
+
+    impl EventMiddleware for RejectFinishMiddleware {
+        fn before_send(&self, event: &mut Value) -> Result<(), QwenTtsError> {
+            if event["type"] == "session.finish" {
+                return Err(QwenTtsError::MiddlewareRejected(
+                    "finish is not allowed".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_duration_byte_conversion() {
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        assert_eq!(format.bytes_for_duration(Duration::from_secs(1)), 48000);
+        assert_eq!(format.bytes_for_duration(Duration::from_millis(500)), 24000);
+        assert_eq!(format.bytes_for_duration(Duration::from_micros(50)), 2);
+        assert_eq!(format.duration_for_bytes(48000), Duration::from_secs(1));
+        assert_eq!(format.duration_for_bytes(48001), Duration::from_secs(1));
+        assert_eq!(format.duration_for_bytes(4800), Duration::from_millis(100));
+
+        let stereo = AudioFormat::new("pcm", 16000, "stereo", "16bit", "pcm16");
+        assert_eq!(
+            (stereo.format(), stereo.channels(), stereo.bit_rate(), stereo.format_str()),
+            ("pcm", "stereo", "16bit", "pcm16")
+        );
+        assert_eq!(stereo.channel_count(), 2);
+        assert_eq!(stereo.bytes_for_duration(Duration::from_secs(1)), 64000);
+        assert_eq!(stereo.duration_for_bytes(6400), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_wav_header_bytes() {
+        let u32_at = |h: &[u8], i: usize| u32::from_le_bytes(h[i..i + 4].try_into().unwrap());
+        let u16_at = |h: &[u8], i: usize| u16::from_le_bytes([h[i], h[i + 1]]);
+
+        let header = AudioFormat::PCM_24000HZ_MONO_16BIT.wav_header(48000);
+        assert_eq!(header.len(), 44);
+        assert_eq!(
+            (&header[0..4], &header[8..16], &header[36..40]),
+            (&b"RIFF"[..], &b"WAVEfmt "[..], &b"data"[..])
+        );
+        assert_eq!(u32_at(&header, 4), 48000 + 36);
+        assert_eq!(u16_at(&header, 22), 1);
+        assert_eq!(u32_at(&header, 24), 24000);
+        assert_eq!(u32_at(&header, 28), 48000);
+        assert_eq!(u16_at(&header, 32), 2);
+        assert_eq!(u16_at(&header, 34), 16);
+        assert_eq!(u32_at(&header, 40), 48000);
+
+        let stereo = AudioFormat::new("pcm", 16000, "stereo", "24bit", "pcm24");
+        let header = stereo.wav_header(0);
+        assert_eq!(u16_at(&header, 22), 2);
+        assert_eq!(u32_at(&header, 28), 16000 * 6);
+        assert_eq!(u16_at(&header, 32), 6);
+        assert_eq!(u16_at(&header, 34), 24);
+        assert_eq!(u32_at(&header, 40), 0);
+    }
+
+    #[test]
+    fn test_audio_format_from_str() {
+        let cases = [
+            ("pcm_24000_mono_16bit", "pcm", 24000),
+            ("PCM_24000HZ_MONO_16BIT", "pcm", 24000),
+            ("pcm_8000", "pcm", 8000),
+            ("pcm_16000", "pcm", 16000),
+            ("pcm_48000_mono", "pcm", 48000),
+            ("wav_24000", "wav", 24000),
+            ("mp3_24000", "mp3", 24000),
+            ("opus_48000", "opus", 48000),
+        ];
+        for (input, format, sample_rate) in cases {
+            let parsed = input.parse::<AudioFormat>().unwrap();
+            assert_eq!(parsed.format(), format, "{}", input);
+            assert_eq!(parsed.sample_rate(), sample_rate, "{}", input);
+            assert_eq!(parsed.channel_count(), 1);
+            assert_eq!(parsed.bits_per_sample(), 16);
+        }
+        assert_eq!(
+            "pcm_24000_mono_16bit".parse::<AudioFormat>().unwrap(),
+            AudioFormat::PCM_24000HZ_MONO_16BIT
+        );
+        assert_eq!(
+            AudioFormat::PCM_24000HZ_MONO_16BIT.to_string().parse::<AudioFormat>(),
+            Ok(AudioFormat::PCM_24000HZ_MONO_16BIT)
+        );
+        let invalid_inputs = [
+            "flac_24000",
+            "pcm",
+            "pcm_22050",
+            "pcm_24000_stereo",
+            "pcm_24000_mono_16bit_x",
+            "",
+        ];
+        for invalid in invalid_inputs {
+            let err = invalid.parse::<AudioFormat>().unwrap_err();
+            assert!(err.to_string().contains("无法识别的音频格式"));
+        }
+    }
+
+    #[test]
+    fn test_reconcile_audio_format() {
+        let frame = json!({"type": "response.audio.delta", "delta": "", "sample_rate": 16000});
+        let (confirmed, mismatches) =
+            reconcile_audio_format(AudioFormat::PCM_24000HZ_MONO_16BIT, &frame);
+        assert_eq!(confirmed.sample_rate(), 16000);
+        assert_eq!(confirmed.format(), "pcm");
+        assert_eq!(mismatches, vec!["sample_rate: 请求 24000, 实际 16000"]);
+
+        let frame = json!({"type": "response.audio.delta", "delta": ""});
+        let (confirmed, mismatches) =
+            reconcile_audio_format(AudioFormat::PCM_24000HZ_MONO_16BIT, &frame);
+        assert_eq!(confirmed, AudioFormat::PCM_24000HZ_MONO_16BIT);
+        assert!(mismatches.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_format_from_first_frame() {
+        let server = MockServerBuilder::new()
+            .handler(|event| match event["type"].as_str() {
+                Some("input_text_buffer.append") => vec![MockAction::Text(
+                    json!({
+                        "type": "response.audio.delta",
+                        "delta": "AAAA",
+                        "audio_format": {"format": "pcm", "sample_rate": 16000},
+                    })
+                    .to_string(),
+                )],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session(
+                "Cherry",
+                AudioFormat::PCM_24000HZ_MONO_16BIT,
+                "server_commit",
+            )
+            .await
+            .unwrap();
+        assert_eq!(qwen_tts_realtime.confirmed_format(), None);
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        for _ in 0..50 {
+            if qwen_tts_realtime.confirmed_format().is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let confirmed = qwen_tts_realtime.confirmed_format().unwrap();
+        assert_eq!(confirmed.sample_rate(), 16000);
+        assert_eq!(confirmed.format(), "pcm");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_handshake() {
+        let server = MockServerBuilder::new()
+            .reject(429, vec![("retry-after", "7")])
+            .spawn()
+            .await;
+        let result = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await;
+        let Err(e) = result else {
+            panic!("handshake should be rejected");
+        };
+        assert!(e.is_transient());
+        assert_eq!(e.retry_after(), Some(Duration::from_secs(7)));
+        assert!(matches!(e, QwenTtsError::RateLimited { status: 429, .. }));
+    }
+
+    #[test]
+    fn test_build_session_update() {
+        let msg = build_session_update(
+            "event_1",
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            "server_commit",
+        );
+        assert_eq!(
+            msg,
+            json!({
+                "event_id": "event_1",
+                "type": "session.update",
+                "session": {
+                    "voice": "Cherry",
+                    "mode": "server_commit",
+                    "response_format": "pcm",
+                    "sample_rate": 24000,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_session_update_per_format() {
+        let cases = [
+            (
+                AudioFormat::PCM_24000HZ_MONO_16BIT,
+                json!({"response_format": "pcm", "sample_rate": 24000}),
+            ),
+            (
+                AudioFormat::MP3_16000HZ_MONO,
+                json!({"response_format": "mp3", "sample_rate": 16000, "bit_rate": 128}),
+            ),
+            (
+                AudioFormat::MP3_24000HZ_MONO,
+                json!({"response_format": "mp3", "sample_rate": 24000, "bit_rate": 128}),
+            ),
+            (
+                AudioFormat::MP3_48000HZ_MONO,
+                json!({"response_format": "mp3", "sample_rate": 48000, "bit_rate": 128}),
+            ),
+            (
+                AudioFormat::PCM_16000HZ_MONO_16BIT,
+                json!({"response_format": "pcm", "sample_rate": 16000}),
+            ),
+            (
+                AudioFormat::OPUS_24000HZ_MONO,
+                json!({"response_format": "opus", "sample_rate": 24000, "bit_rate": 32}),
+            ),
+        ];
+        for (format, expected) in cases {
+            let mut expected_session = json!({"voice": "Cherry", "mode": "server_commit"});
+            for (key, value) in expected.as_object().unwrap() {
+                expected_session[key] = value.clone();
+            }
+            let msg = build_session_update("event_1", "Cherry", format, "server_commit");
+            assert_eq!(msg["session"], expected_session, "{}", format);
+            // 协议不认识 pcm16 等 format_str, response_format 只发送 format
+            assert_eq!(msg["session"]["response_format"], format.format(), "{}", format);
+            assert!(!msg.to_string().contains("pcm16"), "{}", format);
+        }
+        assert_eq!(
+            "mp3_24000".parse::<AudioFormat>(),
+            Ok(AudioFormat::MP3_24000HZ_MONO)
+        );
+        assert_eq!(
+            AudioFormat::MP3_24000HZ_MONO.to_string().parse::<AudioFormat>(),
+            Ok(AudioFormat::MP3_24000HZ_MONO)
+        );
+        assert_eq!(
+            "opus_24000".parse::<AudioFormat>(),
+            Ok(AudioFormat::OPUS_24000HZ_MONO)
+        );
+        assert_eq!(
+            "pcm_16000".parse::<AudioFormat>(),
+            Ok(AudioFormat::PCM_16000HZ_MONO_16BIT)
+        );
+        assert_eq!(AudioFormat::PCM_24000HZ_MONO_16BIT.bit_rate_kbps(), None);
+    }
+
+    #[tokio::test]
+    async fn test_voice_names_in_session_update() {
+        assert_eq!(Voice::from("cherry"), Voice::Cherry);
+        assert_eq!(" ETHAN ".parse::<Voice>(), Ok(Voice::Ethan));
+        assert_eq!(Voice::from("my-clone"), Voice::Custom("my-clone".to_string()));
+
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        qwen_tts_realtime.update_session("cherry", format, "commit").await.unwrap();
+        qwen_tts_realtime.update_session(Voice::Dylan, format, "commit").await.unwrap();
+        qwen_tts_realtime.update_session("my-clone", format, "commit").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        let voices = server
+            .received_json()
+            .iter()
+            .filter(|event| event["type"] == "session.update")
+            .map(|event| event["session"]["voice"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(voices, vec![json!("Cherry"), json!("Dylan"), json!("my-clone")]);
+    }
+
+    #[tokio::test]
+    async fn test_mp3_session_update_json() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::MP3_24000HZ_MONO, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        let received = server.received_json();
+        assert_eq!(received[0]["type"], "session.update");
+        assert_eq!(
+            received[0]["session"],
+            json!({
+                "voice": "Cherry",
+                "mode": "server_commit",
+                "response_format": "mp3",
+                "sample_rate": 24000,
+                "bit_rate": 128,
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_session_update_language_type() {
+        let config = SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .language_type("English");
+        let msg = build_session_update_from("event_1", &config);
+        assert_eq!(msg["session"]["language_type"], "English");
+        let msg = build_session_update("event_1", "Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit");
+        assert!(msg["session"].get("language_type").is_none());
+    }
+
+    #[test]
+    fn test_build_session_update_extra_params() {
+        let config = SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .extra_param("speech_rate", 1.2)
+            .extra_param("instructions", json!({"tone": "calm"}))
+            .extra_param("voice", "Ethan");
+        let msg = build_session_update_from("event_1", &config);
+        assert_eq!(msg["session"]["speech_rate"], 1.2);
+        assert_eq!(msg["session"]["instructions"], json!({"tone": "calm"}));
+        // 已建模的字段优先
+        assert_eq!(msg["session"]["voice"], "Cherry");
+    }
+
+    #[test]
+    fn test_build_session_update_style() {
+        let config = SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .style(Style::Cheerful);
+        let msg = build_session_update_from("event_1", &config);
+        assert_eq!(msg["session"]["style"], "cheerful");
+        let config = config.style(Style::Custom("whisper".to_string()));
+        let msg = build_session_update_from("event_1", &config);
+        assert_eq!(msg["session"]["style"], "whisper");
+        let msg = build_session_update("event_1", "Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit");
+        assert!(msg["session"].get("style").is_none());
+        assert_eq!(Style::from("serious"), Style::Serious);
+    }
+
+    #[test]
+    fn test_build_session_update_seed() {
+        let config = SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .seed(42);
+        let msg = build_session_update_from("event_1", &config);
+        assert_eq!(msg["session"]["seed"], 42);
+        let msg = build_session_update("event_1", "Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit");
+        assert!(msg["session"].get("seed").is_none());
+    }
+
+    #[test]
+    fn test_build_append_text() {
+        assert_eq!(
+            build_append_text("event_2", "你好"),
+            json!({
+                "event_id": "event_2",
+                "type": "input_text_buffer.append",
+                "text": "你好",
+            })
+        );
+    }
+
+    #[test]
+    fn test_build_finish() {
+        assert_eq!(
+            build_finish("event_3"),
+            json!({"event_id": "event_3", "type": "session.finish"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reader_task_name() {
+        let server = MockServerBuilder::new().spawn().await;
+        let qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let connection_id = qwen_tts_realtime.connection_id();
+        assert!(Uuid::parse_str(connection_id).is_ok());
+        assert_eq!(
+            QwenTtsRealtime::reader_task_name(connection_id),
+            format!("qwen-tts-reader-{}", connection_id)
+        );
+    }
+
+    #[test]
+    fn test_build_url_api_version() {
+        assert_eq!(
+            QwenTtsRealtime::build_url(None, "qwen3-tts-flash-realtime", None).unwrap(),
+            "wss://dashscope.aliyuncs.com/api-ws/v1/realtime?model=qwen3-tts-flash-realtime"
+        );
+        assert_eq!(
+            QwenTtsRealtime::build_url(Some("ws://127.0.0.1:8080/rt"), "m", Some("2025-09-01"))
+                .unwrap(),
+            "ws://127.0.0.1:8080/rt?model=m&api_version=2025-09-01"
+        );
+    }
+
+    #[test]
+    fn test_build_url_query_and_scheme() {
+        // 保留代理需要的查询参数, 覆盖已有的 model, 去掉末尾的 /
+        let url = Some("ws://127.0.0.1:8080/rt/?token=abc&model=old");
+        assert_eq!(
+            QwenTtsRealtime::build_url(url, "m", None).unwrap(),
+            "ws://127.0.0.1:8080/rt?model=m&token=abc"
+        );
+        assert_eq!(
+            QwenTtsRealtime::build_url(Some("wss://proxy.example.com"), "m", None).unwrap(),
+            "wss://proxy.example.com/?model=m"
+        );
+        for url in ["https://dashscope.aliyuncs.com/api-ws/v1/realtime", "not a url", ""] {
+            let result = QwenTtsRealtime::build_url(Some(url), "m", None);
+            assert!(matches!(result, Err(QwenTtsError::InvalidUrl(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_effective_config_precedence() {
+        let env = |key: &str| match key {
+            API_KEY_ENV => Some("sk-env-0000000000".to_string()),
+            REALTIME_URL_ENV => Some("wss://dashscope-intl.aliyuncs.com/api-ws/v1/realtime".to_string()),
+            _ => None,
+        };
+        // 参数优先于环境变量
+        let effective = QwenTtsRealtimeBuilder::new("sk-param-1234567890")
+            .url("ws://127.0.0.1:8080/rt")
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::Param));
+        assert_eq!(effective.api_key, "sk-***7890");
+        assert_eq!(effective.url_source, ValueSource::Param);
+        assert_eq!(effective.url, "ws://127.0.0.1:8080/rt?model=qwen3-tts-flash-realtime");
+        assert_eq!(effective.region, "custom");
+
+        let effective = QwenTtsRealtimeBuilder::from_env().effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::Env));
+        assert_eq!(effective.api_key, "sk-***0000");
+        assert_eq!(effective.url_source, ValueSource::Env);
+        assert_eq!(effective.region, "ap-southeast-1");
+
+        // 链式设置的API Key同样优先于环境变量, 空字符串回退到环境变量
+        let effective = QwenTtsRealtime::builder()
+            .api_key("sk-chain-1234567890")
+            .model("qwen-tts-realtime")
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::Param));
+        assert_eq!(effective.api_key, "sk-***7890");
+        assert!(effective.url.ends_with("?model=qwen-tts-realtime"));
+        let effective = QwenTtsRealtimeBuilder::default()
+            .api_key("")
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::Env));
+
+        // 环境变量的首尾空白(如复制时带入的换行)会被去掉, 只有空白视为未设置
+        let padded = |key: &str| match key {
+            API_KEY_ENV => Some("  sk-env-0000000000\n".to_string()),
+            WORKSPACE_ENV => Some(" ws-env ".to_string()),
+            _ => None,
+        };
+        let effective = QwenTtsRealtimeBuilder::from_env().effective_config_with(padded);
+        assert_eq!(effective.api_key, "sk-***0000");
+        assert_eq!(effective.workspace.as_deref(), Some("ws-env"));
+        let effective = QwenTtsRealtimeBuilder::from_env()
+            .workspace("ws-param")
+            .effective_config_with(padded);
+        assert_eq!(effective.workspace.as_deref(), Some("ws-param"));
+        let blank = |_: &str| Some(" \t\n".to_string());
+        let effective = QwenTtsRealtimeBuilder::from_env().effective_config_with(blank);
+        assert_eq!(effective.api_key_source, None);
+        assert_eq!(effective.workspace, None);
+
+        let effective = QwenTtsRealtimeBuilder::from_env().effective_config_with(|_| None);
+        assert_eq!(effective.api_key_source, None);
+        assert_eq!(effective.url_source, ValueSource::Default);
+        assert_eq!(effective.region, "cn-beijing");
+        assert_eq!(effective.voice, None);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_file() {
+        let path = std::env::temp_dir().join(format!("qwen-tts-key-{}", Uuid::new_v4()));
+        std::fs::write(&path, "  sk-file-1234567890\n").unwrap();
+        let env = |key: &str| match key {
+            API_KEY_ENV => Some("sk-env-0000000000".to_string()),
+            _ => None,
+        };
+        // 密钥文件优先于环境变量, 参数优先于密钥文件
+        let effective = QwenTtsRealtimeBuilder::from_env()
+            .api_key_file(&path)
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::File));
+        assert_eq!(effective.api_key, "sk-***7890");
+        let effective = QwenTtsRealtimeBuilder::new("sk-param-1234567890")
+            .api_key_file(&path)
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, Some(ValueSource::Param));
+        // 也可以由环境变量 DASHSCOPE_API_KEY_FILE 指定
+        let path_str = path.to_str().unwrap().to_string();
+        let key_file_env = |key: &str| match key {
+            API_KEY_FILE_ENV => Some(path_str.clone()),
+            _ => env(key),
+        };
+        let resolved = QwenTtsRealtimeBuilder::from_env().resolve_api_key(&key_file_env).unwrap();
+        assert_eq!(resolved, Some(("sk-file-1234567890".to_string(), ValueSource::File)));
+
+        // 文件不存在或为空时报错, 不回退到环境变量
+        let missing = path.with_extension("missing");
+        let builder = QwenTtsRealtimeBuilder::from_env().api_key_file(&missing);
+        let error = builder.resolve_api_key(&env).unwrap_err();
+        assert!(
+            matches!(&error, QwenTtsError::ApiKeyFile { path, .. } if path.ends_with("missing")),
+            "{:?}",
+            error
+        );
+        assert!(matches!(builder.connect().await, Err(QwenTtsError::ApiKeyFile { .. })));
+        let effective = QwenTtsRealtimeBuilder::from_env()
+            .api_key_file(&missing)
+            .effective_config_with(env);
+        assert_eq!(effective.api_key_source, None);
+        std::fs::write(&path, " \n").unwrap();
+        let error = QwenTtsRealtimeBuilder::from_env()
+            .api_key_file(&path)
+            .resolve_api_key(&env)
+            .unwrap_err();
+        assert!(error.to_string().contains("文件内容为空"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_effective_config_after_update() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .await
+            .unwrap();
+        let effective = qwen_tts_realtime.effective_config();
+        assert_eq!(effective.api_key, "tes***-key");
+        assert_eq!(effective.url_source, ValueSource::Param);
+        assert_eq!(effective.voice.as_deref(), Some("Cherry"));
+        assert_eq!(effective.response_format, Some(AudioFormat::PCM_24000HZ_MONO_16BIT));
+        assert_eq!(effective.mode.as_deref(), Some("commit"));
+    }
+
+    #[test]
+    fn test_reconnect_hint() {
+        assert!(reconnect_hint(Some(1012), &Value::Null));
+        assert!(reconnect_hint(Some(1013), &Value::Null));
+        assert!(!reconnect_hint(Some(1011), &Value::Null));
+        assert!(reconnect_hint(None, &json!({"reconnect": true})));
+        assert!(reconnect_hint(
+            None,
+            &json!({"type": "error", "error": {"code": "ServiceRestart", "reconnect": true}})
+        ));
+        assert!(!reconnect_hint(None, &json!({"type": "error", "error": {"reconnect": false}})));
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let later = chrono::Utc::now() + chrono::Duration::seconds(30);
+        let delay = parse_retry_after(&later.to_rfc2822()).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_plain_ws_handshake_headers() {
+        let server = MockServerBuilder::new().spawn().await;
+        assert!(server.url.starts_with("ws://"));
+        let _qwen_tts_realtime = QwenTtsRealtime::new(
+            "qwen3-tts-flash-realtime",
+            "test-api-key",
+            Some(server.url.as_str()),
+            Some("ws-on-prem"),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            server.header("authorization").as_deref(),
+            Some("bearer test-api-key")
+        );
+        assert_eq!(
+            server.header("x-dashscope-workspace").as_deref(),
+            Some("ws-on-prem")
+        );
+        assert!(
+            server
+                .header("user-agent")
+                .is_some_and(|ua| ua.starts_with("dashscope/"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_with_channel_collects_audio() {
+        let server = MockServerBuilder::new().spawn().await;
+        let (mut qwen_tts_realtime, mut audio_rx) = QwenTtsRealtime::new_with_channel(
+            "qwen3-tts-flash-realtime",
+            "test-api-key",
+            Some(server.url.as_str()),
+            None,
+        )
+        .await
+        .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.append_text("世界").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let collect = async {
+            let mut audio = vec![];
+            while let Some(audio_bytes) = audio_rx.recv().await {
+                audio.extend_from_slice(&audio_bytes);
+            }
+            audio
+        };
+        // 通道在会话结束后关闭, 收集不会一直挂起
+        let audio = tokio::time::timeout(Duration::from_secs(5), collect)
+            .await
+            .unwrap();
+        assert_eq!(audio, "你好世界".as_bytes());
+        assert!(qwen_tts_realtime.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_audio_channel_closes_on_session_finished() {
+        // 服务端在 session.finished 之后迟迟不关闭连接
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.finish") => vec![
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Sleep(Duration::from_secs(10)),
+                ],
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let (mut qwen_tts_realtime, mut audio_rx) = QwenTtsRealtime::new_with_channel(
+            "qwen3-tts-flash-realtime",
+            "test-api-key",
+            Some(server.url.as_str()),
+            None,
+        )
+        .await
+        .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let first = tokio::time::timeout(Duration::from_secs(2), audio_rx.recv()).await.unwrap();
+        assert_eq!(first.unwrap(), "你好".as_bytes());
+        let end = tokio::time::timeout(Duration::from_secs(2), audio_rx.recv()).await.unwrap();
+        assert_eq!(end, None);
+        assert!(qwen_tts_realtime.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_deferred_text_sent_once_on_commit() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        for text in ["第一句。", "第二句。", "第三句。"] {
+            qwen_tts_realtime.append_text_deferred(text);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.received_types(), vec!["session.update"]);
+        qwen_tts_realtime.commit().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let received = server.received_json();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1]["type"], "input_text_buffer.append");
+        assert_eq!(received[1]["text"], "第一句。第二句。第三句。");
+
+        // commit 模式下还需要提交; finish 时发送尚未提交的暂存文本
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text_deferred("你好");
+        qwen_tts_realtime.commit().await.unwrap();
+        qwen_tts_realtime.append_text_deferred("再见");
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        assert_eq!(
+            server.received_types()[2..],
+            [
+                "session.update",
+                "input_text_buffer.append",
+                "input_text_buffer.commit",
+                "input_text_buffer.append",
+                "session.finish",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_callback() {
+        let server = MockServerBuilder::new()
+            .handler(|e| match e["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(
+                        json!({
+                            "type": "response.audio_transcript.delta",
+                            "delta": "你好",
+                            "words": [
+                                {"text": "你", "begin_time": 0, "end_time": 200},
+                                {"text": "好", "begin_time": 200, "end_time": 450},
+                            ],
+                        })
+                        .to_string(),
+                    ),
+                    MockAction::Text(audio_delta(b"pcm")),
+                    MockAction::Text(event("response.done")),
+                ],
+                _ => default_handler(e),
+            })
+            .spawn()
+            .await;
+        let recording = RecordingCallback::new();
+        let timestamps = Arc::clone(&recording.timestamps);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(Mutex::new(Box::new(recording))))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        let marks = timestamps
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|mark| (mark.text.clone(), mark.start_ms, mark.end_ms))
+            .collect::<Vec<_>>();
+        assert_eq!(marks, vec![("你".to_string(), 0, 200), ("好".to_string(), 200, 450)]);
+    }
+
+    #[tokio::test]
+    async fn test_late_audio_within_finished_grace() {
+        // 最后一个分片在 session.finished 之后才到达, 连接过一段时间才关闭
+        let server = MockServerBuilder::new()
+            .handler(|e| match e["type"].as_str() {
+                Some("session.finish") => vec![
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Sleep(Duration::from_millis(50)),
+                    MockAction::Text(audio_delta(b"tail")),
+                    MockAction::Sleep(Duration::from_secs(2)),
+                    MockAction::Close(1000, "bye".to_string()),
+                ],
+                _ => default_handler(e),
+            })
+            .spawn()
+            .await;
+        for (grace, expected) in [(None, "你好tail"), (Some(Duration::ZERO), "你好")] {
+            let mut builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+            if let Some(grace) = grace {
+                builder = builder.finished_grace(grace);
+            }
+            let mut qwen_tts_realtime = builder.connect().await.unwrap();
+            let audio = qwen_tts_realtime.audio_stream();
+            qwen_tts_realtime.append_text("你好").await.unwrap();
+            qwen_tts_realtime.finish().await.unwrap();
+            // 宽限期结束后音频流结束, 不需要等连接关闭
+            let chunks = tokio::time::timeout(Duration::from_secs(1), audio.collect::<Vec<_>>())
+                .await
+                .unwrap();
+            assert_eq!(chunks.concat(), expected.as_bytes(), "{:?}", grace);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_subscribers_share_chunks() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtime::builder()
+            .api_key("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let mut first = qwen_tts_realtime.subscribe();
+        let mut second = qwen_tts_realtime.subscribe();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.append_text("世界").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        for rx in [&mut first, &mut second] {
+            for expected in ["你好", "世界"] {
+                let chunk = tokio::time::timeout(Duration::from_secs(2), rx.recv()).await;
+                assert_eq!(chunk.unwrap().unwrap().as_slice(), expected.as_bytes());
+            }
+            let end = tokio::time::timeout(Duration::from_secs(2), rx.recv()).await;
+            assert!(matches!(end.unwrap(), Err(broadcast::error::RecvError::Closed)));
+        }
+        // 会话结束后订阅, 立即得到 Closed
+        let mut late = qwen_tts_realtime.subscribe();
+        assert!(matches!(late.recv().await, Err(broadcast::error::RecvError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_dns_failure_distinct_from_refused() {
+        let result = QwenTtsRealtime::new(
+            "qwen3-tts-flash-realtime",
+            "test-api-key",
+            Some("ws://nonexistent.invalid/api-ws/v1/realtime"),
+            None,
+            None,
+        )
+        .await;
+        assert!(
+            matches!(&result, Err(QwenTtsError::DnsFailure { host, .. })
+                if host == "nonexistent.invalid"),
+            "{:?}",
+            result.err()
+        );
+        // 按重连策略重试解析后仍然失败
+        let result = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url("ws://nonexistent.invalid/api-ws/v1/realtime")
+            .with_reconnect(2, Duration::from_millis(10))
+            .connect()
+            .await;
+        assert!(matches!(result, Err(QwenTtsError::DnsFailure { .. })));
+
+        // 端口没有监听时是连接被拒绝, 不是DNS失败
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_addr = listener.local_addr().unwrap();
+        drop(listener);
+        let url = format!("ws://{}/api-ws/v1/realtime", closed_addr);
+        let result =
+            QwenTtsRealtime::new("qwen3-tts-flash-realtime", "test-api-key", Some(&url), None, None)
+                .await;
+        assert!(
+            matches!(&result, Err(QwenTtsError::WebSocketError(tungstenite::Error::Io(e)))
+                if e.kind() == std::io::ErrorKind::ConnectionRefused),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_withholds_audio_until_resume() {
+        let server = MockServerBuilder::new().spawn().await;
+        let callback = RecordingCallback::new();
+        let events = Arc::clone(&callback.events);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(Mutex::new(Box::new(callback))))
+            .connect()
+            .await
+            .unwrap();
+        let mut audio = Box::pin(qwen_tts_realtime.audio_stream());
+        qwen_tts_realtime.append_text("一").await.unwrap();
+        assert_eq!(audio.next().await.unwrap(), "一".as_bytes());
+
+        qwen_tts_realtime.pause();
+        assert!(qwen_tts_realtime.is_paused());
+        qwen_tts_realtime.append_text("二").await.unwrap();
+        qwen_tts_realtime.append_text("三").await.unwrap();
+        // 连接照常读取, 回调收到了音频事件, 但订阅者收不到
+        tokio::time::timeout(Duration::from_secs(2), async {
+            while RecordingCallback::event_types(&events)
+                .iter()
+                .filter(|t| *t == "response.done")
+                .count()
+                < 3
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), audio.next())
+                .await
+                .is_err()
+        );
+
+        qwen_tts_realtime.resume().await;
+        assert_eq!(audio.next().await.unwrap(), "二".as_bytes());
+        assert_eq!(audio.next().await.unwrap(), "三".as_bytes());
+        qwen_tts_realtime.append_text("四").await.unwrap();
+        assert_eq!(audio.next().await.unwrap(), "四".as_bytes());
+
+        // 暂停期间会话结束, resume 后补发完流才结束
+        qwen_tts_realtime.pause();
+        qwen_tts_realtime.append_text("五").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        qwen_tts_realtime.resume().await;
+        assert_eq!(audio.next().await.unwrap(), "五".as_bytes());
+        assert_eq!(audio.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_pings_until_finished() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .heartbeat_interval(Duration::from_millis(50))
+            .connect()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(server.ping_count() >= 2, "{}", server.ping_count());
+        assert!(qwen_tts_realtime.is_connected());
+        // 心跳不会影响正常的事件收发
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        assert_eq!(
+            qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await,
+            FinishOutcome::Finished
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let pings = server.ping_count();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(server.ping_count(), pings);
+
+        // 关闭心跳
+        let server = MockServerBuilder::new().spawn().await;
+        let _qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .heartbeat_interval(Duration::ZERO)
+            .build()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.ping_count(), 0);
+
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key");
+        let disabled = builder.clone().keepalive(false).connect_options.heartbeat_interval;
+        assert_eq!(disabled, Some(Duration::ZERO));
+        let enabled = builder.heartbeat_interval(Duration::ZERO).keepalive(true);
+        assert_eq!(enabled.connect_options.heartbeat_interval, Some(DEFAULT_HEARTBEAT_INTERVAL));
+    }
+
+    #[tokio::test]
+    async fn test_drop_closes_connection() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .heartbeat_interval(Duration::from_millis(50))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        let state = qwen_tts_realtime.watch_state();
+        drop(qwen_tts_realtime);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(server.client_closed_count(), 1);
+        assert_eq!(server.ping_count(), 0);
+        assert_eq!(*state.borrow(), SessionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_skipped_while_sending() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .heartbeat_interval(Duration::from_millis(150))
+            .connect()
+            .await
+            .unwrap();
+        // 持续发送文本时连接不空闲, 不需要心跳
+        for _ in 0..8 {
+            qwen_tts_realtime.append_text("你好").await.unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(server.ping_count(), 0);
+        // 停止发送后恢复心跳
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(server.ping_count() >= 1, "{}", server.ping_count());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout_on_blackhole() {
+        let url = spawn_blackhole_server().await;
+        let started = Instant::now();
+        let result = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&url)
+            .connect_timeout(Duration::from_millis(200))
+            .connect()
+            .await;
+        assert!(matches!(result, Err(QwenTtsError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_finish_audio_timeout() {
+        // finish 之后服务端不再响应
+        let server = MockServerBuilder::new()
+            .handler(|event| match event["type"].as_str() {
+                Some("session.finish") => vec![],
+                _ => default_handler(event),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .finish_audio_timeout(Duration::from_millis(200))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        // finish 之前空闲不会触发
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(qwen_tts_realtime.is_connected());
+        qwen_tts_realtime.finish().await.unwrap();
+        let mut state = qwen_tts_realtime.watch_state();
+        let state = tokio::time::timeout(Duration::from_secs(2), state.wait_for(|s| !s.is_open()))
+            .await
+            .unwrap()
+            .unwrap()
+            .clone();
+        assert!(
+            matches!(&state, SessionState::Failed(error) if error.contains("没有收到音频")),
+            "{:?}",
+            state
+        );
+
+        // 持续收到音频时每个分片重新计时
+        let server = MockServerBuilder::new()
+            .handler(|e| match e["type"].as_str() {
+                Some("session.finish") => vec![
+                    MockAction::Sleep(Duration::from_millis(120)),
+                    MockAction::Text(audio_delta(b"a")),
+                    MockAction::Sleep(Duration::from_millis(120)),
+                    MockAction::Text(audio_delta(b"b")),
+                    MockAction::Sleep(Duration::from_millis(120)),
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Close(1000, "bye".to_string()),
+                ],
+                _ => default_handler(e),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .finish_audio_timeout(Duration::from_millis(200))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        assert_eq!(
+            qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await,
+            FinishOutcome::Finished
+        );
+        assert_eq!(qwen_tts_realtime.state(), SessionState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_watch_state_transitions_in_order() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let mut state = qwen_tts_realtime.state_changes();
+        assert_eq!(*state.borrow_and_update(), SessionState::Connected);
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        assert!(!state.has_changed().unwrap());
+
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        assert!(state.has_changed().unwrap());
+        assert_eq!(*state.borrow_and_update(), SessionState::Active);
+        // 继续发送文本不会重复通知
+        qwen_tts_realtime.append_text("世界").await.unwrap();
+        assert!(!state.has_changed().unwrap());
+
+        qwen_tts_realtime.finish().await.unwrap();
+        let mut observed = vec![];
+        while let Ok(Ok(())) = tokio::time::timeout(Duration::from_secs(5), state.changed()).await {
+            let current = state.borrow_and_update().clone();
+            let open = current.is_open();
+            observed.push(current);
+            if !open {
+                break;
+            }
+        }
+        assert_eq!(observed, vec![SessionState::Finishing, SessionState::Closed]);
+        assert!(!qwen_tts_realtime.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_plain_ws_end_to_end() {
+        let server = MockServerBuilder::new().spawn().await;
+        let callback = RecordingCallback::new();
+        let events = Arc::clone(&callback.events);
+        let finished = Arc::clone(&callback.finished);
+        let mut qwen_tts_realtime = QwenTtsRealtime::new(
+            "qwen3-tts-flash-realtime",
+            "test-api-key",
+            Some(server.url.as_str()),
+            None,
+            Some(Arc::new(Mutex::new(Box::new(callback)))),
+        )
+        .await
+        .unwrap();
+        qwen_tts_realtime
+            .update_session(
+                "Cherry",
+                AudioFormat::PCM_24000HZ_MONO_16BIT,
+                "server_commit",
+            )
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), finished.notified())
+            .await
+            .unwrap();
+        assert_eq!(
+            RecordingCallback::event_types(&events),
+            vec![
+                "session.created",
+                "session.updated",
+                "response.audio.delta",
+                "response.done",
+                "session.finished"
+            ]
+        );
+        assert_eq!(
+            server.received_types(),
+            vec!["session.update", "input_text_buffer.append", "session.finish"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_minimal_user_agent() {
+        let server = MockServerBuilder::new().spawn().await;
+        let _qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .minimal_user_agent(true)
+            .connect()
+            .await
+            .unwrap();
+        let ua = server.header("user-agent").unwrap();
+        assert!(ua.starts_with("dashscope/1.18.0; rust/"));
+        assert!(!ua.contains("platform/"));
+        assert!(!ua.contains("processor/"));
+
+        let full = QwenTtsRealtime::build_user_agent(false);
+        assert!(full.contains(&format!("; platform/{}", std::env::consts::OS)));
+        assert!(full.contains(&format!("; processor/{}", std::env::consts::ARCH)));
+    }
+
+    #[tokio::test]
+    async fn test_audio_stream_base64_passthrough() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let audio_stream = qwen_tts_realtime.audio_stream();
+        let audio_stream_base64 = qwen_tts_realtime.audio_stream_base64();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.append_text("世界").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+
+        let deltas = tokio::time::timeout(
+            Duration::from_secs(5),
+            audio_stream_base64.collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+        let expected = ["你好", "世界"]
+            .iter()
+            .map(|text| {
+                serde_json::from_str::<Value>(&audio_delta(text.as_bytes())).unwrap()["delta"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(deltas, expected);
+        let chunks = tokio::time::timeout(Duration::from_secs(5), audio_stream.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec!["你好".as_bytes().to_vec(), "世界".as_bytes().to_vec()]);
+    }
+
+    async fn deliver_chunks(policy: DeliveryPolicy, chunks: Vec<(Duration, Vec<u8>)>) -> Vec<Vec<u8>> {
+        let (tx, rx) = audio_channel(OverflowPolicy::Block, 16);
+        let (batched_tx, batched_rx) = audio_channel(OverflowPolicy::Block, 16);
+        tokio::spawn(policy.deliver(rx, batched_tx));
+        for (delay, chunk) in chunks {
+            tokio::time::sleep(delay).await;
+            assert_eq!(tx.send(chunk).await, SendOutcome::Delivered);
+        }
+        drop(tx);
+        batched_rx.collect().await
+    }
+
+    #[tokio::test]
+    async fn test_delivery_policy_per_delta() {
+        let batches = deliver_chunks(
+            DeliveryPolicy::PerDelta,
+            vec![(Duration::ZERO, vec![1]), (Duration::ZERO, vec![2, 3])],
+        )
+        .await;
+        assert_eq!(batches, vec![vec![1], vec![2, 3]]);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_policy_accumulate() {
+        let batches = deliver_chunks(
+            DeliveryPolicy::Accumulate { min_bytes: 4 },
+            vec![
+                (Duration::ZERO, vec![1, 2]),
+                (Duration::ZERO, vec![3]),
+                (Duration::ZERO, vec![4, 5]),
+                (Duration::ZERO, vec![6]),
+            ],
+        )
+        .await;
+        // 结束时剩余不足min_bytes的部分也要交付
+        assert_eq!(batches, vec![vec![1, 2, 3, 4, 5], vec![6]]);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_policy_interval() {
+        let batches = deliver_chunks(
+            DeliveryPolicy::Interval {
+                duration: Duration::from_millis(100),
+            },
+            vec![
+                (Duration::from_millis(10), vec![1]),
+                (Duration::from_millis(10), vec![2]),
+                (Duration::from_millis(150), vec![3]),
+                (Duration::from_millis(10), vec![4]),
+            ],
+        )
+        .await;
+        assert_eq!(batches, vec![vec![1, 2], vec![3, 4]]);
+    }
+
+    #[tokio::test]
+    async fn test_event_channel_callback() {
+        let server = MockServerBuilder::new().spawn().await;
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .event_channel(tx)
+            .connect()
+            .await
+            .unwrap();
+        let consumer = tokio::spawn(async move {
+            let mut received = vec![];
+            while let Some(event) = rx.recv().await {
+                // 模拟需要await的异步sink
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                if matches!(event, CallbackEvent::Sent(_)) {
+                    continue;
+                }
+                let done = matches!(event, CallbackEvent::Finish(_));
+                received.push(match event {
+                    CallbackEvent::Event(text) => serde_json::from_str::<Value>(&text).unwrap()
+                        ["type"]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                    other => format!("{:?}", other),
+                });
+                if done {
+                    break;
+                }
+            }
+            received
+        });
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), consumer)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            received,
+            vec![
+                "Open",
+                "session.created",
+                "response.audio.delta",
+                "response.done",
+                "session.finished",
+                "Close(\"Connection closed by server\")",
+                "Finish(\"reader task ended\")",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sample_transform_in_audio_stream() {
+        let pcm = [1000i16.to_le_bytes(), 20000i16.to_le_bytes()].concat();
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![MockAction::Text(audio_delta(&pcm))],
+                Some("session.finish") => vec![MockAction::Close(1000, "bye".to_string())],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .sample_transform(Arc::new(GainTransform(6.0)))
+            .connect()
+            .await
+            .unwrap();
+        let audio_stream = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let chunks = tokio::time::timeout(Duration::from_secs(5), audio_stream.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(
+            chunks,
+            vec![[1995i16.to_le_bytes(), i16::MAX.to_le_bytes()].concat()]
+        );
     }
-}
 
-pub async fn prepare_qwen_tts_realtime(
-    callback: Option<Arc<Mutex<Box<dyn QwenTtsRealtimeCallback + Sync + Send>>>>,
-) -> QwenTtsRealtime {
-    init_logger("info");
-    let api_key = std::env::var("DASHSCOPE_API_KEY").unwrap();
-    log::info!("{}", api_key);
-    QwenTtsRealtime::new(
-        "qwen3-tts-flash-realtime",
-        api_key.as_str(),
-        Some("wss://dashscope.aliyuncs.com/api-ws/v1/realtime"),
-        None,
-        callback,
-    )
+    #[tokio::test]
+    async fn test_response_retry_after_failed_response() {
+        let appends = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let handler_appends = Arc::clone(&appends);
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => {
+                    if handler_appends.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                        vec![MockAction::Text(
+                            json!({
+                                "type": "response.done",
+                                "response": {
+                                    "status": "failed",
+                                    "status_details": {"error": {"type": "server_error", "code": "InternalError"}},
+                                },
+                            })
+                            .to_string(),
+                        )]
+                    } else {
+                        vec![
+                            MockAction::Text(audio_delta(b"pcm")),
+                            MockAction::Text(
+                                json!({"type": "response.done", "response": {"status": "completed"}})
+                                    .to_string(),
+                            ),
+                        ]
+                    }
+                }
+                Some("session.finish") => vec![MockAction::Close(1000, "bye".to_string())],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .response_retry(2)
+            .connect()
+            .await
+            .unwrap();
+        let audio_stream = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        qwen_tts_realtime.finish().await.unwrap();
+        let chunks = tokio::time::timeout(Duration::from_secs(5), audio_stream.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        assert_eq!(chunks, vec![b"pcm".to_vec()]);
+        let received = server.received_json();
+        assert_eq!(
+            server.received_types(),
+            vec!["input_text_buffer.append", "input_text_buffer.append", "session.finish"]
+        );
+        assert_eq!(received[1]["text"], "你好");
+        assert_ne!(received[0]["event_id"], received[1]["event_id"]);
+    }
+
+    /// 每段文本回复6个音频分片, 分片内容为序号
+    async fn spawn_burst_server() -> crate::dashscope::mock_server::MockServer {
+        MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => (1..=6u8)
+                    .map(|i| MockAction::Text(audio_delta(&[i])))
+                    .collect(),
+                Some("session.finish") => vec![
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Close(1000, "bye".to_string()),
+                ],
+                _ => vec![],
+            })
+            .spawn()
+            .await
+    }
+
+    /// 先让读取任务处理完全部分片, 再开始消费
+    async fn consume_after_finished(overflow_policy: OverflowPolicy) -> (Vec<Vec<u8>>, bool) {
+        let server = spawn_burst_server().await;
+        let callback = RecordingCallback::new();
+        let finished = Arc::clone(&callback.finished);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(Mutex::new(Box::new(callback))))
+            .audio_buffer_capacity(2)
+            .overflow_policy(overflow_policy)
+            .connect()
+            .await
+            .unwrap();
+        let audio_stream = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), finished.notified())
+            .await
+            .unwrap();
+        let chunks = audio_stream.collect::<Vec<_>>().await;
+        (chunks, qwen_tts_realtime.audio_overflowed())
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policy_block() {
+        let server = spawn_burst_server().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .audio_buffer_capacity(2)
+            .connect()
+            .await
+            .unwrap();
+        let audio_stream = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let chunks = tokio::time::timeout(
+            Duration::from_secs(5),
+            audio_stream
+                .then(|chunk| async move {
+                    // 慢消费者, 读取任务需要等待
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    chunk
+                })
+                .collect::<Vec<_>>(),
+        )
         .await
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
+        .unwrap();
+        assert_eq!(chunks, (1..=6u8).map(|i| vec![i]).collect::<Vec<_>>());
+        assert!(!qwen_tts_realtime.audio_overflowed());
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policy_drop_oldest() {
+        let (chunks, overflowed) = consume_after_finished(OverflowPolicy::DropOldest).await;
+        assert_eq!(chunks, vec![vec![5], vec![6]]);
+        assert!(!overflowed);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policy_error() {
+        let (chunks, overflowed) = consume_after_finished(OverflowPolicy::Error).await;
+        assert_eq!(chunks, vec![vec![1], vec![2]]);
+        assert!(overflowed);
+    }
+
+    async fn recv_until_closed(qwen_tts_realtime: &mut QwenTtsRealtime) -> (Vec<String>, CloseInfo) {
+        let mut types = vec![];
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), qwen_tts_realtime.recv())
+                .await
+                .unwrap()
+            {
+                Some(ServerEvent::Event(v)) => types.push(v["type"].as_str().unwrap().to_string()),
+                Some(ServerEvent::Closed(close_info)) => {
+                    assert_eq!(qwen_tts_realtime.recv().await, None);
+                    return (types, close_info);
+                }
+                None => panic!("Closed 之前收到了 None"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_normal_finish() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .pull_events(true)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let (types, close_info) = recv_until_closed(&mut qwen_tts_realtime).await;
+        assert_eq!(
+            types,
+            vec!["session.created", "response.audio.delta", "response.done", "session.finished"]
+        );
+        assert!(close_info.is_normal());
+        assert_eq!(close_info.close_code, Some(1000));
+        let summary = qwen_tts_realtime.finish_summary();
+        assert!(summary.session_finished);
+        assert_eq!(summary.delta_count, 1);
+        assert_eq!(summary.audio_bytes, "你好".len() as u64);
+        assert_eq!(summary.jitter.gaps, 0);
+    }
+
+    #[tokio::test]
+    async fn test_recv_server_initiated_close() {
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => {
+                    vec![MockAction::Close(1011, "internal error".to_string())]
+                }
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .pull_events(true)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        let (types, close_info) = recv_until_closed(&mut qwen_tts_realtime).await;
+        assert_eq!(types, vec!["session.created"]);
+        assert!(!close_info.is_normal());
+        assert!(!close_info.session_finished);
+        assert_eq!(close_info.close_code, Some(1011));
+        assert_eq!(close_info.close_reason, "internal error");
+    }
+
+    #[test]
+    fn test_split_for_append_boundaries() {
+        assert_eq!(split_for_append("", 4), vec![""]);
+        assert_eq!(split_for_append("abc", 4), vec!["abc"]);
+        // 汉字占3字节, 不能在字符中间拆分
+        assert_eq!(split_for_append("你好世界", 7), vec!["你好", "世界"]);
+        // 组合声调不与前一个字母拆开
+        assert_eq!(split_for_append("ae\u{0301}b", 2), vec!["a", "e\u{0301}", "b"]);
+        // 上限小于单个字符时整体发送
+        assert_eq!(split_for_append("你好", 1), vec!["你", "好"]);
+    }
+
+    #[tokio::test]
+    async fn test_large_text_split_into_appends() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .max_append_bytes(100)
+            .connect()
+            .await
+            .unwrap();
+        let text = "长文本合成测试, mixed with English words. ".repeat(20);
+        qwen_tts_realtime.append_text(&text).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let appends = server
+            .received_json()
+            .into_iter()
+            .filter(|v| v["type"] == "input_text_buffer.append")
+            .map(|v| v["text"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert!(appends.len() > 1);
+        assert!(appends.iter().all(|chunk| chunk.len() <= 100));
+        assert_eq!(appends.concat(), text);
+    }
+
+    #[tokio::test]
+    async fn test_on_send_sees_outbound_frames() {
+        let server = MockServerBuilder::new().spawn().await;
+        let callback = RecordingCallback::new();
+        let sent = Arc::clone(&callback.sent);
+        let finished = Arc::clone(&callback.finished);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(Mutex::new(Box::new(callback))))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("第一段").await.unwrap();
+        qwen_tts_realtime.append_text("第二段").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), finished.notified())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            RecordingCallback::event_types(&sent),
+            vec![
+                "session.update",
+                "input_text_buffer.append",
+                "input_text_buffer.append",
+                "session.finish",
+            ]
+        );
+        // 与服务端实际收到的帧完全一致
+        assert_eq!(*sent.lock().unwrap(), *server.received.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_caption_follows_text_echo() {
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => {
+                    let text = event_json["text"].as_str().unwrap();
+                    let (head, tail) = text.split_at(text.len() / 2);
+                    vec![
+                        MockAction::Text(
+                            json!({"type": "response.text.delta", "delta": head}).to_string(),
+                        ),
+                        MockAction::Text(audio_delta(&[0, 0])),
+                        MockAction::Text(
+                            json!({"type": "response.text.delta", "delta": tail}).to_string(),
+                        ),
+                        MockAction::Text(
+                            json!({"type": "response.text.done", "text": text}).to_string(),
+                        ),
+                        MockAction::Text(event("response.done")),
+                    ]
+                }
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .pull_events(true)
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(qwen_tts_realtime.caption(), "");
+        qwen_tts_realtime.append_text("一二三四").await.unwrap();
+        qwen_tts_realtime.append_text("abcd").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        while let Some(event) = qwen_tts_realtime.recv().await {
+            if matches!(event, ServerEvent::Closed(_)) {
+                break;
+            }
+        }
+        assert_eq!(qwen_tts_realtime.caption(), "一二三四abcd");
+    }
+
+    #[tokio::test]
+    async fn test_after_finish_policy() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let result = qwen_tts_realtime.append_text("你好").await;
+        assert!(matches!(result, Err(QwenTtsError::InvalidState(_))));
+
+        // NewSession: finish 之后立即 append, 自动开启新会话并沿用原来的会话配置
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .after_finish(AfterFinishPolicy::NewSession)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("第一轮").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.append_text("第二轮").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        let mut state = qwen_tts_realtime.watch_state();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            state.wait_for(|s| !s.is_open()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(server.connection_count(), 2);
+        let received = server.received_json();
+        let types = received
+            .iter()
+            .map(|v| v["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                "session.update",
+                "input_text_buffer.append",
+                "session.finish",
+                "session.update",
+                "input_text_buffer.append",
+                "session.finish",
+            ]
+        );
+        assert_eq!(received[3]["session"]["voice"], "Cherry");
+        assert_eq!(received[4]["text"], "第二轮");
+
+        // 服务端一直不关闭连接: 等待旧会话关闭超时, 之后再 append 仍然重试而不是 InvalidState
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.finish") => vec![],
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .after_finish(AfterFinishPolicy::NewSession)
+            .connect_timeout(Duration::from_millis(200))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        for _ in 0..2 {
+            let result = qwen_tts_realtime.append_text("你好").await;
+            assert!(matches!(result, Err(QwenTtsError::Timeout(_))), "{:?}", result);
+        }
+        assert_eq!(server.connection_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_is_connected_and_is_finished() {
+        // session.finished 之后稍等再关闭连接, 观察两个状态先后变化
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.finish") => vec![
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Sleep(Duration::from_millis(300)),
+                    MockAction::Close(1000, "bye".to_string()),
+                ],
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        assert!(qwen_tts_realtime.is_connected());
+        assert!(!qwen_tts_realtime.is_finished());
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !qwen_tts_realtime.is_finished() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(qwen_tts_realtime.is_connected());
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        assert!(!qwen_tts_realtime.is_connected());
+        assert!(qwen_tts_realtime.is_finished());
+
+        // 异常断开: 连接结束但没有完成
+        let server = MockServerBuilder::new()
+            .handler(|_| vec![MockAction::Drop])
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        assert!(!qwen_tts_realtime.is_connected());
+        assert!(!qwen_tts_realtime.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_finished_outcomes() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        assert_eq!(
+            qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await,
+            FinishOutcome::Finished
+        );
+
+        // 没有 session.finished 就断开
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.finish") => {
+                    vec![MockAction::Close(1011, "internal error".to_string())]
+                }
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        match qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await {
+            FinishOutcome::ClosedEarly(close_info) => {
+                assert!(!close_info.session_finished);
+                assert_eq!(close_info.close_code, Some(1011));
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+
+        // 服务端不响应 finish
+        let server = MockServerBuilder::new().handler(|_| vec![]).spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        assert_eq!(
+            qwen_tts_realtime.wait_until_finished(Duration::from_millis(200)).await,
+            FinishOutcome::Timeout
+        );
+        assert_eq!(qwen_tts_realtime.state(), SessionState::Finishing);
+    }
+
+    #[tokio::test]
+    async fn test_voice_override_append() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let result = qwen_tts_realtime.append_text_with_voice("你好", "Ethan").await;
+        assert!(matches!(result, Err(QwenTtsError::SessionNotConfigured)));
+
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .append_text_with_voice("我是另一个声音", "Ethan")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("我回来了").await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let received = server.received_json();
+        let types = received
+            .iter()
+            .map(|v| v["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                "session.update",
+                "session.update",
+                "input_text_buffer.append",
+                "input_text_buffer.commit",
+                "session.update",
+                "input_text_buffer.append",
+            ]
+        );
+        assert_eq!(received[0]["session"]["voice"], "Cherry");
+        assert_eq!(received[1]["session"]["voice"], "Ethan");
+        assert_eq!(received[2]["text"], "我是另一个声音");
+        assert_eq!(received[4]["session"]["voice"], "Cherry");
+        assert_eq!(received[4]["session"]["mode"], "commit");
+    }
+
+    #[tokio::test]
+    async fn test_session_updated_ack_diff() {
+        // 服务端把采样率修正为24000, 并且不认识 language_type
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.update") => {
+                    let mut session = event_json["session"].clone();
+                    session["sample_rate"] = json!(24000);
+                    session.as_object_mut().unwrap().remove("language_type");
+                    vec![MockAction::Text(
+                        json!({"type": "session.updated", "session": session}).to_string(),
+                    )]
+                }
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let mut response_format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        response_format.sample_rate = 48000;
+        let config = SessionConfig::new("Cherry", response_format, "server_commit")
+            .language_type("Chinese");
+        let result = qwen_tts_realtime
+            .update_session_and_wait(&config, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(result.ignored_fields, vec!["language_type", "sample_rate"]);
+        assert_eq!(result.applied.response_format, AudioFormat::PCM_24000HZ_MONO_16BIT);
+        assert_eq!(result.applied.language_type, None);
+        assert_eq!(result.applied.voice, "Cherry");
+
+        // 服务端不回复 session.updated 时超时
+        let server = MockServerBuilder::new().handler(|_| vec![]).spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let result = qwen_tts_realtime
+            .update_session_and_wait(&config, Duration::from_millis(100))
+            .await;
+        assert!(matches!(result, Err(QwenTtsError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_idle_session_resend_keeps_session_alive() {
+        // 模拟服务端: 超过200ms没有收到任何事件时会话过期, 之后的 append 返回错误
+        let last_received = Arc::new(std::sync::Mutex::new(std::time::Instant::now()));
+        let spawn_server = || {
+            let last_received = Arc::clone(&last_received);
+            MockServerBuilder::new()
+                .handler(move |event_json| {
+                    let mut last_received = last_received.lock().unwrap();
+                    let expired = last_received.elapsed() > Duration::from_millis(200);
+                    *last_received = std::time::Instant::now();
+                    match event_json["type"].as_str() {
+                        Some("input_text_buffer.append") if expired => {
+                            vec![MockAction::Text(
+                                json!({"type": "error", "error": {"message": "session expired"}})
+                                    .to_string(),
+                            )]
+                        }
+                        Some("input_text_buffer.append") => {
+                            vec![MockAction::Text(audio_delta(b"ok"))]
+                        }
+                        _ => vec![],
+                    }
+                })
+                .spawn()
+        };
+
+        let cases = [
+            (None, "error"),
+            (Some(Duration::from_millis(80)), "response.audio.delta"),
+        ];
+        for (idle_resend, expected) in cases {
+            *last_received.lock().unwrap() = std::time::Instant::now();
+            let server = spawn_server().await;
+            let mut builder = QwenTtsRealtimeBuilder::new("test-api-key")
+                .url(&server.url)
+                .pull_events(true);
+            if let Some(idle) = idle_resend {
+                builder = builder.idle_session_resend(idle);
+            }
+            let mut qwen_tts_realtime = builder.connect().await.unwrap();
+            qwen_tts_realtime
+                .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+                .await
+                .unwrap();
+            // 用户思考时间
+            tokio::time::sleep(Duration::from_millis(450)).await;
+            qwen_tts_realtime.append_text("你好").await.unwrap();
+            let reply = loop {
+                match tokio::time::timeout(Duration::from_secs(5), qwen_tts_realtime.recv())
+                    .await
+                    .unwrap()
+                {
+                    Some(ServerEvent::Event(v)) if v["type"] != "session.created" => break v,
+                    Some(ServerEvent::Event(_)) => continue,
+                    other => panic!("意外的事件: {:?}", other),
+                }
+            };
+            assert_eq!(reply["type"], expected);
+            let updates = server
+                .received_json()
+                .into_iter()
+                .filter(|v| v["type"] == "session.update")
+                .collect::<Vec<_>>();
+            if idle_resend.is_some() {
+                assert!(updates.len() >= 3, "session.update 次数: {}", updates.len());
+                assert!(updates.iter().all(|v| v["session"]["voice"] == "Cherry"));
+                // finish 之后不再重发, 即使服务端一直不关闭连接
+                qwen_tts_realtime.finish().await.unwrap();
+                let count = || {
+                    let types = server.received_types();
+                    types.iter().filter(|t| *t == "session.update").count()
+                };
+                let before = count();
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                assert_eq!(count(), before);
+            } else {
+                assert_eq!(updates.len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pad_trailing_silence() {
+        async fn collect_output(pad: Option<Duration>) -> Vec<u8> {
+            let server = MockServerBuilder::new().spawn().await;
+            let mut builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+            if let Some(pad) = pad {
+                builder = builder.pad_trailing_silence(pad);
+            }
+            let mut qwen_tts_realtime = builder.connect().await.unwrap();
+            let audio = qwen_tts_realtime.audio_stream();
+            qwen_tts_realtime.append_text("你好").await.unwrap();
+            qwen_tts_realtime.finish().await.unwrap();
+            let chunks = tokio::time::timeout(Duration::from_secs(5), audio.collect::<Vec<_>>())
+                .await
+                .unwrap();
+            chunks.concat()
+        }
+
+        let plain = collect_output(None).await;
+        let padded = collect_output(Some(Duration::from_millis(250))).await;
+        let expected =
+            AudioFormat::PCM_24000HZ_MONO_16BIT.bytes_for_duration(Duration::from_millis(250));
+        assert_eq!(expected, 12000);
+        assert_eq!(padded.len(), plain.len() + expected);
+        assert_eq!(&padded[..plain.len()], plain.as_slice());
+        assert!(padded[plain.len()..].iter().all(|b| *b == 0));
+    }
+
+    #[tokio::test]
+    async fn test_align_frames_final_length() {
+        async fn collect_output(alignment: FrameAlignment) -> Vec<Vec<u8>> {
+            let server = MockServerBuilder::new()
+                .handler(|event_json| {
+                    if event_json["type"] != "input_text_buffer.append" {
+                        return default_handler(event_json);
+                    }
+                    vec![
+                        MockAction::Text(audio_delta(&[1u8; 250])),
+                        MockAction::Text(audio_delta(&[2u8; 250])),
+                        MockAction::Text(event("response.done")),
+                    ]
+                })
+                .spawn()
+                .await;
+            let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+                .url(&server.url)
+                .align_frames(alignment)
+                .connect()
+                .await
+                .unwrap();
+            let audio = qwen_tts_realtime.audio_stream();
+            qwen_tts_realtime.append_text("你好").await.unwrap();
+            qwen_tts_realtime.finish().await.unwrap();
+            tokio::time::timeout(Duration::from_secs(5), audio.collect::<Vec<_>>())
+                .await
+                .unwrap()
+        }
+
+        // 100个样本(200字节)一帧, 收到500字节
+        let frame_bytes = 200;
+        let padded = collect_output(FrameAlignment::new(100, AlignMode::Pad)).await;
+        assert!(padded.iter().all(|chunk| chunk.len() % frame_bytes == 0));
+        let padded = padded.concat();
+        assert_eq!(padded.len(), 600);
+        assert_eq!(&padded[..250], &[1u8; 250]);
+        assert_eq!(&padded[250..500], &[2u8; 250]);
+        assert!(padded[500..].iter().all(|b| *b == 0));
+
+        let trimmed = collect_output(FrameAlignment::new(100, AlignMode::Trim)).await;
+        assert!(trimmed.iter().all(|chunk| chunk.len() % frame_bytes == 0));
+        assert_eq!(trimmed.concat(), padded[..400]);
+    }
+
+    #[tokio::test]
+    async fn test_server_pushed_config_change() {
+        let server = MockServerBuilder::new()
+            .handler(|event_json| {
+                let mut actions = default_handler(event_json);
+                if event_json["type"] == "input_text_buffer.append" {
+                    // 合成过程中服务端推送新的配置
+                    actions.insert(
+                        0,
+                        MockAction::Text(
+                            json!({
+                                "type": "session.updated",
+                                "session": {"voice": "Ethan", "sample_rate": 16000},
+                            })
+                            .to_string(),
+                        ),
+                    );
+                }
+                actions
+            })
+            .spawn()
+            .await;
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .event_channel(tx)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+
+        let mut changes = vec![];
+        while let Some(event) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+        {
+            match event {
+                CallbackEvent::ConfigChanged(config) => changes.push(config),
+                CallbackEvent::Finish(_) => break,
+                _ => {}
+            }
+        }
+        // session.update 的确认与请求一致, 不算配置变化
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].voice, "Ethan");
+        assert_eq!(changes[0].response_format.sample_rate(), 16000);
+        assert_eq!(changes[0].mode, "server_commit");
+        let confirmed = qwen_tts_realtime.confirmed_format().unwrap();
+        assert_eq!(confirmed.sample_rate(), 16000);
+        assert_eq!(confirmed.duration_for_bytes(32000), Duration::from_secs(1));
+    }
+
+    struct TypedCallback {
+        audio: Arc<std::sync::Mutex<Vec<u8>>>,
+        finished: Arc<tokio::sync::Notify>,
+    }
+
+    impl QwenTtsRealtimeCallback for TypedCallback {
+        fn on_open(&self) {}
+
+        fn on_close(&self, _close_msg: &str) {}
+
+        fn on_event(&mut self, _message: &str) -> bool {
+            false
+        }
+
+        fn on_typed_event(&mut self, event: QwenTtsEvent) -> bool {
+            match event {
+                QwenTtsEvent::ResponseAudioDelta { audio } => {
+                    self.audio.lock().unwrap().extend_from_slice(&audio);
+                }
+                QwenTtsEvent::SessionFinished => self.finished.notify_one(),
+                _ => {}
+            }
+            false
+        }
+
+        fn typed_events(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_typed_event_callback() {
+        let server = MockServerBuilder::new().spawn().await;
+        let audio = Arc::new(std::sync::Mutex::new(vec![]));
+        let finished = Arc::new(tokio::sync::Notify::new());
+        let callback: SharedCallback = Arc::new(Mutex::new(Box::new(TypedCallback {
+            audio: Arc::clone(&audio),
+            finished: Arc::clone(&finished),
+        })));
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(callback)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), finished.notified())
+            .await
+            .unwrap();
+        // 模拟服务端把文本原样作为音频返回
+        assert_eq!(*audio.lock().unwrap(), "你好".as_bytes());
+    }
+
+    struct PanickingCallback {
+        errors: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl QwenTtsRealtimeCallback for PanickingCallback {
+        fn on_open(&self) {}
+
+        fn on_close(&self, _close_msg: &str) {}
+
+        fn on_event(&mut self, message: &str) -> bool {
+            let v: Value = serde_json::from_str(message).unwrap();
+            if v["type"] == "response.audio.delta" {
+                panic!("malformed delta");
+            }
+            false
+        }
+
+        fn on_error(&mut self, error: &str) {
+            self.errors.lock().unwrap().push(error.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_error_when_connection_dropped() {
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![MockAction::Drop],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
+        // 连接断开前不会收到音频分片, 回调不会panic, 只记录 on_error
+        let callback: SharedCallback = Arc::new(Mutex::new(Box::new(PanickingCallback {
+            errors: Arc::clone(&errors),
+        })));
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(callback)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        let outcome = qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        let FinishOutcome::ClosedEarly(close_info) = outcome else {
+            panic!("unexpected outcome: {:?}", outcome);
+        };
+        let error = close_info.error.unwrap();
+        assert_eq!(*errors.lock().unwrap(), vec![error]);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_pending_text() {
+        let dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let server_dropped = Arc::clone(&dropped);
+        // 第一次收到 append 时不发关闭帧直接断开, 之后按默认行为响应
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| {
+                if event_json["type"] == "input_text_buffer.append"
+                    && !server_dropped.swap(true, std::sync::atomic::Ordering::SeqCst)
+                {
+                    return vec![MockAction::Drop];
+                }
+                default_handler(event_json)
+            })
+            .spawn()
+            .await;
+        let recording = RecordingCallback::new();
+        let reconnects = Arc::clone(&recording.reconnects);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(Mutex::new(Box::new(recording))))
+            .with_reconnect(3, Duration::from_millis(10))
+            .connect()
+            .await
+            .unwrap();
+        let audio = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        let replayed = async {
+            while server.received_types().len() < 4 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), replayed).await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+
+        let outcome = qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        assert_eq!(outcome, FinishOutcome::Finished);
+        assert_eq!(audio.collect::<Vec<_>>().await.concat(), "你好".as_bytes());
+        // 正常结束后不会再重连
+        assert_eq!(server.connection_count(), 2);
+        assert_eq!(*reconnects.lock().unwrap(), vec![1]);
+        assert_eq!(
+            server.received_types(),
+            vec![
+                "session.update",
+                "input_text_buffer.append",
+                "session.update",
+                "input_text_buffer.append",
+                "session.finish",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_reader() {
+        // 服务端发出第一个分片后长时间不再响应, 模拟正在合成的长文本
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(audio_delta(b"first")),
+                    MockAction::Sleep(Duration::from_secs(30)),
+                    MockAction::Text(audio_delta(b"late")),
+                ],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let recording = RecordingCallback::new();
+        let closes = Arc::clone(&recording.closes);
+        let sent = Arc::clone(&recording.sent);
+        let callback: SharedCallback = Arc::new(Mutex::new(Box::new(recording)));
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(callback)
+            .connect()
+            .await
+            .unwrap();
+        let mut audio = Box::pin(qwen_tts_realtime.audio_stream());
+        qwen_tts_realtime.append_text("很长的文本").await.unwrap();
+        assert_eq!(audio.next().await.unwrap(), b"first");
+
+        tokio::time::timeout(Duration::from_secs(2), qwen_tts_realtime.cancel())
+            .await
+            .expect("cancel 应立即返回")
+            .unwrap();
+        assert_eq!(*closes.lock().unwrap(), vec!["Connection cancelled by client"]);
+        assert_eq!(
+            RecordingCallback::event_types(&sent).last().map(String::as_str),
+            Some("response.cancel")
+        );
+        assert_eq!(qwen_tts_realtime.state(), SessionState::Closed);
+        assert!(!qwen_tts_realtime.is_finished());
+        assert_eq!(
+            qwen_tts_realtime.close_info().unwrap().close_reason,
+            "cancelled by client"
+        );
+        assert_eq!(audio.next().await, None);
+        // 连接结束后再次取消什么也不做
+        qwen_tts_realtime.cancel().await.unwrap();
+        assert_eq!(closes.lock().unwrap().len(), 1);
+        // 取消之后 finish 不再发送 session.finish
+        qwen_tts_realtime.finish().await.unwrap();
+        assert_eq!(
+            RecordingCallback::event_types(&sent).last().map(String::as_str),
+            Some("response.cancel")
+        );
+
+        // finish 之后取消什么也不做
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime.cancel().await.unwrap();
+        let outcome = qwen_tts_realtime.wait_until_finished(Duration::from_secs(5)).await;
+        assert_eq!(outcome, FinishOutcome::Finished);
+        assert!(!server.received_types().contains(&"response.cancel".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_reader_panic_is_reported() {
+        let server = MockServerBuilder::new().spawn().await;
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
+        let callback: SharedCallback = Arc::new(Mutex::new(Box::new(PanickingCallback {
+            errors: Arc::clone(&errors),
+        })));
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(callback)
+            .pull_events(true)
+            .connect()
+            .await
+            .unwrap();
+        let mut state = qwen_tts_realtime.watch_state();
+        let audio = qwen_tts_realtime.audio_stream();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+
+        let (_, close_info) = recv_until_closed(&mut qwen_tts_realtime).await;
+        let error = close_info.error.unwrap();
+        assert!(error.contains("malformed delta"));
+        // 音频流随读取任务结束, 不会一直挂起
+        let _ = tokio::time::timeout(Duration::from_secs(5), audio.collect::<Vec<_>>())
+            .await
+            .unwrap();
+        let changed = state.wait_for(|s| !s.is_open());
+        tokio::time::timeout(Duration::from_secs(5), changed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(qwen_tts_realtime.state(), SessionState::Failed(error.clone()));
+        assert_eq!(*errors.lock().unwrap(), vec![error]);
+    }
+
+    #[tokio::test]
+    async fn test_append_lang_segments() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime
+            .update_session("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit")
+            .await
+            .unwrap();
+        let segments = [
+            ("大家好", Language::Chinese),
+            ("Welcome to the show.", Language::English),
+            ("Let's begin.", Language::English),
+            ("我们开始吧", Language::Chinese),
+        ];
+        for (text, lang) in segments {
+            qwen_tts_realtime.append_text_lang(text, lang).await.unwrap();
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let sent = server
+            .received_json()
+            .iter()
+            .map(|v| match v["type"].as_str().unwrap() {
+                "session.update" => format!(
+                    "update:{}",
+                    v["session"]["language_type"].as_str().unwrap_or("-")
+                ),
+                _ => format!("append:{}", v["text"].as_str().unwrap()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![
+                "update:-",
+                "update:Chinese",
+                "append:大家好",
+                "update:English",
+                "append:Welcome to the show.",
+                "append:Let's begin.",
+                "update:Chinese",
+                "append:我们开始吧",
+            ]
+        );
+        // 语言切换不影响其余配置
+        assert!(server
+            .received_json()
+            .iter()
+            .filter(|v| v["type"] == "session.update")
+            .all(|v| v["session"]["voice"] == "Cherry"));
+    }
+
+    #[tokio::test]
+    async fn test_error_variants() {
+        let result = QwenTtsRealtime::new("m", "key", Some("not a url"), None, None).await;
+        assert!(matches!(result, Err(QwenTtsError::InvalidUrl(_))));
+
+        let server = MockServerBuilder::new().spawn().await;
+        let result = QwenTtsRealtime::new("m", "bad\nkey", Some(&server.url), None, None).await;
+        assert!(matches!(result, Err(QwenTtsError::InvalidHeader(_))));
+
+        let server = MockServerBuilder::new().reject(403, vec![]).spawn().await;
+        let result = QwenTtsRealtime::new("m", "key", Some(&server.url), None, None).await;
+        assert!(matches!(result, Err(QwenTtsError::Unauthorized { status: 403, .. })));
+
+        let server = MockServerBuilder::new().reject(401, vec![]).spawn().await;
+        let result = QwenTtsRealtimeBuilder::new("bad-key").url(&server.url).connect().await;
+        let error = result.err().unwrap();
+        assert!(matches!(
+            &error,
+            QwenTtsError::Unauthorized { status: 401, body } if body.contains("mock")
+        ));
+        assert!(error.to_string().contains("401"), "{}", error);
+        assert!(error.to_string().contains("\"code\":\"mock\""), "{}", error);
+
+        let server = MockServerBuilder::new().reject(502, vec![]).spawn().await;
+        let result = QwenTtsRealtime::new("m", "key", Some(&server.url), None, None).await;
+        let error = result.err().unwrap();
+        assert!(matches!(error, QwenTtsError::HandshakeRejected { status: 502, .. }));
+        assert!(error.is_transient());
+
+        // 明文服务端无法完成TLS握手
+        let server = MockServerBuilder::new().spawn().await;
+        let url = server.url.replacen("ws://", "wss://", 1);
+        let result = QwenTtsRealtime::new("m", "key", Some(&url), None, None).await;
+        assert!(matches!(result, Err(QwenTtsError::Tls(_))));
+
+        // 服务端关闭连接后继续发送
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.commit") => {
+                    vec![MockAction::Close(1000, "bye".to_string())]
+                }
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let result = qwen_tts_realtime.append_text_with_voice("你好", "Ethan").await;
+        assert!(matches!(result, Err(QwenTtsError::SessionNotConfigured)));
+        qwen_tts_realtime.commit().await.unwrap();
+        let mut state = qwen_tts_realtime.watch_state();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            state.wait_for(|s| !s.is_open()),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        let result = qwen_tts_realtime.append_text("你好").await;
+        assert!(matches!(result, Err(QwenTtsError::ConnectionClosed)));
+        assert!(result.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_verify_credentials() {
+        let server = MockServerBuilder::new().spawn().await;
+        verify_credentials_with_url("test-api-key", Some(server.url.as_str()))
+            .await
+            .unwrap();
+        assert_eq!(server.connection_count(), 1);
+        assert!(server.received_types().is_empty());
+
+        let server = MockServerBuilder::new().reject(401, vec![]).spawn().await;
+        let result = verify_credentials_with_url("bad-key", Some(server.url.as_str())).await;
+        assert!(matches!(
+            result,
+            Err(QwenTtsError::Unauthorized { status: 401, .. })
+        ));
+        assert!(!result.unwrap_err().is_transient());
+    }
+
+    #[tokio::test]
+    async fn test_middleware_rejects_finish() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .middleware(Arc::new(TraceMiddleware))
+            .middleware(Arc::new(RejectFinishMiddleware))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        let result = qwen_tts_realtime.finish().await;
+        assert!(matches!(result, Err(QwenTtsError::MiddlewareRejected(_))));
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let received = server.received_json();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["type"], "input_text_buffer.append");
+        assert_eq!(received[0]["trace_id"], "trace_test");
+    }
 
 
     #[tokio::test]
     async fn test_update_session() {
-        let mut qwen_tts_realtime = prepare_qwen_tts_realtime(None).await;
+        let mut qwen_tts_realtime = prepare_qwen_tts_realtime(None).await.unwrap();
         let _ = qwen_tts_realtime
             .update_session(
                 "Cherry",
@@ -237,7 +6203,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_append_text() {
-        let mut qwen_tts_realtime = prepare_qwen_tts_realtime(None).await;
+        let mut qwen_tts_realtime = prepare_qwen_tts_realtime(None).await.unwrap();
         let _ = qwen_tts_realtime
             .update_session(
                 "Cherry",
@@ -0,0 +1,254 @@
+//!
+//! ## 集成自检
+//! self_test 跑一遍 连接 -> update_session -> append_text -> finish 的完整流程, 并给出耗时统计.
+//! 不指定url时连接进程内启动的模拟服务端(ws://127.0.0.1), 只能验证本库和运行时环境是否正常,
+//! 不代表真实服务可用; 指定url和API Key后才会连接真实服务端.
+use crate::common::errors::QwenTtsError;
+use crate::dashscope::qwen_tts_realtime::{AudioFormat, QwenTtsRealtimeBuilder, ServerEvent};
+use base64::Engine;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+/// 模拟服务端每段文本返回的静音时长
+const MOCK_AUDIO_DURATION: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct SelfTestConfig {
+    api_key: String,
+    // None 时使用进程内模拟服务端
+    url: Option<String>,
+    model_name: String,
+    voice: String,
+    text: String,
+    timeout: Duration,
+}
+
+impl SelfTestConfig {
+    /// 使用进程内模拟服务端
+    pub fn mock() -> Self {
+        Self {
+            api_key: "self-test".to_string(),
+            url: None,
+            model_name: "qwen3-tts-flash-realtime".to_string(),
+            voice: "Cherry".to_string(),
+            text: "自检".to_string(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// 连接真实服务端, url 为None时使用默认的 DashScope 地址
+    pub fn endpoint(api_key: &str, url: Option<&str>) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+            url: Some(
+                url.unwrap_or("wss://dashscope.aliyuncs.com/api-ws/v1/realtime")
+                    .to_string(),
+            ),
+            ..Self::mock()
+        }
+    }
+
+    pub fn model(mut self, model_name: &str) -> Self {
+        self.model_name = model_name.to_string();
+        self
+    }
+
+    pub fn voice(mut self, voice: &str) -> Self {
+        self.voice = voice.to_string();
+        self
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.text = text.to_string();
+        self
+    }
+
+    /// 从连接开始到会话结束的最长等待时间
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    /// 为true时结果来自进程内模拟服务端
+    pub used_mock: bool,
+    pub url: String,
+    /// websocket 握手耗时
+    pub connect_latency: Duration,
+    /// 从发送文本到收到第一个音频分片的耗时, 没有收到音频时为None
+    pub ttfb: Option<Duration>,
+    /// 解码后的音频字节数
+    pub bytes_received: usize,
+    /// 是否收到 session.finished
+    pub finished: bool,
+}
+
+impl SelfTestReport {
+    pub fn is_healthy(&self) -> bool {
+        self.finished && self.bytes_received > 0
+    }
+}
+
+///
+/// 跑一次短合成并返回自检报告; 超时不算错误, 体现为报告中 finished 为false
+pub async fn self_test(config: SelfTestConfig) -> Result<SelfTestReport, QwenTtsError> {
+    let used_mock = config.url.is_none();
+    let url = match config.url.clone() {
+        Some(url) => url,
+        None => spawn_mock_server().await?,
+    };
+    let mut report = SelfTestReport {
+        used_mock,
+        url: url.clone(),
+        connect_latency: Duration::ZERO,
+        ttfb: None,
+        bytes_received: 0,
+        finished: false,
+    };
+    let deadline = Instant::now() + config.timeout;
+
+    let started = Instant::now();
+    let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new(&config.api_key)
+        .model(&config.model_name)
+        .url(&url)
+        .pull_events(true)
+        .connect()
+        .await?;
+    report.connect_latency = started.elapsed();
+
+    qwen_tts_realtime
+        .update_session(
+            &config.voice,
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            "server_commit",
+        )
+        .await?;
+    let sent_at = Instant::now();
+    qwen_tts_realtime.append_text(&config.text).await?;
+    qwen_tts_realtime.finish().await?;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Ok(Some(event)) = tokio::time::timeout(remaining, qwen_tts_realtime.recv()).await
+        else {
+            log::warn!("自检在会话结束前超时或中断, url: {}", url);
+            break;
+        };
+        match event {
+            ServerEvent::Event(v) => {
+                if v["type"] == "response.audio.delta" {
+                    let delta = v["delta"].as_str().unwrap_or_default();
+                    let audio = base64::engine::general_purpose::STANDARD
+                        .decode(delta)
+                        .unwrap_or_default();
+                    report.ttfb.get_or_insert_with(|| sent_at.elapsed());
+                    report.bytes_received += audio.len();
+                }
+            }
+            ServerEvent::Closed(close_info) => {
+                report.finished = close_info.session_finished;
+                break;
+            }
+        }
+    }
+    log::info!("自检结果: {:?}", report);
+    Ok(report)
+}
+
+///
+/// 进程内模拟服务端: 只接受一个连接, 每段文本返回一段静音
+async fn spawn_mock_server() -> Result<String, QwenTtsError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("ws://{}/api-ws/v1/realtime", listener.local_addr()?);
+    tokio::spawn(async move {
+        let Ok((stream, _)) = listener.accept().await else {
+            return;
+        };
+        let Ok(ws) = tokio_tungstenite::accept_async(stream).await else {
+            return;
+        };
+        let (mut writer, mut reader) = ws.split();
+        let _ = writer
+            .send(Message::text(
+                json!({"event_id": "event_self_test", "type": "session.created"}).to_string(),
+            ))
+            .await;
+        while let Some(Ok(msg)) = reader.next().await {
+            let Ok(event) = serde_json::from_str::<Value>(msg.to_text().unwrap_or_default())
+            else {
+                continue;
+            };
+            let replies = match event["type"].as_str().unwrap_or_default() {
+                "session.update" => vec![json!({
+                    "event_id": "event_self_test",
+                    "type": "session.updated",
+                    "session": event["session"],
+                })],
+                "input_text_buffer.append" => {
+                    let silence = vec![
+                        0u8;
+                        AudioFormat::PCM_24000HZ_MONO_16BIT
+                            .bytes_for_duration(MOCK_AUDIO_DURATION)
+                    ];
+                    vec![
+                        json!({
+                            "event_id": "event_self_test",
+                            "type": "response.audio.delta",
+                            "delta": base64::engine::general_purpose::STANDARD.encode(silence),
+                        }),
+                        json!({"event_id": "event_self_test", "type": "response.done"}),
+                    ]
+                }
+                "session.finish" => {
+                    let _ = writer
+                        .send(Message::text(
+                            json!({"event_id": "event_self_test", "type": "session.finished"})
+                                .to_string(),
+                        ))
+                        .await;
+                    let _ = writer
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "self test done".into(),
+                        })))
+                        .await;
+                    return;
+                }
+                _ => vec![],
+            };
+            for reply in replies {
+                if writer.send(Message::text(reply.to_string())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_self_test_against_mock() {
+        let report = self_test(SelfTestConfig::mock()).await.unwrap();
+        assert!(report.used_mock);
+        assert!(report.url.starts_with("ws://127.0.0.1:"));
+        assert!(report.connect_latency > Duration::ZERO);
+        assert!(report.ttfb.is_some());
+        assert_eq!(
+            report.bytes_received,
+            AudioFormat::PCM_24000HZ_MONO_16BIT.bytes_for_duration(MOCK_AUDIO_DURATION)
+        );
+        assert!(report.finished);
+        assert!(report.is_healthy());
+    }
+}
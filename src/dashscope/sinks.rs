@@ -0,0 +1,1120 @@
+use crate::dashscope::qwen_tts_realtime::{AudioFormat, QwenTtsRealtimeCallback};
+use base64::Engine;
+use serde_json::Value;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+///
+/// 把 response.audio.delta 解码后追加写入文件
+///
+/// 写入经过 BufWriter 缓冲, 默认只在缓冲写满、session.finished 或 drop 时落盘;
+/// 通过 with_flush_interval 开启定时flush后, 进程崩溃时最多丢失一个间隔内的音频
+pub struct FileSinkCallback {
+    writer: Arc<Mutex<BufWriter<File>>>,
+    // 与 writer 是同一个文件, 用于在不持有 writer 锁时 sync_data
+    file: Arc<File>,
+    flush_task: Option<JoinHandle<()>>,
+}
+
+impl FileSinkCallback {
+    pub fn new(filename: &str) -> std::io::Result<Self> {
+        let p = Path::new(filename);
+        if let Some(parent) = p.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(p)?;
+        Ok(Self {
+            file: Arc::new(file.try_clone()?),
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+            flush_task: None,
+        })
+    }
+
+    ///
+    /// 每隔interval把缓冲的音频flush并同步到磁盘, 需要在tokio运行时内调用;
+    /// 定时任务在 on_finish 或 drop 时最后flush一次后停止; flush和同步在阻塞线程池中执行,
+    /// 不占用运行时的工作线程
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        let writer = Arc::clone(&self.writer);
+        let file = Arc::clone(&self.file);
+        self.flush_task = Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let (writer, file) = (Arc::clone(&writer), Arc::clone(&file));
+                match tokio::task::spawn_blocking(move || Self::flush_writer(&writer, &file)).await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::error!("定时flush音频文件失败: {}", e),
+                    Err(e) => log::error!("定时flush任务异常退出: {}", e),
+                }
+            }
+        }));
+        self
+    }
+
+    /// 只在flush时持有 writer 的锁, sync_data 期间读取任务仍然可以写入
+    fn flush_writer(writer: &Mutex<BufWriter<File>>, file: &File) -> std::io::Result<()> {
+        writer.lock().unwrap().flush()?;
+        file.sync_data()
+    }
+
+    fn stop(&mut self) {
+        if let Some(flush_task) = self.flush_task.take() {
+            flush_task.abort();
+        }
+        if let Err(e) = Self::flush_writer(&self.writer, &self.file) {
+            log::error!("flush音频文件失败: {}", e);
+        }
+    }
+}
+
+impl Drop for FileSinkCallback {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl QwenTtsRealtimeCallback for FileSinkCallback {
+    fn on_open(&self) {}
+
+    fn on_close(&self, close_msg: &str) {
+        log::info!("Connection closed: {}", close_msg);
+    }
+
+    fn on_finish(&mut self, _close_msg: &str) {
+        self.stop();
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            return false;
+        };
+        match v["type"].as_str() {
+            Some("response.audio.delta") => {
+                if let Some(delta) = v["delta"].as_str() {
+                    match base64::engine::general_purpose::STANDARD.decode(delta) {
+                        Ok(audio_bytes) => {
+                            if let Err(e) = self.writer.lock().unwrap().write_all(&audio_bytes) {
+                                log::error!("写入音频文件失败: {}", e);
+                            }
+                        }
+                        Err(e) => log::error!("音频数据base64解码失败: {}", e),
+                    }
+                }
+            }
+            Some("session.finished") => return true,
+            _ => {}
+        }
+        false
+    }
+}
+
+#[cfg(unix)]
+type SinkErrorHandler = Arc<dyn Fn(&std::io::Error) + Send + Sync>;
+
+///
+/// 把 response.audio.delta 解码后的PCM写入 Unix domain socket, 用于本机进程间传输音频
+///
+/// 默认直接写裸PCM字节流; 开启 length_prefixed 后每个分片前加4字节小端长度, 便于接收方按分片处理.
+/// 接收方断开时不会中断会话: 通过 on_sink_error 通知一次, 之后的音频直接丢弃
+#[cfg(unix)]
+pub struct UnixSocketSink {
+    // 写入失败后置为None, 不再尝试写入
+    stream: Option<std::os::unix::net::UnixStream>,
+    length_prefixed: bool,
+    on_sink_error: Option<SinkErrorHandler>,
+}
+
+#[cfg(unix)]
+impl UnixSocketSink {
+    pub fn connect(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Ok(Self {
+            stream: Some(stream),
+            length_prefixed: false,
+            on_sink_error: None,
+        })
+    }
+
+    pub fn length_prefixed(mut self, length_prefixed: bool) -> Self {
+        self.length_prefixed = length_prefixed;
+        self
+    }
+
+    /// 写入失败(如接收方断开)时调用, 只会调用一次
+    pub fn on_sink_error(
+        mut self,
+        handler: impl Fn(&std::io::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_sink_error = Some(Arc::new(handler));
+        self
+    }
+
+    fn write_chunk(&mut self, audio_bytes: &[u8]) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        let result = if self.length_prefixed {
+            stream
+                .write_all(&(audio_bytes.len() as u32).to_le_bytes())
+                .and_then(|_| stream.write_all(audio_bytes))
+        } else {
+            stream.write_all(audio_bytes)
+        };
+        if let Err(e) = result {
+            log::error!("写入Unix socket失败, 之后的音频将被丢弃: {}", e);
+            self.stream = None;
+            if let Some(handler) = &self.on_sink_error {
+                handler(&e);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl QwenTtsRealtimeCallback for UnixSocketSink {
+    fn on_open(&self) {}
+
+    fn on_close(&self, close_msg: &str) {
+        log::info!("Connection closed: {}", close_msg);
+    }
+
+    fn on_finish(&mut self, _close_msg: &str) {
+        // 关闭写端, 接收方读到EOF
+        if let Some(stream) = self.stream.take() {
+            let _ = stream.shutdown(std::net::Shutdown::Write);
+        }
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            return false;
+        };
+        match v["type"].as_str() {
+            Some("response.audio.delta") => {
+                if let Some(delta) = v["delta"].as_str() {
+                    match base64::engine::general_purpose::STANDARD.decode(delta) {
+                        Ok(audio_bytes) => self.write_chunk(&audio_bytes),
+                        Err(e) => log::error!("音频数据base64解码失败: {}", e),
+                    }
+                }
+            }
+            Some("session.finished") => return true,
+            _ => {}
+        }
+        false
+    }
+}
+
+///
+/// 16bit PCM 样本的字节序; 服务端输出和WAV(RIFF)文件都是小端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+///
+/// 按目标字节序写出 16bit PCM 的 Write 装饰器, 输入为服务端输出的小端PCM;
+/// 目标为大端时交换每个样本的两个字节, 被拆在两次 write 之间的半个样本暂存到下一次写入.
+/// 只用于裸PCM输出, WAV(RIFF)规定为小端, 不要用于 WavWriter
+pub struct ByteSwapSink<W: Write> {
+    inner: W,
+    target: Endianness,
+    pending: Option<u8>,
+}
+
+impl<W: Write> ByteSwapSink<W> {
+    pub fn new(inner: W, target: Endianness) -> Self {
+        Self {
+            inner,
+            target,
+            pending: None,
+        }
+    }
+
+    /// 取回内部的writer; 还有未凑满一个样本的字节时它会被丢弃
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for ByteSwapSink<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.target == Endianness::Little {
+            self.inner.write_all(buf)?;
+            return Ok(buf.len());
+        }
+        let mut swapped = Vec::with_capacity(buf.len() + 1);
+        let mut rest = buf;
+        if let Some(low) = self.pending.take() {
+            let Some((&high, tail)) = rest.split_first() else {
+                self.pending = Some(low);
+                return Ok(0);
+            };
+            swapped.extend_from_slice(&[high, low]);
+            rest = tail;
+        }
+        let mut samples = rest.chunks_exact(2);
+        for sample in &mut samples {
+            swapped.extend_from_slice(&[sample[1], sample[0]]);
+        }
+        self.pending = samples.remainder().first().copied();
+        self.inner.write_all(&swapped)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+///
+/// 把多声道PCM按帧取平均混成单声道的 Write 装饰器, 支持 16bit 和 24bit 小端PCM, 平均值四舍五入(远离零);
+/// 只有协商出的格式是多声道而目标是单声道时才混音, 否则原样写出. 被拆在两次 write 之间的半帧暂存到下一次写入
+pub struct DownmixSink<W: Write> {
+    inner: W,
+    // 需要混音时的 (声道数, 每个样本的字节数)
+    downmix: Option<(usize, usize)>,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> DownmixSink<W> {
+    /// negotiated 为服务端实际输出的格式, target 为下游需要的格式; 需要混音但位深不是16/24bit时返回错误
+    pub fn new(inner: W, negotiated: AudioFormat, target: AudioFormat) -> std::io::Result<Self> {
+        let channels = negotiated.channel_count() as usize;
+        let downmix = if channels > 1 && target.channel_count() == 1 {
+            let width = match negotiated.bits_per_sample() {
+                16 => 2,
+                24 => 3,
+                bits => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("DownmixSink 只支持16bit和24bit, 实际为{}bit", bits),
+                    ));
+                }
+            };
+            Some((channels, width))
+        } else {
+            None
+        };
+        Ok(Self {
+            inner,
+            downmix,
+            pending: vec![],
+        })
+    }
+
+    /// 取回内部的writer; 还有未凑满一帧的字节时它们会被丢弃
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for DownmixSink<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some((channels, width)) = self.downmix else {
+            self.inner.write_all(buf)?;
+            return Ok(buf.len());
+        };
+        self.pending.extend_from_slice(buf);
+        let whole = self.pending.len() / (channels * width) * (channels * width);
+        let mut mono = Vec::with_capacity(whole / channels);
+        for frame in self.pending[..whole].chunks_exact(channels * width) {
+            let sum = frame
+                .chunks_exact(width)
+                .map(|sample| match width {
+                    2 => i16::from_le_bytes([sample[0], sample[1]]) as i64,
+                    _ => (i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8) as i64,
+                })
+                .sum::<i64>();
+            let n = channels as i64;
+            // 整数除法向零截断, 先加上半个除数(与和同号)实现远离零的四舍五入
+            let average = (2 * sum + sum.signum() * n) / (2 * n);
+            mono.extend_from_slice(&(average as i32).to_le_bytes()[..width]);
+        }
+        self.pending.drain(..whole);
+        self.inner.write_all(&mono)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// RIFF 大小字段和 data 大小字段在44字节头中的偏移
+const WAV_HEADER_LEN: u64 = 44;
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+
+///
+/// WAV 头中大小字段的写入方式
+///
+/// - OnFinalize: 只在 finalize/drop 时回填, 开销最小; 进程崩溃时大小为0, 大多数播放器无法播放
+/// - Periodic: 每隔 interval 把缓冲落盘并按已写入字节数回填, 崩溃时最多丢失一个间隔内的音频,
+///   代价是每次回填都要两次seek和一次flush
+/// - Streaming: 一开始就写入最大大小(0xFFFFFFFF), 崩溃后播放器会一直读到文件末尾;
+///   不需要额外seek, 但崩溃留下的文件大小字段不准确, 部分严格的解码器会报警告
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WavHeaderMode {
+    #[default]
+    OnFinalize,
+    Periodic { interval: Duration },
+    Streaming,
+}
+
+/// WAV fmt 块中与合并/校验相关的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+pub(crate) fn write_wav_header(w: &mut impl Write, spec: WavSpec, data_size: u32) -> std::io::Result<()> {
+    let block_align = spec.channels * spec.bits_per_sample / 8;
+    let byte_rate = spec.sample_rate * block_align as u32;
+    w.write_all(b"RIFF")?;
+    w.write_all(&data_size.saturating_add(WAV_HEADER_LEN as u32 - 8).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    // 1 表示 PCM
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&spec.channels.to_le_bytes())?;
+    w.write_all(&spec.sample_rate.to_le_bytes())?;
+    w.write_all(&byte_rate.to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&spec.bits_per_sample.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())
+}
+
+fn invalid_wav(path: &Path, reason: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path.display(), reason),
+    )
+}
+
+///
+/// 解析WAV文件, 返回 (格式, data块偏移, data字节数);
+/// 声明的data大小超过文件实际长度时(流式写入或崩溃留下的文件)以实际长度为准
+fn read_wav_layout(path: &Path) -> std::io::Result<(WavSpec, u64, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let file_len = reader.get_ref().metadata()?.len();
+    let mut riff = [0u8; 12];
+    reader.read_exact(&mut riff)?;
+    if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(invalid_wav(path, "不是RIFF/WAVE文件"));
+    }
+    let mut spec = None;
+    let mut offset = 12u64;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        reader.read_exact(&mut chunk_header).map_err(|_| invalid_wav(path, "缺少data块"))?;
+        offset += 8;
+        let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as u64;
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                let mut fmt = vec![0u8; chunk_size as usize];
+                reader.read_exact(&mut fmt)?;
+                if fmt.len() < 16 || u16::from_le_bytes([fmt[0], fmt[1]]) != 1 {
+                    return Err(invalid_wav(path, "只支持PCM编码"));
+                }
+                spec = Some(WavSpec {
+                    channels: u16::from_le_bytes([fmt[2], fmt[3]]),
+                    sample_rate: u32::from_le_bytes(fmt[4..8].try_into().unwrap()),
+                    bits_per_sample: u16::from_le_bytes([fmt[14], fmt[15]]),
+                });
+            }
+            b"data" => {
+                let spec = spec.ok_or_else(|| invalid_wav(path, "data块之前没有fmt块"))?;
+                return Ok((spec, offset, chunk_size.min(file_len - offset)));
+            }
+            _ => {
+                reader.seek_relative(chunk_size as i64)?;
+            }
+        }
+        // 奇数大小的块有一个填充字节
+        let padded = chunk_size + (chunk_size & 1);
+        if chunk_size & 1 == 1 {
+            reader.seek_relative(1)?;
+        }
+        offset += padded;
+    }
+}
+
+///
+/// 把多个WAV依次拼接成一个文件并写入正确的头; 所有输入的声道数、采样率、位深必须一致
+pub fn merge_wavs(inputs: &[PathBuf], output: &Path) -> std::io::Result<()> {
+    let mut layouts = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let layout = read_wav_layout(input)?;
+        if let Some((first_spec, _, _)) = layouts.first()
+            && *first_spec != layout.0
+        {
+            return Err(invalid_wav(
+                input,
+                &format!("格式 {:?} 与第一个输入 {:?} 不一致", layout.0, first_spec),
+            ));
+        }
+        layouts.push(layout);
+    }
+    let Some((spec, _, _)) = layouts.first().copied() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "merge_wavs 至少需要一个输入",
+        ));
+    };
+    let total = layouts.iter().map(|(_, _, len)| len).sum::<u64>();
+    let total = u32::try_from(total).map_err(|_| invalid_wav(output, "合并后超过WAV的4GB上限"))?;
+    let mut writer = BufWriter::new(File::create(output)?);
+    write_wav_header(&mut writer, spec, total)?;
+    for (input, (_, data_offset, data_len)) in inputs.iter().zip(layouts) {
+        let mut file = File::open(input)?;
+        file.seek(SeekFrom::Start(data_offset))?;
+        std::io::copy(&mut file.take(data_len), &mut writer)?;
+    }
+    writer.flush()
+}
+
+///
+/// 把PCM写成WAV文件, 大小字段的回填方式见 WavHeaderMode;
+/// WAV 的样本固定为小端, write_samples 传入服务端输出的PCM原样写入, 不做字节序转换
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    format: AudioFormat,
+    mode: WavHeaderMode,
+    data_bytes: u64,
+    last_patch: Instant,
+    finalized: bool,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, format: AudioFormat, mode: WavHeaderMode) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            create_dir_all(parent)?;
+        }
+        let mut wav_writer = Self {
+            writer: BufWriter::new(File::create(path)?),
+            format,
+            mode,
+            data_bytes: 0,
+            last_patch: Instant::now(),
+            finalized: false,
+        };
+        let declared = if mode == WavHeaderMode::Streaming {
+            u32::MAX - (WAV_HEADER_LEN as u32 - 8)
+        } else {
+            0
+        };
+        wav_writer.write_header(declared)?;
+        Ok(wav_writer)
+    }
+
+    fn write_header(&mut self, data_size: u32) -> std::io::Result<()> {
+        self.writer.write_all(&self.format.wav_header(data_size))
+    }
+
+    pub fn write_samples(&mut self, audio_bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(audio_bytes)?;
+        self.data_bytes += audio_bytes.len() as u64;
+        if let WavHeaderMode::Periodic { interval } = self.mode
+            && self.last_patch.elapsed() >= interval
+        {
+            self.patch_sizes()?;
+        }
+        Ok(())
+    }
+
+    /// 已写入的音频字节数(不含头)
+    pub fn data_bytes(&self) -> u64 {
+        self.data_bytes
+    }
+
+    /// 把缓冲落盘并按已写入的字节数回填 RIFF/data 大小
+    fn patch_sizes(&mut self) -> std::io::Result<()> {
+        let data_size = u32::try_from(self.data_bytes).unwrap_or(u32::MAX - 36);
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+        file.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        file.write_all(&data_size.saturating_add(WAV_HEADER_LEN as u32 - 8).to_le_bytes())?;
+        file.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        file.write_all(&data_size.to_le_bytes())?;
+        file.seek(SeekFrom::End(0))?;
+        file.sync_data()?;
+        self.last_patch = Instant::now();
+        Ok(())
+    }
+
+    /// 写入正确的大小并落盘, drop 时也会自动调用
+    pub fn finalize(&mut self) -> std::io::Result<()> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.patch_sizes()?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finalize() {
+            log::error!("回填WAV头失败: {}", e);
+        }
+    }
+}
+
+///
+/// 把PCM分片编码成WAV字节流, 用于HTTP响应边合成边发送; 第一次 encode 的输出以44字节的头开头
+///
+/// - new: 总长度未知, 头中的大小与 WavHeaderMode::Streaming 相同(最大值), 播放器读到连接结束为止;
+///   此时没有 Content-Length, 只能用分块传输, 不支持 Range 请求
+/// - with_data_len: 已知PCM总字节数, 头中写入正确的大小, total_len 可直接作为 Content-Length;
+///   超出的音频被截断, finish 时不足的部分补静音, 保证实际发送的字节数与 Content-Length 一致
+///
+/// 流式输出无法真正支持拖动: 播放器按 Range 请求尚未合成的位置时服务端还没有这部分音频.
+/// 需要拖动时先用 synthesize_to_vec 等方式缓冲完整音频, 再以 with_data_len(音频长度) 编码,
+/// 按 Range 从完整的字节中截取
+pub struct WavStreamEncoder {
+    format: AudioFormat,
+    data_len: Option<u32>,
+    header_sent: bool,
+    written: u64,
+}
+
+impl WavStreamEncoder {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            data_len: None,
+            header_sent: false,
+            written: 0,
+        }
+    }
+
+    pub fn with_data_len(format: AudioFormat, data_len: u32) -> Self {
+        Self {
+            data_len: Some(data_len),
+            ..Self::new(format)
+        }
+    }
+
+    /// 完整WAV的字节数(头+数据), 总长度未知时为None
+    pub fn total_len(&self) -> Option<u64> {
+        self.data_len.map(|data_len| WAV_HEADER_LEN + data_len as u64)
+    }
+
+    /// 已输出的PCM字节数(不含头)
+    pub fn data_bytes(&self) -> u64 {
+        self.written
+    }
+
+    pub fn encode(&mut self, audio_bytes: &[u8]) -> Vec<u8> {
+        let audio_bytes = match self.data_len {
+            Some(data_len) => {
+                let remaining = (data_len as u64).saturating_sub(self.written) as usize;
+                &audio_bytes[..audio_bytes.len().min(remaining)]
+            }
+            None => audio_bytes,
+        };
+        let mut out = self.take_header();
+        out.extend_from_slice(audio_bytes);
+        self.written += audio_bytes.len() as u64;
+        out
+    }
+
+    /// 结束编码, 已知总长度时补齐不足的部分; 之后不应再 encode
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut out = self.take_header();
+        if let Some(data_len) = self.data_len {
+            let missing = (data_len as u64).saturating_sub(self.written);
+            out.resize(out.len() + missing as usize, 0);
+            self.written += missing;
+        }
+        out
+    }
+
+    fn take_header(&mut self) -> Vec<u8> {
+        if self.header_sent {
+            return vec![];
+        }
+        self.header_sent = true;
+        let declared = self
+            .data_len
+            .unwrap_or(u32::MAX - (WAV_HEADER_LEN as u32 - 8));
+        self.format.wav_header(declared)
+    }
+}
+
+///
+/// 把 response.audio.delta 解码后写成可直接播放的WAV文件的回调, 只适用于PCM格式
+///
+/// on_open 时创建文件并写入占位的头, 之后逐个分片追加; 收到 session.finished 或 on_finish 时
+/// 按实际写入的字节数回填头中的大小字段, 连接异常断开时得到的也是可播放的文件
+pub struct WavSink {
+    path: PathBuf,
+    format: AudioFormat,
+    mode: WavHeaderMode,
+    // on_open 只拿到 &self, 写入端在其中创建
+    writer: Mutex<Option<WavWriter>>,
+}
+
+impl WavSink {
+    pub fn new(path: impl AsRef<Path>, format: AudioFormat) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            format,
+            mode: WavHeaderMode::default(),
+            writer: Mutex::new(None),
+        }
+    }
+
+    /// 大小字段的回填方式, 默认 WavHeaderMode::OnFinalize
+    pub fn header_mode(mut self, mode: WavHeaderMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    fn finalize(&self) {
+        if let Some(writer) = self.writer.lock().unwrap().as_mut()
+            && let Err(e) = writer.finalize()
+        {
+            log::error!("回填WAV头失败: {}", e);
+        }
+    }
+}
+
+impl QwenTtsRealtimeCallback for WavSink {
+    fn on_open(&self) {
+        match WavWriter::create(&self.path, self.format, self.mode) {
+            Ok(writer) => *self.writer.lock().unwrap() = Some(writer),
+            Err(e) => log::error!("创建WAV文件 {} 失败: {}", self.path.display(), e),
+        }
+    }
+
+    fn on_close(&self, close_msg: &str) {
+        log::info!("Connection closed: {}", close_msg);
+    }
+
+    fn on_finish(&mut self, _close_msg: &str) {
+        self.finalize();
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            return false;
+        };
+        match v["type"].as_str() {
+            Some("response.audio.delta") => {
+                let Some(delta) = v["delta"].as_str() else {
+                    return false;
+                };
+                match base64::engine::general_purpose::STANDARD.decode(delta) {
+                    Ok(audio_bytes) => {
+                        if let Some(writer) = self.writer.lock().unwrap().as_mut()
+                            && let Err(e) = writer.write_samples(&audio_bytes)
+                        {
+                            log::error!("写入WAV文件失败: {}", e);
+                        }
+                    }
+                    Err(e) => log::error!("音频数据base64解码失败: {}", e),
+                }
+            }
+            Some("session.finished") => self.finalize(),
+            _ => {}
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::mock_server::audio_delta;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}", uuid::Uuid::new_v4(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// 按WAV头解析出 (声明的data大小, 实际可读的data字节)
+    fn parse_wav(path: &str) -> (u32, Vec<u8>) {
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([bytes[20], bytes[21]]), 1);
+        assert_eq!(&bytes[36..40], b"data");
+        let riff_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let data_size = u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+        assert_eq!(riff_size, data_size.saturating_add(36));
+        let available = (bytes.len() - 44).min(data_size as usize);
+        (data_size, bytes[44..44 + available].to_vec())
+    }
+
+    #[test]
+    fn test_byte_swap_sink_big_endian() {
+        let samples = [0x1234i16, -2, 0x00FF];
+        let le = samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<_>>();
+        let be = samples.iter().flat_map(|s| s.to_be_bytes()).collect::<Vec<_>>();
+
+        let mut little = ByteSwapSink::new(Vec::new(), Endianness::Little);
+        little.write_all(&le).unwrap();
+        assert_eq!(little.into_inner(), le);
+
+        // 奇数长度的分片: 样本被拆在两次写入之间
+        let mut big = ByteSwapSink::new(Vec::new(), Endianness::Big);
+        big.write_all(&le[..3]).unwrap();
+        big.write_all(&le[3..]).unwrap();
+        assert_eq!(big.into_inner(), be);
+    }
+
+    #[test]
+    fn test_downmix_sink_averages_channels() {
+        let stereo16 = AudioFormat::new("pcm", 24000, "stereo", "16bit", "pcm16");
+        let mono16 = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        // (L, R) -> 平均值, .5 远离零取整
+        let frames = [
+            (100i16, 200i16),
+            (1, 2),
+            (-1, -2),
+            (-3, 0),
+            (32767, 32767),
+            (-32768, 32767),
+        ];
+        let input = frames
+            .iter()
+            .flat_map(|(l, r)| [l.to_le_bytes(), r.to_le_bytes()].concat())
+            .collect::<Vec<_>>();
+        let expected = [150i16, 2, -2, -2, 32767, -1]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut sink = DownmixSink::new(Vec::new(), stereo16, mono16).unwrap();
+        // 帧被拆在两次写入之间
+        sink.write_all(&input[..5]).unwrap();
+        sink.write_all(&input[5..]).unwrap();
+        assert_eq!(sink.into_inner(), expected);
+
+        let stereo24 = AudioFormat::new("pcm", 24000, "stereo", "24bit", "pcm24");
+        let mono24 = AudioFormat::new("pcm", 24000, "mono", "24bit", "pcm24");
+        let to_24 = |s: i32| s.to_le_bytes()[..3].to_vec();
+        let frames24 = [
+            (8_388_607, 8_388_607),
+            (-8_388_608, 8_388_607),
+            (1_000_001, 2_000_000),
+            (-5, -4),
+        ];
+        let input = frames24
+            .iter()
+            .flat_map(|(l, r)| [to_24(*l), to_24(*r)].concat())
+            .collect::<Vec<_>>();
+        let expected = [8_388_607, -1, 1_500_001, -5]
+            .into_iter()
+            .flat_map(to_24)
+            .collect::<Vec<_>>();
+        let mut sink = DownmixSink::new(Vec::new(), stereo24, mono24).unwrap();
+        sink.write_all(&input).unwrap();
+        assert_eq!(sink.into_inner(), expected);
+
+        // 已经是单声道时原样写出; 不支持的位深返回错误
+        let mut passthrough = DownmixSink::new(Vec::new(), mono16, mono16).unwrap();
+        passthrough.write_all(&[1, 2, 3]).unwrap();
+        assert_eq!(passthrough.into_inner(), vec![1, 2, 3]);
+        let stereo8 = AudioFormat::new("pcm", 24000, "stereo", "8bit", "pcm8");
+        assert!(DownmixSink::new(Vec::new(), stereo8, mono16).is_err());
+    }
+
+    #[test]
+    fn test_wav_writer_finalize_on_drop() {
+        let path = temp_path("drop.wav");
+        let mut wav_writer = WavWriter::create(
+            Path::new(&path),
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            WavHeaderMode::OnFinalize,
+        )
+        .unwrap();
+        wav_writer.write_samples(&[1, 2, 3, 4]).unwrap();
+        drop(wav_writer);
+        assert_eq!(parse_wav(&path), (4, vec![1, 2, 3, 4]));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_wav_writer_periodic_survives_crash() {
+        let path = temp_path("crash.wav");
+        let mut wav_writer = WavWriter::create(
+            Path::new(&path),
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            WavHeaderMode::Periodic {
+                interval: Duration::ZERO,
+            },
+        )
+        .unwrap();
+        wav_writer.write_samples(&[1, 2]).unwrap();
+        wav_writer.write_samples(&[3, 4]).unwrap();
+        // 模拟崩溃: 不执行drop
+        std::mem::forget(wav_writer);
+        assert_eq!(parse_wav(&path), (4, vec![1, 2, 3, 4]));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_wav_writer_streaming_header() {
+        let path = temp_path("streaming.wav");
+        let mut wav_writer = WavWriter::create(
+            Path::new(&path),
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            WavHeaderMode::Streaming,
+        )
+        .unwrap();
+        wav_writer.write_samples(&[1, 2, 3, 4]).unwrap();
+        wav_writer.writer.flush().unwrap();
+        // 崩溃时声明的是最大大小, 播放器读到文件末尾为止
+        let (declared, data) = parse_wav(&path);
+        assert_eq!(declared, u32::MAX - 36);
+        assert_eq!(data, vec![1, 2, 3, 4]);
+        wav_writer.finalize().unwrap();
+        assert_eq!(parse_wav(&path), (4, vec![1, 2, 3, 4]));
+        drop(wav_writer);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn write_wav(name: &str, format: AudioFormat, data: &[u8]) -> PathBuf {
+        let path = PathBuf::from(temp_path(name));
+        let mut wav_writer = WavWriter::create(&path, format, WavHeaderMode::OnFinalize).unwrap();
+        wav_writer.write_samples(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_merge_wavs() {
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        let first = write_wav("first.wav", format, &[1, 2, 3, 4]);
+        let second = write_wav("second.wav", format, &[5, 6]);
+        let output = PathBuf::from(temp_path("merged.wav"));
+        merge_wavs(&[first.clone(), second.clone()], &output).unwrap();
+        assert_eq!(
+            std::fs::metadata(&output).unwrap().len(),
+            WAV_HEADER_LEN + 6
+        );
+        assert_eq!(
+            parse_wav(output.to_str().unwrap()),
+            (6, vec![1, 2, 3, 4, 5, 6])
+        );
+        let (spec, data_offset, data_len) = read_wav_layout(&output).unwrap();
+        assert_eq!(
+            spec,
+            WavSpec {
+                channels: 1,
+                sample_rate: 24000,
+                bits_per_sample: 16
+            }
+        );
+        assert_eq!((data_offset, data_len), (WAV_HEADER_LEN, 6));
+        for path in [first, second, output] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_merge_wavs_rejects_mismatched_format() {
+        let first = write_wav("mono.wav", AudioFormat::PCM_24000HZ_MONO_16BIT, &[1, 2]);
+        let second = PathBuf::from(temp_path("stereo.wav"));
+        {
+            let mut file = File::create(&second).unwrap();
+            let spec = WavSpec {
+                channels: 2,
+                sample_rate: 24000,
+                bits_per_sample: 16,
+            };
+            write_wav_header(&mut file, spec, 4).unwrap();
+            file.write_all(&[1, 2, 3, 4]).unwrap();
+        }
+        let output = PathBuf::from(temp_path("bad_merge.wav"));
+        let err = merge_wavs(&[first.clone(), second.clone()], &output).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(!output.exists());
+        for path in [first, second] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_flush_interval() {
+        let path = temp_path("flush.pcm");
+        let mut sink = FileSinkCallback::new(&path)
+            .unwrap()
+            .with_flush_interval(Duration::from_millis(50));
+        sink.on_event(&audio_delta(&[1, 2, 3, 4]));
+        // BufWriter 还没写满, 数据仍在进程内
+        assert!(std::fs::read(&path).unwrap().is_empty());
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3, 4]);
+
+        sink.on_event(&audio_delta(&[5, 6]));
+        sink.on_finish("done");
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3, 4, 5, 6]);
+        assert!(sink.flush_task.is_none());
+        drop(sink);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_wav_stream_encoder_matches_content_length() {
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        let data_len_of = |bytes: &[u8]| u32::from_le_bytes(bytes[40..44].try_into().unwrap());
+        let riff_len_of = |bytes: &[u8]| u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+
+        // 已知长度: 超出的部分被截断, 总字节数与 total_len 一致
+        let mut encoder = WavStreamEncoder::with_data_len(format, 10);
+        assert_eq!(encoder.total_len(), Some(54));
+        let mut body = encoder.encode(b"hello");
+        body.extend(encoder.encode(b"world!!"));
+        body.extend(encoder.finish());
+        assert_eq!(body.len() as u64, encoder.total_len().unwrap());
+        assert_eq!(body[..44], format.wav_header(10)[..]);
+        assert_eq!(riff_len_of(&body) as usize, body.len() - 8);
+        assert_eq!(&body[44..], b"helloworld");
+
+        // 音频比声明的短时补静音
+        let mut encoder = WavStreamEncoder::with_data_len(format, 8);
+        let mut body = encoder.encode(b"abc");
+        body.extend(encoder.finish());
+        assert_eq!(body.len(), 52);
+        assert_eq!(data_len_of(&body), 8);
+        assert_eq!(&body[44..], b"abc\0\0\0\0\0");
+
+        // 未知长度: 与 WavHeaderMode::Streaming 相同的最大大小, 没有 Content-Length
+        let mut encoder = WavStreamEncoder::new(format);
+        assert_eq!(encoder.total_len(), None);
+        let body = encoder.encode(b"pcm");
+        assert_eq!(riff_len_of(&body), u32::MAX);
+        assert_eq!(&body[44..], b"pcm");
+        assert_eq!(encoder.encode(b"more"), b"more");
+        assert!(encoder.finish().is_empty());
+        assert_eq!(encoder.data_bytes(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_wav_sink_writes_playable_file() {
+        use crate::dashscope::mock_server::MockServerBuilder;
+        use crate::dashscope::qwen_tts_realtime::{QwenTtsRealtimeBuilder, SharedCallback};
+
+        let path = temp_path("sink.wav");
+        let server = MockServerBuilder::new().spawn().await;
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        let callback: SharedCallback = Arc::new(tokio::sync::Mutex::new(Box::new(WavSink::new(
+            &path, format,
+        ))));
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(callback)
+            .connect()
+            .await
+            .unwrap();
+        // 连接建立时已经创建了文件
+        assert!(Path::new(&path).exists());
+        qwen_tts_realtime
+            .update_session("Cherry", format, "server_commit")
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("hello").await.unwrap();
+        qwen_tts_realtime.append_text("world").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+        qwen_tts_realtime
+            .wait_until_finished(Duration::from_secs(5))
+            .await;
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[..44], format.wav_header(10)[..]);
+        assert_eq!(u16::from_le_bytes([bytes[22], bytes[23]]), 1);
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 24000);
+        assert_eq!(u16::from_le_bytes([bytes[34], bytes[35]]), 16);
+        assert_eq!(parse_wav(&path), (10, b"helloworld".to_vec()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_sink_length_prefixed() {
+        use crate::dashscope::mock_server::MockServerBuilder;
+        use crate::dashscope::qwen_tts_realtime::QwenTtsRealtimeBuilder;
+        use std::os::unix::net::UnixListener;
+
+        let socket_path = temp_path("audio.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let receiver = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut frames = vec![];
+            let mut len = [0u8; 4];
+            // 发送方 shutdown 写端后读到EOF
+            while stream.read_exact(&mut len).is_ok() {
+                let mut frame = vec![0u8; u32::from_le_bytes(len) as usize];
+                stream.read_exact(&mut frame).unwrap();
+                frames.push(frame);
+            }
+            frames
+        });
+
+        let server = MockServerBuilder::new().spawn().await;
+        let sink = UnixSocketSink::connect(&socket_path)
+            .unwrap()
+            .length_prefixed(true);
+        let mut qwen_tts_realtime = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .callback(Arc::new(tokio::sync::Mutex::new(Box::new(sink))))
+            .connect()
+            .await
+            .unwrap();
+        qwen_tts_realtime.append_text("你好").await.unwrap();
+        qwen_tts_realtime.append_text("世界").await.unwrap();
+        qwen_tts_realtime.finish().await.unwrap();
+
+        let frames = tokio::task::spawn_blocking(move || receiver.join().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(frames, vec!["你好".as_bytes().to_vec(), "世界".as_bytes().to_vec()]);
+        let _ = std::fs::remove_file(&socket_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_sink_receiver_disconnect() {
+        use std::os::unix::net::UnixListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let socket_path = temp_path("closed.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let errors = Arc::new(AtomicUsize::new(0));
+        let errors_clone = Arc::clone(&errors);
+        let mut sink = UnixSocketSink::connect(&socket_path)
+            .unwrap()
+            .on_sink_error(move |_| {
+                errors_clone.fetch_add(1, Ordering::SeqCst);
+            });
+        let (stream, _) = listener.accept().unwrap();
+        drop(stream);
+        for _ in 0..3 {
+            assert!(!sink.on_event(&audio_delta(&[1, 2, 3, 4])));
+        }
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        assert!(sink.stream.is_none());
+        let _ = std::fs::remove_file(&socket_path);
+    }
+}
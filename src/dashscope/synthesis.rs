@@ -0,0 +1,1555 @@
+use crate::common::errors::QwenTtsError;
+use crate::common::errors::GenerationError;
+use crate::dashscope::checkpoint::{resume_from_checkpoint, save_checkpoint};
+use crate::dashscope::dashscope_rs::Generation;
+use crate::dashscope::language::detect_language;
+use crate::dashscope::qwen_tts_realtime::{
+    AudioFormat, QwenTtsRealtime, QwenTtsRealtimeBuilder, QwenTtsRealtimeCallback, ServerEvent,
+    SessionConfig, reconcile_audio_format,
+};
+use crate::dashscope::sinks::{WavHeaderMode, WavWriter};
+use crate::dashscope::transforms::PcmConverter;
+use base64::Engine;
+use futures_util::{Stream, StreamExt};
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{Mutex, oneshot};
+use uuid::Uuid;
+
+/// 合成结果的输出文件, 目标格式为wav时写成带头的WAV, 否则原样写入
+enum AudioOutput {
+    Raw(File),
+    Wav(WavWriter),
+}
+
+impl AudioOutput {
+    fn write(&mut self, audio_bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            AudioOutput::Raw(file) => file.write_all(audio_bytes),
+            AudioOutput::Wav(wav_writer) => wav_writer.write_samples(audio_bytes),
+        }
+    }
+
+    fn finalize(&mut self) -> std::io::Result<()> {
+        match self {
+            AudioOutput::Raw(file) => file.flush(),
+            AudioOutput::Wav(wav_writer) => wav_writer.finalize(),
+        }
+    }
+}
+
+///
+/// 把音频写入文件的内部回调, 读取任务结束时通过done告知是否收到了session.finished
+struct FileSynthesisCallback {
+    output: AudioOutput,
+    bytes_written: Arc<AtomicU64>,
+    session_finished: bool,
+    done: Option<oneshot::Sender<bool>>,
+    requested_format: AudioFormat,
+    target_format: Option<AudioFormat>,
+    // 收到第一个音频分片、确定服务端实际格式后才创建; 内层None表示不需要转换
+    converter: Option<Option<PcmConverter>>,
+}
+
+impl FileSynthesisCallback {
+    /// 按服务端实际返回的格式决定是否需要转换到目标格式
+    fn converter_for(&self, frame: &Value) -> Option<PcmConverter> {
+        let target = self.target_format?;
+        let (negotiated, _) = reconcile_audio_format(self.requested_format, frame);
+        if negotiated.format() != "pcm" {
+            log::warn!("{} 格式无法转换, 按原样写入", negotiated.format());
+            return None;
+        }
+        if PcmConverter::is_identity(negotiated, target) {
+            return None;
+        }
+        log::info!("音频格式 {} 将转换为 {}", negotiated, target);
+        Some(PcmConverter::new(negotiated, target))
+    }
+}
+
+impl QwenTtsRealtimeCallback for FileSynthesisCallback {
+    fn on_open(&self) {}
+
+    fn on_close(&self, close_msg: &str) {
+        log::info!("synthesize_to_file connection closed: {}", close_msg);
+    }
+
+    fn on_finish(&mut self, _close_msg: &str) {
+        if let Err(e) = self.output.finalize() {
+            log::error!("写入音频文件失败: {}", e);
+        }
+        if let Some(done) = self.done.take() {
+            let _ = done.send(self.session_finished);
+        }
+    }
+
+    fn on_event(&mut self, message: &str) -> bool {
+        let Ok(v) = serde_json::from_str::<Value>(message) else {
+            log::warn!("无法解析的事件: {}", message);
+            return false;
+        };
+        match v["type"].as_str() {
+            Some("response.audio.delta") => {
+                if let Some(delta) = v["delta"].as_str() {
+                    let audio_bytes = match base64::engine::general_purpose::STANDARD.decode(delta)
+                    {
+                        Ok(audio_bytes) => audio_bytes,
+                        Err(e) => {
+                            log::error!("音频数据base64解码失败: {}", e);
+                            return true;
+                        }
+                    };
+                    if self.converter.is_none() {
+                        self.converter = Some(self.converter_for(&v));
+                    }
+                    let audio_bytes = match self.converter.as_mut().and_then(Option::as_mut) {
+                        Some(converter) => converter.convert(&audio_bytes),
+                        None => audio_bytes,
+                    };
+                    if let Err(e) = self.output.write(&audio_bytes) {
+                        log::error!("写入音频文件失败: {}", e);
+                        return true;
+                    }
+                    self.bytes_written
+                        .fetch_add(audio_bytes.len() as u64, Ordering::SeqCst);
+                }
+            }
+            Some("session.finished") => {
+                self.session_finished = true;
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+async fn synthesize_to_file_once(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    target_format: Option<AudioFormat>,
+    texts: &[&str],
+    path: &Path,
+    bytes_written: Arc<AtomicU64>,
+) -> Result<(), QwenTtsError> {
+    let output = match target_format {
+        Some(target) if target.format() == "wav" => {
+            AudioOutput::Wav(WavWriter::create(path, target, WavHeaderMode::OnFinalize)?)
+        }
+        _ => AudioOutput::Raw(File::create(path)?),
+    };
+    let (done_tx, done_rx) = oneshot::channel();
+    let callback = FileSynthesisCallback {
+        output,
+        bytes_written,
+        session_finished: false,
+        done: Some(done_tx),
+        requested_format: response_format,
+        target_format,
+        converter: None,
+    };
+    let mut qwen_tts_realtime = builder
+        .clone()
+        .callback(Arc::new(Mutex::new(Box::new(callback))))
+        .connect()
+        .await?;
+    qwen_tts_realtime
+        .update_session(voice, response_format, "server_commit")
+        .await?;
+    for text in texts {
+        qwen_tts_realtime.append_text(text).await?;
+    }
+    qwen_tts_realtime.finish().await?;
+    match done_rx.await {
+        Ok(true) => Ok(()),
+        _ => match qwen_tts_realtime.close_info() {
+            Some(close_info) if close_info.reconnect_hint => Err(QwenTtsError::ReconnectRequested(
+                format!("{:?} {}", close_info.close_code, close_info.close_reason),
+            )),
+            _ => Err(QwenTtsError::SessionClosedEarly(
+                "连接在收到session.finished之前断开".to_string(),
+            )),
+        },
+    }
+}
+
+///
+/// 退避时间的随机抖动, 避免大量客户端(如服务端重启时)同时断开后在同一时刻重连
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Jitter {
+    /// 不抖动, 严格按指数退避
+    None,
+    /// 在 [0, 退避时间] 内均匀取值
+    #[default]
+    Full,
+    /// 一半固定, 另一半在 [0, 退避时间/2] 内均匀取值
+    Equal,
+    /// 在 [initial, 上一次等待时间*3] 内均匀取值, 不超过max; 只依赖上一次的等待时间, 与尝试次数无关
+    Decorrelated,
+}
+
+/// [0, 1) 内均匀分布的随机数, 取 UUIDv4 中不含版本/变体位的62位
+fn random_unit() -> f64 {
+    let bits = Uuid::new_v4().as_u128() as u64 & ((1 << 62) - 1);
+    (bits >> 9) as f64 / (1u64 << 53) as f64
+}
+
+///
+/// 连接失败后的重试策略: 最多尝试 max_attempts 次, 两次尝试之间按指数退避等待, 默认 Jitter::Full
+///
+/// 等待时间的优先级: 服务端要求重连(ReconnectRequested)时立即重连, 不退避;
+/// 服务端给出 Retry-After 时按其等待; 否则按退避时间等待. 默认不退避
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: Jitter,
+}
+
+impl ReconnectPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            jitter: Jitter::default(),
+        }
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// 第一次重试前等待initial, 之后每次翻倍, 最多等待max
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// 第attempt次尝试(从1开始)因error失败后, 下一次尝试前的等待时间;
+    /// Decorrelated 以不抖动的上一次退避时间代替实际的上一次等待时间, 需要准确时用 delay_with_previous
+    pub fn delay(&self, attempt: u32, error: &QwenTtsError) -> Duration {
+        let previous = self.exponential(attempt.saturating_sub(1).max(1));
+        self.delay_with_previous(attempt, error, previous)
+    }
+
+    /// 与 delay 相同, previous 为上一次实际等待的时间(第一次传 Duration::ZERO), 只有 Decorrelated 使用
+    pub fn delay_with_previous(
+        &self,
+        attempt: u32,
+        error: &QwenTtsError,
+        previous: Duration,
+    ) -> Duration {
+        if matches!(error, QwenTtsError::ReconnectRequested(_)) {
+            return Duration::ZERO;
+        }
+        if let Some(retry_after) = error.retry_after() {
+            return retry_after;
+        }
+        let backoff = self.exponential(attempt);
+        match self.jitter {
+            Jitter::None => backoff,
+            Jitter::Full => backoff.mul_f64(random_unit()),
+            Jitter::Equal => backoff / 2 + (backoff / 2).mul_f64(random_unit()),
+            Jitter::Decorrelated => {
+                let low = self.initial_backoff;
+                let high = previous.max(low).saturating_mul(3).min(self.max_backoff);
+                (low + high.saturating_sub(low).mul_f64(random_unit())).min(self.max_backoff)
+            }
+        }
+    }
+
+    fn exponential(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+///
+/// 合成全部文本并写入path, 返回写入的音频字节数
+///
+/// 遇到临时性错误(网络抖动、握手返回5xx、连接中途断开)时整体重试, 最多max_attempts次;
+/// 重试只在尚未写入任何音频时进行, 一旦开始写入音频就直接返回错误, 避免输出重复的音频
+pub async fn synthesize_to_file(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    texts: &[&str],
+    path: &Path,
+    max_attempts: u32,
+) -> Result<u64, QwenTtsError> {
+    synthesize_to_file_with_policy(
+        builder,
+        voice,
+        response_format,
+        texts,
+        path,
+        ReconnectPolicy::new(max_attempts),
+    )
+    .await
+}
+
+/// 与 synthesize_to_file 相同, 重试间隔由 ReconnectPolicy 决定
+pub async fn synthesize_to_file_with_policy(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    texts: &[&str],
+    path: &Path,
+    policy: ReconnectPolicy,
+) -> Result<u64, QwenTtsError> {
+    synthesize_to_file_with_target(builder, voice, response_format, None, texts, path, policy)
+        .await
+}
+
+///
+/// 与 synthesize_to_file_with_policy 相同, target_format 与服务端实际返回的PCM格式
+/// (采样率、声道数、位深)不一致时自动转换后写入; target_format 为wav时写成WAV文件.
+/// 返回值为转换后写入的字节数; 服务端返回压缩格式(mp3/opus)时无法转换, 按原样写入
+pub async fn synthesize_to_file_with_target(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    target_format: Option<AudioFormat>,
+    texts: &[&str],
+    path: &Path,
+    policy: ReconnectPolicy,
+) -> Result<u64, QwenTtsError> {
+    let max_attempts = policy.max_attempts;
+    let bytes_written = Arc::new(AtomicU64::new(0));
+    let mut attempt = 1;
+    let mut previous_delay = Duration::ZERO;
+    loop {
+        log::info!("synthesize_to_file: 第 {}/{} 次尝试", attempt, max_attempts);
+        let result = synthesize_to_file_once(
+            builder,
+            voice,
+            response_format,
+            target_format,
+            texts,
+            path,
+            Arc::clone(&bytes_written),
+        )
+        .await;
+        let written = bytes_written.load(Ordering::SeqCst);
+        match result {
+            Ok(()) => {
+                log::info!(
+                    "synthesize_to_file: 第 {} 次尝试成功, 写入 {} 字节",
+                    attempt,
+                    written
+                );
+                return Ok(written);
+            }
+            Err(e) if written > 0 => {
+                log::error!(
+                    "synthesize_to_file: 第 {} 次尝试失败, 已写入 {} 字节音频, 不再重试: {}",
+                    attempt,
+                    written,
+                    e
+                );
+                return Err(e);
+            }
+            Err(e) if !e.is_transient() || attempt >= max_attempts => {
+                log::error!("synthesize_to_file: 第 {} 次尝试失败: {}", attempt, e);
+                return Err(e);
+            }
+            Err(e) => {
+                log::warn!(
+                    "synthesize_to_file: 第 {} 次尝试失败(尚未写入音频), 准备重试: {}",
+                    attempt,
+                    e
+                );
+                let delay = policy.delay_with_previous(attempt, &e, previous_delay);
+                previous_delay = delay;
+                if !delay.is_zero() {
+                    log::info!("synthesize_to_file: 等待 {:?} 后重试", delay);
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+        attempt += 1;
+    }
+}
+
+///
+/// 带断点的批量合成: 每段文本合成完成(收到 response.done)并落盘后更新断点文件,
+/// 进程重启后用同样的参数再次调用, 会跳过断点中已完成的文本, 返回本次写入的音频字节数
+///
+/// 输出文件会先截断到断点记录的长度, 丢弃上次崩溃时未完成那一段的音频;
+/// 输出文件缺失或比断点记录的短时返回错误, 不会补零后继续;
+/// 使用 commit 模式逐段提交, 保证每个 response.done 对应一段文本
+pub async fn synthesize_with_checkpoint(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    texts: &[&str],
+    output: &Path,
+    checkpoint_path: &Path,
+) -> Result<u64, QwenTtsError> {
+    let mut checkpoint = resume_from_checkpoint(checkpoint_path)?;
+    let remaining = checkpoint.remaining(texts)?;
+    log::info!(
+        "synthesize_with_checkpoint: 已完成 {} 段, 剩余 {} 段",
+        checkpoint.completed.len(),
+        remaining.len()
+    );
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(output)?;
+    let output_len = file.metadata()?.len();
+    if output_len < checkpoint.output_offset {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "输出文件只有 {} 字节, 短于断点记录的 {} 字节",
+                output_len, checkpoint.output_offset
+            ),
+        )
+        .into());
+    }
+    file.set_len(checkpoint.output_offset)?;
+    file.seek(SeekFrom::End(0))?;
+    if remaining.is_empty() {
+        return Ok(0);
+    }
+
+    let mut qwen_tts_realtime = builder.clone().pull_events(true).connect().await?;
+    qwen_tts_realtime
+        .update_session(voice, response_format, "commit")
+        .await?;
+    let mut written = 0u64;
+    for text in remaining {
+        qwen_tts_realtime.append_text(text).await?;
+        qwen_tts_realtime.commit().await?;
+        let mut chunk_bytes = 0u64;
+        loop {
+            match qwen_tts_realtime.recv().await {
+                Some(ServerEvent::Event(v)) if v["type"] == "response.audio.delta" => {
+                    let delta = v["delta"].as_str().unwrap_or_default();
+                    let audio_bytes = base64::engine::general_purpose::STANDARD
+                        .decode(delta)
+                        .map_err(|e| {
+                            QwenTtsError::ProtocolViolation(format!("音频数据base64解码失败: {}", e))
+                        })?;
+                    file.write_all(&audio_bytes)?;
+                    chunk_bytes += audio_bytes.len() as u64;
+                }
+                Some(ServerEvent::Event(v)) if v["type"] == "response.done" => {
+                    if v["response"]["status"] == "failed" {
+                        return Err(QwenTtsError::SessionClosedEarly(format!(
+                            "文本合成失败: {}",
+                            v["response"]["status_details"]
+                        )));
+                    }
+                    break;
+                }
+                Some(ServerEvent::Event(_)) => {}
+                Some(ServerEvent::Closed(_)) | None => {
+                    return Err(QwenTtsError::SessionClosedEarly(
+                        "连接在当前文本合成完成之前断开".to_string(),
+                    ));
+                }
+            }
+        }
+        // 先保证音频落盘再记录断点, 断点不会超前于输出文件
+        file.sync_data()?;
+        checkpoint.complete(text, chunk_bytes);
+        save_checkpoint(checkpoint_path, &checkpoint)?;
+        written += chunk_bytes;
+    }
+    qwen_tts_realtime.finish().await?;
+    Ok(written)
+}
+
+///
+/// 流式合成: texts 每产出一段文本就立即 append, texts 结束后 finish;
+/// 返回解码后的音频流, 连接关闭时音频流结束
+///
+/// 文本的发送在后台任务中进行, 发送失败只记录日志并停止发送, 音频流会随连接关闭而结束
+pub async fn synthesize_stream<S>(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    texts: S,
+) -> Result<impl Stream<Item = Vec<u8>> + Send + 'static, QwenTtsError>
+where
+    S: Stream<Item = String> + Send + 'static,
+{
+    let mut qwen_tts_realtime = builder.clone().connect().await?;
+    qwen_tts_realtime
+        .update_session(voice, response_format, "server_commit")
+        .await?;
+    // 先订阅再发送文本, 避免漏掉最早的音频分片
+    let audio_stream = qwen_tts_realtime.audio_stream();
+    tokio::spawn(async move {
+        let mut texts = Box::pin(texts);
+        while let Some(text) = texts.next().await {
+            if let Err(e) = qwen_tts_realtime.append_text(&text).await {
+                log::error!("synthesize_stream: 发送文本失败: {}", e);
+                return;
+            }
+        }
+        if let Err(e) = qwen_tts_realtime.finish().await {
+            log::error!("synthesize_stream: 发送finish失败: {}", e);
+        }
+    });
+    Ok(audio_stream)
+}
+
+///
+/// synthesize_to_vec 的结果, 出错时 audio 中保留出错前已经收到的音频
+#[derive(Debug)]
+pub struct CollectedAudio {
+    pub audio: Vec<u8>,
+    pub error: Option<QwenTtsError>,
+}
+
+impl CollectedAudio {
+    /// 出错时丢弃部分音频, 只返回错误
+    pub fn into_result(self) -> Result<Vec<u8>, QwenTtsError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.audio),
+        }
+    }
+}
+
+///
+/// 合成全部文本并把音频收集到内存中; 为避免过长的合成耗尽内存,
+/// 可以在 builder 上设置 max_audio_bytes, 超过时返回已收到的部分音频和 AudioSizeLimitExceeded;
+/// 不限制等待时间, 批处理时用 synthesize_to_vec_with_timeout 或 QwenTtsRealtime::synthesize_all
+pub async fn synthesize_to_vec(
+    builder: &QwenTtsRealtimeBuilder,
+    config: &SessionConfig,
+    texts: &[&str],
+) -> CollectedAudio {
+    collect_audio(builder, config, texts, None).await
+}
+
+///
+/// 与 synthesize_to_vec 相同, 从建连到收到 session.finished 最多等待timeout,
+/// 超时时关闭连接, 返回已收到的部分音频和 QwenTtsError::Timeout; 适合批处理/离线场景防止卡住
+pub async fn synthesize_to_vec_with_timeout(
+    builder: &QwenTtsRealtimeBuilder,
+    config: &SessionConfig,
+    texts: &[&str],
+    timeout: Duration,
+) -> CollectedAudio {
+    collect_audio(builder, config, texts, Some(timeout)).await
+}
+
+async fn collect_audio(
+    builder: &QwenTtsRealtimeBuilder,
+    config: &SessionConfig,
+    texts: &[&str],
+    timeout: Option<Duration>,
+) -> CollectedAudio {
+    let mut audio = vec![];
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    // 未设置超时时一直等待
+    let until_deadline = || async move {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    let timed_out = || {
+        QwenTtsError::Timeout(format!("{:?} 内没有完成合成", timeout.unwrap_or_default()))
+    };
+    let mut tts = tokio::select! {
+        connected = builder.clone().connect() => match connected {
+            Ok(tts) => tts,
+            Err(e) => return CollectedAudio { audio, error: Some(e) },
+        },
+        _ = until_deadline() => return CollectedAudio { audio, error: Some(timed_out()) },
+    };
+    let mut audio_stream = Box::pin(tts.audio_stream());
+    let send = async {
+        tts.update_session_config(config).await?;
+        for text in texts {
+            tts.append_text(text).await?;
+        }
+        tts.finish().await
+    };
+    let drain = async {
+        while let Some(audio_bytes) = audio_stream.next().await {
+            audio.extend_from_slice(&audio_bytes);
+        }
+    };
+    let sent = tokio::select! {
+        (sent, ()) = async { tokio::join!(send, drain) } => Some(sent),
+        _ = until_deadline() => None,
+    };
+    let error = match (tts.audio_limit_exceeded(), sent) {
+        (Some(limit), _) => Some(QwenTtsError::AudioSizeLimitExceeded { limit }),
+        (None, None) => {
+            tts.abort();
+            Some(timed_out())
+        }
+        (None, Some(Err(e))) => Some(e),
+        (None, Some(Ok(()))) if !tts.finish_summary().session_finished => Some(
+            QwenTtsError::SessionClosedEarly("连接在收到session.finished之前断开".to_string()),
+        ),
+        (None, Some(Ok(()))) => None,
+    };
+    if let Some(e) = &error {
+        log::error!("synthesize_to_vec: 合成失败, 已收到 {} 字节音频: {}", audio.len(), e);
+    }
+    CollectedAudio { audio, error }
+}
+
+///
+/// 逐行读取 reader(例如stdin), 每读到一行就提交合成, EOF时结束会话;
+/// 音频每到一个分片就写入 writer 并flush, 返回写入的字节数
+///
+/// 最后一行没有换行符时也会被合成, 空白行会被跳过
+pub async fn synthesize_lines<R, W>(
+    builder: &QwenTtsRealtimeBuilder,
+    voice: &str,
+    response_format: AudioFormat,
+    reader: R,
+    writer: &mut W,
+) -> Result<u64, QwenTtsError>
+where
+    R: AsyncBufRead + Send + Unpin + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let texts = futures_util::stream::unfold(reader.lines(), |mut lines| async move {
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => return Some((line.trim().to_string(), lines)),
+                Ok(None) => return None,
+                Err(e) => {
+                    log::error!("synthesize_lines: 读取输入失败: {}", e);
+                    return None;
+                }
+            }
+        }
+    });
+    let mut audio_stream = Box::pin(synthesize_stream(builder, voice, response_format, texts).await?);
+    let mut written = 0u64;
+    while let Some(audio_bytes) = audio_stream.next().await {
+        writer.write_all(&audio_bytes).await?;
+        writer.flush().await?;
+        written += audio_bytes.len() as u64;
+    }
+    Ok(written)
+}
+
+///
+/// 把流式文本依次送入已连接的 tts, 结束后 finish
+///
+/// detect_language 为true时检测每段文本的语言, 语言变化时先用新的 language_type 重发
+/// session.update 再 append, 适合中英混杂的LLM输出; 检测不出语言的片段沿用之前的提示
+pub async fn speak_text_stream<S>(
+    tts: &mut QwenTtsRealtime,
+    mut config: SessionConfig,
+    texts: S,
+    detect_language_hint: bool,
+) -> Result<(), QwenTtsError>
+where
+    S: Stream<Item = String>,
+{
+    tts.update_session_config(&config).await?;
+    let mut texts = Box::pin(texts);
+    while let Some(text) = texts.next().await {
+        if detect_language_hint
+            && let Some(language) = detect_language(&text)
+            && config.language_type.as_deref() != Some(language)
+        {
+            log::info!("检测到语言切换为 {}, 更新 language_type", language);
+            config.language_type = Some(language.to_string());
+            tts.update_session_config(&config).await?;
+        }
+        tts.append_text(&text).await?;
+    }
+    tts.finish().await
+}
+
+///
+/// 长会话自动切换: 单个会话累计提交的字符数或持续时间超过阈值后, 结束当前会话并新建会话继续;
+/// 两个阈值都不设置时不切换
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RolloverPolicy {
+    max_chars: Option<usize>,
+    max_duration: Option<Duration>,
+}
+
+impl RolloverPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 单个会话最多提交的字符数(按char计)
+    pub fn max_chars(mut self, max_chars: usize) -> Self {
+        self.max_chars = Some(max_chars);
+        self
+    }
+
+    /// 单个会话从建立连接起的最长时间
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    fn exceeded(&self, chars: usize, elapsed: Duration) -> bool {
+        self.max_chars.is_some_and(|max| chars >= max)
+            || self.max_duration.is_some_and(|max| elapsed >= max)
+    }
+}
+
+/// synthesize_with_rollover 中一个会话对应的输出片段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RolloverSegment {
+    /// 本会话第一段文本在输入中的下标
+    pub first_text: usize,
+    /// 本会话提交的文本段数
+    pub texts: usize,
+    /// 本会话音频在输出中的起始位置
+    pub output_offset: u64,
+    pub bytes: u64,
+}
+
+///
+/// 按 RolloverPolicy 把很长的文本流拆到多个会话中依次合成, 音频连续写入同一个 writer,
+/// 返回每个会话的片段边界. 切换时先等当前会话的音频全部写完再建立新会话, 音频不会交错或丢失;
+/// 阈值只在文本段之间检查, 单段文本不会被拆开
+pub async fn synthesize_with_rollover<S, W>(
+    builder: &QwenTtsRealtimeBuilder,
+    config: &SessionConfig,
+    texts: S,
+    policy: RolloverPolicy,
+    writer: &mut W,
+) -> Result<Vec<RolloverSegment>, QwenTtsError>
+where
+    S: Stream<Item = String>,
+    W: AsyncWrite + Unpin,
+{
+    let mut texts = Box::pin(texts);
+    let mut pending = texts.next().await;
+    let mut segments: Vec<RolloverSegment> = vec![];
+    let mut output_offset = 0u64;
+    let mut text_index = 0usize;
+    while pending.is_some() {
+        let mut tts = builder.clone().connect().await?;
+        tts.update_session_config(config).await?;
+        let mut audio_stream = Box::pin(tts.audio_stream());
+        let started = Instant::now();
+        let first_text = text_index;
+        let send = async {
+            let mut chars = 0usize;
+            while let Some(text) = pending.take() {
+                if text_index > first_text && policy.exceeded(chars, started.elapsed()) {
+                    pending = Some(text);
+                    break;
+                }
+                tts.append_text(&text).await?;
+                chars += text.chars().count();
+                text_index += 1;
+                pending = texts.next().await;
+            }
+            tts.finish().await
+        };
+        let drain = async {
+            let mut bytes = 0u64;
+            while let Some(audio_bytes) = audio_stream.next().await {
+                writer.write_all(&audio_bytes).await?;
+                bytes += audio_bytes.len() as u64;
+            }
+            writer.flush().await?;
+            Ok::<u64, QwenTtsError>(bytes)
+        };
+        let ((), bytes) = tokio::try_join!(send, drain)?;
+        if !tts.finish_summary().session_finished {
+            return Err(QwenTtsError::SessionClosedEarly(format!(
+                "第 {} 个会话在收到session.finished之前断开",
+                segments.len() + 1
+            )));
+        }
+        log::info!(
+            "synthesize_with_rollover: 第 {} 个会话完成, 文本 {}..{}, 音频 {} 字节",
+            segments.len() + 1,
+            first_text,
+            text_index,
+            bytes
+        );
+        segments.push(RolloverSegment {
+            first_text,
+            texts: text_index - first_text,
+            output_offset,
+            bytes,
+        });
+        output_offset += bytes;
+    }
+    Ok(segments)
+}
+
+/// 默认的分句标点: 中日文句读、英文标点和换行
+pub const DEFAULT_CLAUSE_BOUNDARIES: [char; 14] = [
+    '。', '！', '？', '；', '，', '、', '…', '\n', '.', '!', '?', ';', ',', ':',
+];
+
+/// 紧跟在分句标点之后、仍属于当前分句的右引号和右括号
+const CLOSING_MARKS: [char; 7] = ['”', '’', '」', '』', '）', '】', '》'];
+
+///
+/// 把LLM逐token输出的文本攒成以标点结尾的分句再交给TTS, 避免逐token append 造成的断句不自然
+///
+/// 连续的标点(如 "！？"、"……")和其后的右引号/右括号归入同一分句;
+/// ASCII标点后面必须跟空白才算分句, 避免拆开 "3.14"、"1,000" 这类数字
+#[derive(Debug, Clone)]
+pub struct ClauseBuffer {
+    boundaries: Vec<char>,
+    pending: String,
+}
+
+impl Default for ClauseBuffer {
+    fn default() -> Self {
+        Self::new(&DEFAULT_CLAUSE_BOUNDARIES)
+    }
+}
+
+impl ClauseBuffer {
+    pub fn new(boundaries: &[char]) -> Self {
+        Self {
+            boundaries: boundaries.to_vec(),
+            pending: String::new(),
+        }
+    }
+
+    /// 追加一段token, 返回已经完整的分句; 以标点结尾的部分要等下一个字符才能确定是否分句
+    pub fn push(&mut self, token: &str) -> Vec<String> {
+        self.pending.push_str(token);
+        let mut clauses = vec![];
+        let mut start = 0;
+        // 前一个字符处在标点串中时为 Some(该标点串是否需要后接空白)
+        let mut run: Option<bool> = None;
+        for (i, c) in self.pending.char_indices() {
+            if self.boundaries.contains(&c) {
+                run = Some(c.is_ascii_punctuation());
+                continue;
+            }
+            if let Some(needs_whitespace) = run {
+                if CLOSING_MARKS.contains(&c) {
+                    continue;
+                }
+                if !needs_whitespace || c.is_whitespace() {
+                    let clause = self.pending[start..i].trim();
+                    if !clause.is_empty() {
+                        clauses.push(clause.to_string());
+                    }
+                    start = i;
+                }
+            }
+            run = None;
+        }
+        self.pending.drain(..start);
+        clauses
+    }
+
+    /// 取出剩余的不完整分句, 文本流结束时调用
+    pub fn flush(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.pending);
+        let rest = rest.trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    }
+}
+
+///
+/// 把 Generation::call(stream + incremental_output) 返回的SSE响应直接朗读出来,
+/// 按 DEFAULT_CLAUSE_BOUNDARIES 分句后 append
+///
+/// 见 speak_text_stream; SSE由 Generation::response_stream 解析, 收到 data: [DONE] 时结束
+pub async fn speak_generation_stream(
+    response: reqwest::Response,
+    tts: &mut QwenTtsRealtime,
+    config: SessionConfig,
+    detect_language_hint: bool,
+) -> Result<(), QwenTtsError> {
+    speak_generation_stream_with_boundaries(
+        response,
+        tts,
+        config,
+        detect_language_hint,
+        &DEFAULT_CLAUSE_BOUNDARIES,
+    )
+    .await
+}
+
+/// 同 speak_generation_stream, boundaries 为分句标点
+pub async fn speak_generation_stream_with_boundaries(
+    response: reqwest::Response,
+    tts: &mut QwenTtsRealtime,
+    config: SessionConfig,
+    detect_language_hint: bool,
+    boundaries: &[char],
+) -> Result<(), QwenTtsError> {
+    if !response.status().is_success() {
+        let url = response.url().to_string();
+        return Err(GenerationError::DashScopeResponseError(format!(
+            "请求失败, url: {}, reason: {}",
+            url,
+            response.text().await.map_err(GenerationError::from)?
+        ))
+        .into());
+    }
+    // 读取或解析出错时结束文本流, 已经朗读的部分保留; 思考内容(reasoning_content)不朗读
+    let texts = Generation::response_stream(response)
+        .scan((), |_, chunk| {
+            futures_util::future::ready(match chunk {
+                Ok(chunk) => Some(chunk.content),
+                Err(e) => {
+                    log::error!("speak_generation_stream: 读取Generation响应失败: {}", e);
+                    None
+                }
+            })
+        })
+        .filter(|content| futures_util::future::ready(!content.is_empty()));
+    // 末尾补一个None, 用来取出最后不以标点结尾的部分
+    let clauses = texts
+        .map(Some)
+        .chain(futures_util::stream::once(async { None }))
+        .scan(ClauseBuffer::new(boundaries), |buffer, token| {
+            let clauses = match token {
+                Some(token) => buffer.push(&token),
+                None => buffer.flush().into_iter().collect(),
+            };
+            futures_util::future::ready(Some(futures_util::stream::iter(clauses)))
+        })
+        .flatten();
+    speak_text_stream(tts, config, clauses, detect_language_hint).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashscope::mock_server::{
+        MockAction, MockServerBuilder, audio_delta, default_handler, event, spawn_http_server,
+    };
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}_{}", uuid::Uuid::new_v4(), name))
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_to_file_retries_before_first_byte() {
+        let server = MockServerBuilder::new()
+            .reject(503, vec![])
+            .reject_first(2)
+            .spawn()
+            .await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let path = temp_path("retry.pcm");
+        let written = synthesize_to_file(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            &["你好", "世界"],
+            &path,
+            3,
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.connection_count(), 3);
+        assert_eq!(written, "你好世界".len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), "你好世界".as_bytes());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_target_format_resamples_to_16k_wav() {
+        // 服务端只输出 24kHz: 每段文本返回20ms(480个样本)的恒定值PCM
+        let pcm = 1000i16.to_le_bytes().repeat(480);
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(audio_delta(&pcm)),
+                    MockAction::Text(event("response.done")),
+                ],
+                _ => crate::dashscope::mock_server::default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let path = temp_path("target.wav");
+        let target: AudioFormat = "wav_16000_mono_16bit".parse().unwrap();
+        let written = synthesize_to_file_with_target(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            Some(target),
+            &["你好"],
+            &path,
+            ReconnectPolicy::new(1),
+        )
+        .await
+        .unwrap();
+
+        let wav = std::fs::read(&path).unwrap();
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16000);
+        let samples = wav[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect::<Vec<_>>();
+        // 24kHz 的480个样本重采样为 16kHz 的320个
+        assert_eq!(written, 640);
+        assert_eq!(samples.len(), 320);
+        assert!(samples.iter().all(|sample| *sample == 1000));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_target_format_uses_server_sample_rate() {
+        // 请求 24kHz, 服务端实际输出 16kHz 并在分片上注明, 目标也是 16kHz 时不应重采样
+        let pcm = 1000i16.to_le_bytes().repeat(320);
+        let mut delta: Value = serde_json::from_str(&audio_delta(&pcm)).unwrap();
+        delta["sample_rate"] = 16000.into();
+        let delta = delta.to_string();
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(delta.clone()),
+                    MockAction::Text(event("response.done")),
+                ],
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let path = temp_path("server_rate.wav");
+        let written = synthesize_to_file_with_target(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            Some("wav_16000_mono_16bit".parse().unwrap()),
+            &["你好"],
+            &path,
+            ReconnectPolicy::new(1),
+        )
+        .await
+        .unwrap();
+
+        let wav = std::fs::read(&path).unwrap();
+        assert_eq!(written, 640);
+        assert_eq!(&wav[44..], pcm.as_slice());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_hint_skips_backoff() {
+        // 第一次连接收到文本后服务端以1012(Service Restart)关闭, 第二次正常合成
+        let appends = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = MockServerBuilder::new()
+            .handler(move |event_json| {
+                if event_json["type"] == "input_text_buffer.append"
+                    && appends.fetch_add(1, Ordering::SeqCst) == 0
+                {
+                    return vec![MockAction::Close(1012, "service restart".to_string())];
+                }
+                crate::dashscope::mock_server::default_handler(event_json)
+            })
+            .spawn()
+            .await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let path = temp_path("reconnect.pcm");
+        let policy = ReconnectPolicy::new(2)
+            .backoff(Duration::from_secs(30), Duration::from_secs(30))
+            .jitter(Jitter::None);
+        let started = Instant::now();
+        let written = tokio::time::timeout(
+            Duration::from_secs(5),
+            synthesize_to_file_with_policy(
+                &builder,
+                "Cherry",
+                AudioFormat::PCM_24000HZ_MONO_16BIT,
+                &["你好"],
+                &path,
+                policy,
+            ),
+        )
+        .await
+        .expect("带重连提示时不应等待退避时间")
+        .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(server.connection_count(), 2);
+        assert_eq!(written, "你好".len() as u64);
+        let _ = std::fs::remove_file(&path);
+
+        let hinted = QwenTtsError::ReconnectRequested("1012".to_string());
+        let plain = QwenTtsError::SessionClosedEarly("closed".to_string());
+        assert_eq!(policy.delay(1, &hinted), Duration::ZERO);
+        assert_eq!(policy.delay(1, &plain), Duration::from_secs(30));
+        let policy = ReconnectPolicy::new(5)
+            .backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .jitter(Jitter::None);
+        assert_eq!(policy.delay(2, &plain), Duration::from_millis(200));
+        assert_eq!(policy.delay(4, &plain), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_jitter_delay_bounds() {
+        let plain = QwenTtsError::SessionClosedEarly("closed".to_string());
+        let initial = Duration::from_millis(100);
+        let max = Duration::from_secs(5);
+        let policy = |jitter| ReconnectPolicy::new(10).backoff(initial, max).jitter(jitter);
+        assert_eq!(ReconnectPolicy::new(1).jitter, Jitter::Full);
+        for attempt in 1..=8 {
+            let backoff = (initial * 2u32.pow(attempt - 1)).min(max);
+            for _ in 0..200 {
+                let full = policy(Jitter::Full).delay(attempt, &plain);
+                assert!(full <= backoff, "{:?} > {:?}", full, backoff);
+                let equal = policy(Jitter::Equal).delay(attempt, &plain);
+                assert!(equal >= backoff / 2 && equal <= backoff, "{:?}", equal);
+            }
+        }
+
+        // Decorrelated: 每次在 [initial, min(上一次*3, max)] 内
+        let decorrelated = policy(Jitter::Decorrelated);
+        let mut previous = Duration::ZERO;
+        let mut distinct = std::collections::HashSet::new();
+        for attempt in 1..=1000 {
+            let delay = decorrelated.delay_with_previous(attempt, &plain, previous);
+            let high = (previous.max(initial) * 3).min(max);
+            assert!(
+                delay >= initial && delay <= high,
+                "{:?} not in [{:?}, {:?}]",
+                delay,
+                initial,
+                high
+            );
+            distinct.insert(delay);
+            previous = delay;
+        }
+        assert!(distinct.len() > 100);
+        // 服务端要求重连时不退避, 不受抖动影响
+        let hinted = QwenTtsError::ReconnectRequested("1012".to_string());
+        assert_eq!(decorrelated.delay(3, &hinted), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint_skips_done_chunks() {
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let output = temp_path("checkpoint.pcm");
+        let checkpoint_path = temp_path("checkpoint.json");
+        // 模拟上次进程在第二段写到一半时崩溃
+        std::fs::write(&output, "第一段半截".as_bytes()).unwrap();
+        let mut checkpoint = crate::dashscope::checkpoint::Checkpoint::default();
+        checkpoint.complete("第一段", "第一段".len() as u64);
+        save_checkpoint(&checkpoint_path, &checkpoint).unwrap();
+
+        let texts = ["第一段", "第二段", "第三段"];
+        let written = synthesize_with_checkpoint(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            &texts,
+            &output,
+            &checkpoint_path,
+        )
+        .await
+        .unwrap();
+        let appended = server
+            .received_json()
+            .iter()
+            .filter(|v| v["type"] == "input_text_buffer.append")
+            .map(|v| v["text"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(appended, vec!["第二段", "第三段"]);
+        assert_eq!(written, "第二段第三段".len() as u64);
+        assert_eq!(std::fs::read(&output).unwrap(), "第一段第二段第三段".as_bytes());
+        let checkpoint = resume_from_checkpoint(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.completed, texts);
+        assert_eq!(checkpoint.output_offset, "第一段第二段第三段".len() as u64);
+        let _ = std::fs::remove_file(&output);
+        let _ = std::fs::remove_file(&checkpoint_path);
+    }
+
+    #[tokio::test]
+    async fn test_resume_from_checkpoint_rejects_short_output() {
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let output = temp_path("checkpoint_short.pcm");
+        let checkpoint_path = temp_path("checkpoint_short.json");
+        let mut checkpoint = crate::dashscope::checkpoint::Checkpoint::default();
+        checkpoint.complete("第一段", "第一段".len() as u64);
+        save_checkpoint(&checkpoint_path, &checkpoint).unwrap();
+
+        // 输出文件缺失, 以及比断点记录的短
+        let _ = std::fs::remove_file(&output);
+        for existing in [None, Some("第一".as_bytes())] {
+            if let Some(existing) = existing {
+                std::fs::write(&output, existing).unwrap();
+            }
+            let result = synthesize_with_checkpoint(
+                &builder,
+                "Cherry",
+                AudioFormat::PCM_24000HZ_MONO_16BIT,
+                &["第一段", "第二段"],
+                &output,
+                &checkpoint_path,
+            )
+            .await;
+            assert!(matches!(result, Err(QwenTtsError::IoError(_))), "{:?}", result);
+            assert_eq!(std::fs::read(&output).unwrap(), existing.unwrap_or_default());
+        }
+        assert_eq!(server.connection_count(), 0);
+        let _ = std::fs::remove_file(&output);
+        let _ = std::fs::remove_file(&checkpoint_path);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_keeps_audio_continuous() {
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let config =
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit");
+        let texts = futures_util::stream::iter(
+            ["你好", "世界", "再见", "朋友", "晚安"].map(String::from),
+        );
+        let mut output = Vec::new();
+        let segments = synthesize_with_rollover(
+            &builder,
+            &config,
+            texts,
+            RolloverPolicy::new().max_chars(4),
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.connection_count(), 3);
+        assert_eq!(output, "你好世界再见朋友晚安".as_bytes());
+        let segment_bytes = "你好世界".len() as u64;
+        assert_eq!(
+            segments,
+            vec![
+                RolloverSegment {
+                    first_text: 0,
+                    texts: 2,
+                    output_offset: 0,
+                    bytes: segment_bytes,
+                },
+                RolloverSegment {
+                    first_text: 2,
+                    texts: 2,
+                    output_offset: segment_bytes,
+                    bytes: segment_bytes,
+                },
+                RolloverSegment {
+                    first_text: 4,
+                    texts: 1,
+                    output_offset: segment_bytes * 2,
+                    bytes: segment_bytes / 2,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_lines_from_piped_input() {
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        // 包含空行和没有换行符结尾的最后一行
+        let input: &'static [u8] = "你好\n世界\n\n最后一行".as_bytes();
+        let mut output = Vec::new();
+        let written = synthesize_lines(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            input,
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output, "你好世界最后一行".as_bytes());
+        assert_eq!(written, output.len() as u64);
+        let texts = server
+            .received_json()
+            .iter()
+            .filter(|v| v["type"] == "input_text_buffer.append")
+            .map(|v| v["text"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["你好", "世界", "最后一行"]);
+        assert_eq!(server.received_types().last().unwrap(), "session.finish");
+    }
+
+    #[tokio::test]
+    async fn test_max_audio_bytes_keeps_partial_audio() {
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(audio_delta(&[1u8; 100])),
+                    MockAction::Text(audio_delta(&[2u8; 100])),
+                    MockAction::Text(audio_delta(&[3u8; 100])),
+                    MockAction::Text(event("response.done")),
+                ],
+                Some("session.finish") => vec![
+                    MockAction::Text(event("session.finished")),
+                    MockAction::Close(1000, "bye".to_string()),
+                ],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let config =
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit");
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+
+        // 默认不限制
+        let collected = synthesize_to_vec(&builder, &config, &["你好"]).await;
+        assert!(collected.error.is_none(), "{:?}", collected.error);
+        assert_eq!(collected.audio.len(), 300);
+
+        let collected =
+            synthesize_to_vec(&builder.clone().max_audio_bytes(250), &config, &["你好"]).await;
+        assert!(matches!(
+            collected.error,
+            Some(QwenTtsError::AudioSizeLimitExceeded { limit: 250 })
+        ));
+        assert_eq!(collected.audio, [[1u8; 100], [2u8; 100]].concat());
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_to_vec_with_timeout() {
+        // finish 之后服务端不再响应
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("session.finish") => vec![],
+                _ => default_handler(event_json),
+            })
+            .spawn()
+            .await;
+        let config =
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit");
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let started = Instant::now();
+        let collected = synthesize_to_vec_with_timeout(
+            &builder,
+            &config,
+            &["你好", "世界"],
+            Duration::from_millis(300),
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(matches!(collected.error, Some(QwenTtsError::Timeout(_))));
+        assert_eq!(collected.audio, "你好世界".as_bytes());
+
+        // 正常结束时与 synthesize_to_vec 相同
+        let server = MockServerBuilder::new().spawn().await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let audio = synthesize_to_vec_with_timeout(
+            &builder,
+            &config,
+            &["你好"],
+            Duration::from_secs(5),
+        )
+        .await
+        .into_result()
+        .unwrap();
+        assert_eq!(audio, "你好".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_speak_text_stream_switches_language_hint() {
+        let server = MockServerBuilder::new().spawn().await;
+        let mut tts = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        let texts = futures_util::stream::iter(
+            ["你好，我是通义千问。", "我可以帮你写代码。", "Nice to meet you!", "123"]
+                .map(String::from),
+        );
+        speak_text_stream(
+            &mut tts,
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit"),
+            texts,
+            true,
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let received = server.received_json();
+        let actions = received
+            .iter()
+            .map(|v| match v["type"].as_str().unwrap() {
+                "session.update" => format!(
+                    "update:{}",
+                    v["session"]["language_type"].as_str().unwrap_or("-")
+                ),
+                "input_text_buffer.append" => format!("append:{}", v["text"].as_str().unwrap()),
+                other => other.to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                "update:-",
+                "update:Chinese",
+                "append:你好，我是通义千问。",
+                "append:我可以帮你写代码。",
+                "update:English",
+                "append:Nice to meet you!",
+                "append:123",
+                "session.finish",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_speak_generation_stream() {
+        let base_url = spawn_http_server(|_| {
+            let reasoning = serde_json::json!({"output": {"choices": [{"message": {"content": "", "reasoning_content": "思考", "role": "assistant"}}]}});
+            let mut sse = format!("id:0\ndata:{}\n\n", reasoning);
+            sse.extend(["你好。", "Hello there."].iter().map(|content| {
+                format!(
+                    "id:1\ndata:{}\n\n",
+                    serde_json::json!({"output": {"choices": [{"message": {"content": content, "role": "assistant"}}]}})
+                )
+            }));
+            // [DONE] 之后的内容不再朗读
+            sse.push_str("data: [DONE]\n\ndata:{\"output\":{\"text\":\"多余\"}}\n\n");
+            (200, sse)
+        })
+        .await;
+        let response = reqwest::get(&base_url).await.unwrap();
+        let server = MockServerBuilder::new().spawn().await;
+        let mut tts = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        speak_generation_stream(
+            response,
+            &mut tts,
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit"),
+            true,
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let texts = server
+            .received_json()
+            .iter()
+            .filter(|v| v["type"] == "input_text_buffer.append")
+            .map(|v| v["text"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["你好。", "Hello there."]);
+    }
+
+    #[tokio::test]
+    async fn test_llm_tokens_buffered_to_clauses() {
+        let base_url = spawn_http_server(|_| {
+            let tokens = [
+                "你好", "，", "我是通义", "千问", "。", "“很高兴", "认识你！”", "Nice ", "to meet",
+                " you! Pi", " is 3", ".14", ", roughly", "\n", "最后", "一句",
+            ];
+            let sse = tokens
+                .iter()
+                .map(|content| {
+                    format!(
+                        "data:{}\n\n",
+                        serde_json::json!({"output": {"choices": [{"message": {"content": content}}]}})
+                    )
+                })
+                .collect::<String>();
+            (200, sse)
+        })
+        .await;
+        let response = reqwest::get(&base_url).await.unwrap();
+        let server = MockServerBuilder::new().spawn().await;
+        let mut tts = QwenTtsRealtimeBuilder::new("test-api-key")
+            .url(&server.url)
+            .connect()
+            .await
+            .unwrap();
+        speak_generation_stream(
+            response,
+            &mut tts,
+            SessionConfig::new("Cherry", AudioFormat::PCM_24000HZ_MONO_16BIT, "server_commit"),
+            false,
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let texts = server
+            .received_json()
+            .iter()
+            .filter(|v| v["type"] == "input_text_buffer.append")
+            .map(|v| v["text"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                "你好，",
+                "我是通义千问。",
+                "“很高兴认识你！”",
+                "Nice to meet you!",
+                "Pi is 3.14,",
+                "roughly",
+                "最后一句",
+            ]
+        );
+
+        // 自定义分句标点
+        let mut buffer = ClauseBuffer::new(&['。']);
+        assert!(buffer.push("你好，世界").is_empty());
+        assert_eq!(buffer.push("。再见"), vec!["你好，世界。"]);
+        assert_eq!(buffer.flush().as_deref(), Some("再见"));
+        assert_eq!(buffer.flush(), None);
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_to_file_no_retry_after_audio() {
+        // 返回音频后在finish时异常断开
+        let server = MockServerBuilder::new()
+            .handler(|event_json| match event_json["type"].as_str() {
+                Some("input_text_buffer.append") => vec![
+                    MockAction::Text(audio_delta(b"pcm")),
+                    MockAction::Text(event("response.done")),
+                ],
+                Some("session.finish") => vec![MockAction::Close(1011, "internal".to_string())],
+                _ => vec![],
+            })
+            .spawn()
+            .await;
+        let builder = QwenTtsRealtimeBuilder::new("test-api-key").url(&server.url);
+        let path = temp_path("partial.pcm");
+        let result = synthesize_to_file(
+            &builder,
+            "Cherry",
+            AudioFormat::PCM_24000HZ_MONO_16BIT,
+            &["你好"],
+            &path,
+            3,
+        )
+        .await;
+        assert!(matches!(result, Err(QwenTtsError::SessionClosedEarly(_))));
+        assert_eq!(server.connection_count(), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), b"pcm");
+        let _ = std::fs::remove_file(&path);
+    }
+}
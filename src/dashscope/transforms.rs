@@ -0,0 +1,417 @@
+//!
+//! ## 解码后音频样本的处理
+//! 在 audio_stream 的解码路径上对 16bit PCM 样本做就地变换(增益、响度归一等),
+//! 非 16bit PCM 的输出格式不会经过变换(会话中第一次跳过时记一条warn日志); PcmConverter 用于在不同采样率/声道/位深的PCM之间转换,
+//! FrameAlignment 用于让输出的样本数对齐到编码器的帧长
+use crate::dashscope::qwen_tts_realtime::AudioFormat;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+///
+/// 样本变换, 按注册顺序依次作用在每个音频分片解码后的样本上
+pub trait SampleTransform {
+    fn process(&self, samples: &mut [i16]);
+}
+
+///
+/// 固定增益, 单位dB; 超出 i16 范围的样本会被截断
+pub struct GainTransform(pub f32);
+
+impl SampleTransform for GainTransform {
+    fn process(&self, samples: &mut [i16]) {
+        let factor = 10f32.powf(self.0 / 20.0);
+        for sample in samples.iter_mut() {
+            *sample = (*sample as f32 * factor)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+}
+
+///
+/// 峰值归一: 按目前为止见过的最大峰值把音频放大/缩小到 target_peak
+///
+/// 峰值是跨分片累计的, 同一会话内增益只会单调下降, 避免逐片归一带来的音量忽大忽小;
+/// 增益不超过 max_gain_db(默认20dB), 峰值低于噪声门限的分片(如开头的静音)不计入峰值,
+/// 在见到高于门限的分片之前原样输出, 避免把底噪放大成满幅度的突发
+pub struct NormalizeTransform {
+    target_peak: i16,
+    max_gain: f32,
+    noise_floor: u16,
+    running_peak: AtomicU16,
+}
+
+/// NormalizeTransform 默认的最大增益
+pub const DEFAULT_NORMALIZE_MAX_GAIN_DB: f32 = 20.0;
+/// NormalizeTransform 默认的噪声门限, 约 -54dBFS
+pub const DEFAULT_NORMALIZE_NOISE_FLOOR: u16 = 64;
+
+impl NormalizeTransform {
+    pub fn new(target_peak: i16) -> Self {
+        Self {
+            target_peak,
+            max_gain: 10f32.powf(DEFAULT_NORMALIZE_MAX_GAIN_DB / 20.0),
+            noise_floor: DEFAULT_NORMALIZE_NOISE_FLOOR,
+            running_peak: AtomicU16::new(0),
+        }
+    }
+
+    /// 最大增益, 单位dB
+    pub fn max_gain_db(mut self, max_gain_db: f32) -> Self {
+        self.max_gain = 10f32.powf(max_gain_db / 20.0);
+        self
+    }
+
+    /// 噪声门限, 峰值(绝对值)低于它的分片不计入峰值, 0 表示所有分片都计入
+    pub fn noise_floor(mut self, noise_floor: u16) -> Self {
+        self.noise_floor = noise_floor;
+        self
+    }
+}
+
+impl Default for NormalizeTransform {
+    /// 默认归一到 -1dBFS 左右
+    fn default() -> Self {
+        Self::new(29204)
+    }
+}
+
+impl SampleTransform for NormalizeTransform {
+    fn process(&self, samples: &mut [i16]) {
+        let chunk_peak = samples
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap_or(0);
+        let peak = if chunk_peak < self.noise_floor {
+            self.running_peak.load(Ordering::SeqCst)
+        } else {
+            self.running_peak
+                .fetch_max(chunk_peak, Ordering::SeqCst)
+                .max(chunk_peak)
+        };
+        if peak == 0 {
+            return;
+        }
+        let factor = (self.target_peak as f32 / peak as f32).min(self.max_gain);
+        for sample in samples.iter_mut() {
+            *sample = (*sample as f32 * factor)
+                .round()
+                .clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+}
+
+///
+/// 把小端 16bit PCM 字节依次交给变换处理后写回, 末尾不足一个样本的字节保持不变
+pub(crate) fn apply_pcm16(
+    transforms: &[std::sync::Arc<dyn SampleTransform + Send + Sync>],
+    audio_bytes: &mut [u8],
+) {
+    if transforms.is_empty() {
+        return;
+    }
+    let mut samples = audio_bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect::<Vec<_>>();
+    for transform in transforms {
+        transform.process(&mut samples);
+    }
+    for (bytes, sample) in audio_bytes.chunks_exact_mut(2).zip(samples) {
+        bytes.copy_from_slice(&sample.to_le_bytes());
+    }
+}
+
+///
+/// 流式的PCM格式转换: 重采样(线性插值) + 声道数 + 位深, 输入输出都是小端;
+/// 分片可以在任意字节处切开, 不足一帧的字节和插值需要的上一帧会留到下一次 convert
+pub struct PcmConverter {
+    from: AudioFormat,
+    to: AudioFormat,
+    pending: Vec<u8>,
+    // 上一个分片的最后一帧(已转换声道), 作为插值的起点
+    last_frame: Option<Vec<f32>>,
+    // 下一个输出样本相对 last_frame 的位置, 单位为输入帧
+    position: f64,
+}
+
+impl PcmConverter {
+    pub fn new(from: AudioFormat, to: AudioFormat) -> Self {
+        Self {
+            from,
+            to,
+            pending: vec![],
+            last_frame: None,
+            position: 0.0,
+        }
+    }
+
+    /// 采样率、声道数、位深都相同时不需要转换
+    pub fn is_identity(from: AudioFormat, to: AudioFormat) -> bool {
+        from.sample_rate() == to.sample_rate()
+            && from.channel_count() == to.channel_count()
+            && from.bits_per_sample() == to.bits_per_sample()
+    }
+
+    pub fn convert(&mut self, audio_bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(audio_bytes);
+        let in_channels = self.from.channel_count() as usize;
+        let in_width = self.from.bits_per_sample() as usize / 8;
+        let whole = self.pending.len() / (in_channels * in_width) * (in_channels * in_width);
+        let frames = self.pending[..whole]
+            .chunks_exact(in_channels * in_width)
+            .map(|frame| {
+                let samples = frame
+                    .chunks_exact(in_width)
+                    .map(decode_sample)
+                    .collect::<Vec<_>>();
+                remix(&samples, self.to.channel_count() as usize)
+            })
+            .collect::<Vec<_>>();
+        self.pending.drain(..whole);
+
+        let resampled = if self.from.sample_rate() == self.to.sample_rate() {
+            frames
+        } else {
+            self.resample(frames)
+        };
+        let out_width = self.to.bits_per_sample() as usize / 8;
+        let mut out = Vec::with_capacity(resampled.len() * self.to.channel_count() as usize * out_width);
+        for sample in resampled.iter().flatten() {
+            encode_sample(*sample, out_width, &mut out);
+        }
+        out
+    }
+
+    fn resample(&mut self, frames: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        let step = self.from.sample_rate() as f64 / self.to.sample_rate() as f64;
+        let mut input = self.last_frame.take().into_iter().collect::<Vec<_>>();
+        input.extend(frames);
+        let mut out = vec![];
+        while (self.position.floor() as usize) + 1 < input.len() {
+            let index = self.position.floor() as usize;
+            let t = (self.position - index as f64) as f32;
+            let (a, b) = (&input[index], &input[index + 1]);
+            out.push(a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect());
+            self.position += step;
+        }
+        if let Some(last) = input.pop() {
+            self.position -= input.len() as f64;
+            self.last_frame = Some(last);
+        }
+        out
+    }
+}
+
+/// 把一个小端样本解码到 [-1, 1], 8bit 为无符号(WAV约定)
+fn decode_sample(bytes: &[u8]) -> f32 {
+    match bytes.len() {
+        1 => (bytes[0] as f32 - 128.0) / 128.0,
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+        3 => (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8388608.0,
+        _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0,
+    }
+}
+
+fn encode_sample(sample: f32, width: usize, out: &mut Vec<u8>) {
+    let sample = sample.clamp(-1.0, 1.0) as f64;
+    match width {
+        1 => out.push((sample * 127.0 + 128.0).round() as u8),
+        2 => out.extend_from_slice(&((sample * 32767.0).round() as i16).to_le_bytes()),
+        3 => out.extend_from_slice(&((sample * 8388607.0).round() as i32).to_le_bytes()[..3]),
+        _ => out.extend_from_slice(&((sample * 2147483647.0).round() as i32).to_le_bytes()),
+    }
+}
+
+/// 单声道复制到所有声道, 多声道转单声道取平均
+fn remix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if samples.len() == channels {
+        return samples.to_vec();
+    }
+    if channels == 1 {
+        return vec![samples.iter().sum::<f32>() / samples.len() as f32];
+    }
+    (0..channels)
+        .map(|channel| samples[channel.min(samples.len() - 1)])
+        .collect()
+}
+
+/// 会话结束时最后不足一帧的样本的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlignMode {
+    /// 补零到整帧, 不丢失音频, 结尾多出不到一帧的静音
+    #[default]
+    Pad,
+    /// 丢弃不足一帧的尾部, 不引入额外的静音, 但会截掉结尾不到一帧的音频
+    Trim,
+}
+
+///
+/// 把解码后的PCM按编码器的帧长对齐, 用于 Opus/AAC 等要求输入为整帧的编码器;
+/// 帧长按每声道的样本数计, 如 Opus 20ms@24kHz 为 480, AAC 为 1024
+///
+/// 对齐会把每个分片中不足一帧的部分留到下一个分片, 输出比原始分片最多晚一帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameAlignment {
+    pub frame_samples: usize,
+    pub mode: AlignMode,
+}
+
+impl FrameAlignment {
+    pub fn new(frame_samples: usize, mode: AlignMode) -> Self {
+        Self {
+            frame_samples: frame_samples.max(1),
+            mode,
+        }
+    }
+}
+
+pub(crate) struct FrameAligner {
+    frame_bytes: usize,
+    mode: AlignMode,
+    pending: Vec<u8>,
+}
+
+impl FrameAligner {
+    pub fn new(alignment: FrameAlignment, format: AudioFormat) -> Self {
+        let block_align = format.channel_count() as usize * (format.bits_per_sample() as usize / 8);
+        Self {
+            frame_bytes: alignment.frame_samples * block_align.max(1),
+            mode: alignment.mode,
+            pending: vec![],
+        }
+    }
+
+    /// 返回可以输出的整帧部分, 可能为空
+    pub fn push(&mut self, audio_bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(audio_bytes);
+        let aligned = self.pending.len() / self.frame_bytes * self.frame_bytes;
+        self.pending.drain(..aligned).collect()
+    }
+
+    /// 会话结束时按 AlignMode 处理剩余不足一帧的部分
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        let mut rest = std::mem::take(&mut self.pending);
+        if rest.is_empty() {
+            return None;
+        }
+        match self.mode {
+            AlignMode::Pad => {
+                rest.resize(self.frame_bytes, 0);
+                Some(rest)
+            }
+            AlignMode::Trim => {
+                log::debug!("帧对齐: 丢弃结尾不足一帧的 {} 字节", rest.len());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_gain_6db_doubles_with_clipping() {
+        let mut samples = vec![0, 1000, -1000, 10000, -10000, 20000, -20000];
+        GainTransform(6.0).process(&mut samples);
+        for (sample, original) in samples.iter().zip([0i32, 1000, -1000, 10000, -10000]) {
+            // +6dB 约等于 1.995 倍
+            assert!((*sample as i32 - original * 2).abs() <= original.abs() / 100);
+        }
+        assert_eq!(samples[5], i16::MAX);
+        assert_eq!(samples[6], i16::MIN);
+    }
+
+    #[test]
+    fn test_normalize_running_peak() {
+        let normalize = NormalizeTransform::new(20000);
+        let mut first = vec![100, -5000];
+        normalize.process(&mut first);
+        assert_eq!(first, vec![400, -20000]);
+        // 后续分片峰值更小时沿用之前的峰值
+        let mut second = vec![2500];
+        normalize.process(&mut second);
+        assert_eq!(second, vec![10000]);
+    }
+
+    #[test]
+    fn test_normalize_leading_silence() {
+        let normalize = NormalizeTransform::new(20000);
+        // 开头的静音低于噪声门限, 原样输出
+        let mut silence = vec![2, -3, 1];
+        normalize.process(&mut silence);
+        assert_eq!(silence, vec![2, -3, 1]);
+        // 之后较小的语音最多放大 20dB, 不会被拉到目标峰值
+        let mut quiet = vec![100, -200];
+        normalize.process(&mut quiet);
+        assert_eq!(quiet, vec![1000, -2000]);
+        let mut loud = vec![10000];
+        normalize.process(&mut loud);
+        assert_eq!(loud, vec![20000]);
+        // 已经确定峰值后, 门限以下的分片按同样的增益处理
+        let mut tail = vec![3];
+        normalize.process(&mut tail);
+        assert_eq!(tail, vec![6]);
+
+        let unlimited = NormalizeTransform::new(20000).max_gain_db(100.0).noise_floor(0);
+        let mut silence = vec![2];
+        unlimited.process(&mut silence);
+        assert_eq!(silence, vec![20000]);
+    }
+
+    #[test]
+    fn test_frame_aligner_pad_and_trim() {
+        let format = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        // 4个样本一帧, 即8字节
+        let mut aligner = FrameAligner::new(FrameAlignment::new(4, AlignMode::Pad), format);
+        assert!(aligner.push(&[1; 6]).is_empty());
+        assert_eq!(aligner.push(&[2; 6]), [vec![1; 6], vec![2; 2]].concat());
+        assert_eq!(aligner.finish(), Some([vec![2; 4], vec![0; 4]].concat()));
+        assert_eq!(aligner.finish(), None);
+
+        let mut aligner = FrameAligner::new(FrameAlignment::new(4, AlignMode::Trim), format);
+        assert_eq!(aligner.push(&[1; 20]).len(), 16);
+        assert_eq!(aligner.finish(), None);
+    }
+
+    #[test]
+    fn test_pcm_converter_resample_24k_to_16k() {
+        let from = AudioFormat::PCM_24000HZ_MONO_16BIT;
+        let to: AudioFormat = "pcm_16000_mono_16bit".parse().unwrap();
+        // 24kHz 下 480 个样本(20ms)的 500Hz 正弦
+        let sine = |rate: f64, i: usize| (2.0 * std::f64::consts::PI * 500.0 * i as f64 / rate).sin() * 16000.0;
+        let input = (0..480)
+            .flat_map(|i| (sine(24000.0, i).round() as i16).to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let whole = PcmConverter::new(from, to).convert(&input);
+        // 按奇数字节切开分片, 结果与一次性转换一致
+        let mut converter = PcmConverter::new(from, to);
+        let chunked = input.chunks(97).flat_map(|chunk| converter.convert(chunk)).collect::<Vec<_>>();
+        assert_eq!(chunked, whole);
+
+        let samples = whole
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64)
+            .collect::<Vec<_>>();
+        assert_eq!(samples.len(), 320);
+        for (i, sample) in samples.iter().enumerate() {
+            assert!((sample - sine(16000.0, i)).abs() < 200.0, "{}: {}", i, sample);
+        }
+    }
+
+    #[test]
+    fn test_apply_pcm16_little_endian() {
+        let transforms: Vec<Arc<dyn SampleTransform + Send + Sync>> =
+            vec![Arc::new(GainTransform(6.0))];
+        let mut audio_bytes = [1000i16.to_le_bytes(), (-3i16).to_le_bytes()].concat();
+        audio_bytes.push(0x7f);
+        apply_pcm16(&transforms, &mut audio_bytes);
+        assert_eq!(i16::from_le_bytes([audio_bytes[0], audio_bytes[1]]), 1995);
+        assert_eq!(i16::from_le_bytes([audio_bytes[2], audio_bytes[3]]), -6);
+        assert_eq!(audio_bytes[4], 0x7f);
+    }
+}
@@ -0,0 +1,19 @@
+//!
+//! # qwen_tts_falsh_realtime_rs
+//! 通义千问实时语音合成(qwen-tts-realtime)的 WebSocket 客户端
+//!
+//! 常用类型在crate根路径重新导出:
+//! - QwenTtsRealtimeBuilder / QwenTtsRealtime: 建立连接, update_session -> append_text -> finish
+//! - QwenTtsRealtimeCallback: 同步回调, QwenTtsEvent 为解析后的服务端事件
+//! - AudioFormat: 输出音频格式
+//! - QwenTtsError / GenerationError: 错误类型
+pub mod common;
+pub mod dashscope;
+pub mod odps;
+
+pub use common::errors::{GenerationError, QwenTtsError};
+pub use dashscope::events::{QwenTtsEvent, TimestampMark};
+pub use dashscope::qwen_tts_realtime::{
+    AudioFormat, QwenTtsRealtime, QwenTtsRealtimeBuilder, QwenTtsRealtimeCallback,
+    Voice, prepare_qwen_tts_realtime,
+};
@@ -1,23 +1,23 @@
-use dashscope::qwen_tts_realtime::{prepare_qwen_tts_realtime, AudioFormat, QwenTtsRealtimeCallback};
-use base64::Engine;
+use qwen_tts_falsh_realtime_rs::common::logging::{LogOptions, init_logger_with};
+use qwen_tts_falsh_realtime_rs::dashscope::qwen_tts_realtime::FinishOutcome;
+use qwen_tts_falsh_realtime_rs::dashscope::synthesis::synthesize_lines;
+use qwen_tts_falsh_realtime_rs::{
+    AudioFormat, QwenTtsError, QwenTtsEvent, QwenTtsRealtimeBuilder, QwenTtsRealtimeCallback,
+    prepare_qwen_tts_realtime,
+};
 use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use tokio::sync::Mutex;
 
-pub mod dashscope;
-pub mod odps;
-pub mod common;
-
 struct MyCallback {
     file: File,
-    session_finished: Arc<AtomicBool>,
 }
 
 impl MyCallback {
-    fn new(filename: &str, session_finished: Arc<AtomicBool>) -> Self {
+    fn new(filename: &str) -> Self {
         let p = Path::new(filename);
         if !p.exists() || !p.is_file() {
             if let Some(parent) = p.parent() {
@@ -29,10 +29,7 @@ impl MyCallback {
             .create(true)
             .open(p)
             .unwrap();
-        Self {
-            file,
-            session_finished,
-        }
+        Self { file }
     }
 }
 
@@ -47,49 +44,99 @@ impl QwenTtsRealtimeCallback for MyCallback {
 
     fn on_finish(&mut self, close_msg: &str) {
         log::info!("Session finished: {}", close_msg);
-        self.session_finished.store(true, Ordering::Relaxed);
     }
 
-    fn on_event(&mut self, message: &str) -> bool {
-        log::info!("Received event: {}", message);
-        let v: serde_json::Value = serde_json::from_str(message).unwrap();
-        if let Some(event_type) = v.get("type")
-            && let Some(event_type_str) = event_type.as_str()
-        {
-            match event_type_str {
-                "session.created" => {
-                    log::info!("event: session created");
-                }
-                "response.audio.delta" => {
-                    log::info!("event: response audio delta");
-                    if let Some(recv_audio_b64) = v.get("delta")
-                        && let Some(recv_audio_b64_str) = recv_audio_b64.as_str()
-                    {
-                        let audio_bytes = base64::engine::general_purpose::STANDARD
-                            .decode(recv_audio_b64_str)
-                            .unwrap();
-                        self.file.write_all(&audio_bytes).unwrap();
-                    }
-                }
-                "response.done" => {
-                    log::info!("event: response done");
-                }
-                "session.finished" => {
-                    log::info!("event: session finished");
-                    return true;
-                }
-                _ => {
-                    log::info!("unknown event type: {}", event_type_str);
-                }
+    fn on_event(&mut self, _message: &str) -> bool {
+        false
+    }
+
+    fn on_typed_event(&mut self, event: QwenTtsEvent) -> bool {
+        match event {
+            QwenTtsEvent::SessionCreated { .. } => log::info!("event: session created"),
+            QwenTtsEvent::ResponseAudioDelta { audio } => {
+                log::info!("event: response audio delta");
+                self.file.write_all(&audio).unwrap();
+            }
+            QwenTtsEvent::ResponseDone { .. } => log::info!("event: response done"),
+            QwenTtsEvent::SessionFinished => {
+                log::info!("event: session finished");
+                return true;
             }
+            QwenTtsEvent::Unknown(v) => log::info!("unknown event: {}", v),
         }
         false
     }
+
+    fn typed_events(&self) -> bool {
+        true
+    }
+}
+
+/// 日志目录和旧日志压缩由环境变量 QWEN_TTS_LOG_DIR、QWEN_TTS_LOG_COMPRESS=1 控制,
+/// 压缩需要以 log-compress feature 编译
+fn log_options() -> LogOptions {
+    let mut options = LogOptions::default();
+    #[cfg(feature = "log-compress")]
+    {
+        options = options
+            .compress_rotated(std::env::var("QWEN_TTS_LOG_COMPRESS").is_ok_and(|v| v == "1"));
+    }
+    if let Ok(directory) = std::env::var("QWEN_TTS_LOG_DIR") {
+        options = options.directory(&directory);
+    }
+    options
+}
+
+///
+/// `echo "text" | qwen_tts_falsh_realtime_rs --stdin [--format pcm_16000] > out.pcm`
+/// 逐行读取stdin合成, 音频(默认24k 16bit 单声道 PCM)写到stdout, 日志只输出到stderr
+async fn run_stdin_mode() {
+    let _logger = init_logger_with("info", &log_options());
+    let args = std::env::args().collect::<Vec<_>>();
+    let response_format = match args.iter().position(|arg| arg == "--format") {
+        Some(i) => match args.get(i + 1).map(|format| format.parse::<AudioFormat>()) {
+            Some(Ok(format)) => format,
+            Some(Err(e)) => {
+                log::error!("{}", e);
+                std::process::exit(2);
+            }
+            None => {
+                log::error!("--format 缺少参数");
+                std::process::exit(2);
+            }
+        },
+        None => AudioFormat::PCM_24000HZ_MONO_16BIT,
+    };
+    let builder = QwenTtsRealtimeBuilder::from_env();
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    let mut stdout = tokio::io::stdout();
+    match synthesize_lines(
+        &builder,
+        "Cherry",
+        response_format,
+        stdin,
+        &mut stdout,
+    )
+    .await
+    {
+        Ok(written) => log::info!("合成完成, 写入 {} 字节", written),
+        Err(e @ QwenTtsError::MissingApiKey) => {
+            log::error!("连接失败: {}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            log::error!("合成失败: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let session_finished = Arc::new(AtomicBool::new(false));
+    if std::env::args().any(|arg| arg == "--stdin") {
+        run_stdin_mode().await;
+        return;
+    }
     let text_to_synthesize = [
         "对吧~我就特别喜欢这种超市，",
         "尤其是过年的时候",
@@ -98,11 +145,17 @@ async fn main() {
         "超级超级开心！",
         "想买好多好多的东西呢。",
     ];
-    let session_finished_arc_clone = Arc::clone(&session_finished);
-    let mut qwen_tts_realtime = prepare_qwen_tts_realtime(Some(Arc::new(Mutex::new(Box::new(
-        MyCallback::new("result_24k.pcm", session_finished_arc_clone),
-    )))))
-    .await;
+    let mut qwen_tts_realtime = match prepare_qwen_tts_realtime(Some(Arc::new(Mutex::new(
+        Box::new(MyCallback::new("result_24k.pcm")),
+    ))))
+    .await
+    {
+        Ok(qwen_tts_realtime) => qwen_tts_realtime,
+        Err(e) => {
+            log::error!("连接失败: {}", e);
+            std::process::exit(1);
+        }
+    };
     let _ = qwen_tts_realtime
         .update_session(
             "Cherry",
@@ -114,11 +167,18 @@ async fn main() {
         let _ = qwen_tts_realtime.append_text(text).await;
     }
     let _ = qwen_tts_realtime.finish().await;
-    loop {
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        if session_finished.load(Ordering::Relaxed) {
-            println!("TTS 任務已自動完成。");
-            break;
+    match qwen_tts_realtime
+        .wait_until_finished(Duration::from_secs(120))
+        .await
+    {
+        FinishOutcome::Finished => println!("TTS 任務已自動完成。"),
+        FinishOutcome::ClosedEarly(close_info) => {
+            log::error!("连接在合成完成之前断开: {:?}", close_info);
+            std::process::exit(1);
+        }
+        FinishOutcome::Timeout => {
+            log::error!("等待合成完成超时");
+            std::process::exit(1);
         }
     }
 }
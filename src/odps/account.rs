@@ -2,7 +2,7 @@ use crate::common::errors::GenerationError;
 use base64::Engine;
 use base64::engine::general_purpose;
 use bstr::ByteSlice;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
 use log::debug;
 use reqwest::Request;
@@ -49,6 +49,84 @@ impl<'a> AliyunAccount<'a> {
             base_url: "https://www.aliyun.com",
         }
     }
+
+    ///
+    /// 与 calc_auth_str 相同, date_str 为v4签名使用的日期(如 20260303), 便于用固定日期验证签名;
+    /// region_name 为None时使用v2签名, 忽略 date_str
+    pub fn calc_auth_str_at(
+        &self,
+        canonical_str: &str,
+        region_name: Option<&str>,
+        date_str: &str,
+    ) -> Result<String, GenerationError> {
+        type HmacSha1 = Hmac<Sha1>;
+        if let Some(region_name) = region_name {
+            type HmacSha256 = Hmac<sha2::Sha256>;
+            let credential = [
+                self.access_id,
+                date_str,
+                region_name,
+                "odps/aliyun_v4_request",
+            ]
+            .join("/");
+            let k_secret = format!("{}{}", "aliyun_v4", self.secret_key);
+            // k_date
+            let mut k_date = HmacSha256::new_from_slice(k_secret.as_bytes())?;
+            k_date.update(date_str.as_bytes());
+            let k_date_digest = k_date.finalize().into_bytes();
+            #[cfg(test)]
+            debug!("k_date_digest: {:?}", k_date_digest.as_bstr());
+            // k_region
+            let mut k_region = HmacSha256::new_from_slice(k_date_digest.as_slice())?;
+            k_region.update(region_name.as_bytes());
+            let k_region_digest = k_region.finalize().into_bytes();
+            #[cfg(test)]
+            debug!("k_region_digest: {:?}", k_region_digest.as_bstr());
+            // k_service
+            let mut k_service = HmacSha256::new_from_slice(k_region_digest.as_slice())?;
+            k_service.update("odps".as_bytes());
+            let k_service_digest = k_service.finalize().into_bytes();
+            #[cfg(test)]
+            debug!("k_service_digest: {:?}", k_service_digest.as_bstr());
+            // signature_key
+            let mut signature_key = HmacSha256::new_from_slice(k_service_digest.as_slice())?;
+            signature_key.update("aliyun_v4_request".as_bytes());
+            let v4_signature_key_digest = signature_key.finalize().into_bytes();
+            #[cfg(test)]
+            debug!(
+                "v4_signature_key_digest: {:?}",
+                v4_signature_key_digest.as_bstr()
+            );
+            // signature
+            let mut signature = HmacSha1::new_from_slice(v4_signature_key_digest.as_slice())?;
+            signature.update(canonical_str.as_bytes());
+            let sig_str = general_purpose::STANDARD
+                .encode(signature.finalize().into_bytes().as_slice())
+                .to_string();
+            Ok(format!("ODPS {}:{}", credential, sig_str))
+        } else {
+            let mut signature = HmacSha1::new_from_slice(self.secret_key.as_bytes())?;
+            signature.update(canonical_str.as_bytes());
+            let sig_str = general_purpose::STANDARD
+                .encode(signature.finalize().into_bytes().as_slice())
+                .to_string();
+            Ok(format!("ODPS {}:{}", self.access_id, sig_str))
+        }
+    }
+}
+
+impl AliyunAccount<'_> {
+    /// v4签名的日期取自请求的 Date 头(RFC1123), 保证与参与签名的 Date 一致; 无法解析时取当前日期
+    fn sign_date(header: &HeaderMap) -> String {
+        header
+            .get("date")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|dt| dt.with_timezone(&Utc))
+            .unwrap_or_else(Utc::now)
+            .format("%Y%m%d")
+            .to_string()
+    }
 }
 
 impl SignRequest for AliyunAccount<'_> {
@@ -94,13 +172,11 @@ impl SignRequest for AliyunAccount<'_> {
             if let Some(sorted_url_query) = u.query() {
                 let canonical_str =
                     Self::build_canonical_str(header, &method, u.path(), sorted_url_query)?;
+                let date_str = Self::sign_date(header);
+                let auth_str =
+                    self.calc_auth_str_at(canonical_str.as_str(), region_name, &date_str)?;
                 #[cfg(test)]
-                debug!(
-                    "canonical str: {}\nauth_str: {}",
-                    canonical_str,
-                    self.calc_auth_str(canonical_str.as_str(), region_name)?
-                );
-                let auth_str = self.calc_auth_str(canonical_str.as_str(), region_name)?;
+                debug!("canonical str: {}\nauth_str: {}", canonical_str, auth_str);
                 request
                     .headers_mut()
                     .insert("Authorization", auth_str.parse()?);
@@ -116,8 +192,8 @@ impl SignRequest for AliyunAccount<'_> {
         sorted_url_query: &str,
     ) -> Result<String, GenerationError> {
         let mut header_to_sign = BTreeMap::new();
-        // 插入date
-        if !header.contains_key("date") || !header.contains_key("Date") {
+        // 插入date, HeaderMap 的键不区分大小写
+        if !header.contains_key("date") {
             let dt = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
             header.insert("Date", dt.parse()?);
         }
@@ -163,60 +239,8 @@ impl SignRequest for AliyunAccount<'_> {
         canonical_str: &str,
         region_name: Option<&str>,
     ) -> Result<String, GenerationError> {
-        type HmacSha1 = Hmac<Sha1>;
-        if let Some(region_name) = region_name {
-            type HmacSha256 = Hmac<sha2::Sha256>;
-            let date_str = Utc::now().format("%Y%m%d").to_string();
-            let credential = [
-                self.access_id,
-                date_str.as_str(),
-                region_name,
-                "odps/aliyun_v4_request",
-            ]
-            .join("/");
-            let k_secret = format!("{}{}", "aliyun_v4", self.secret_key);
-            // k_date
-            let mut k_date = HmacSha256::new_from_slice(k_secret.as_bytes())?;
-            k_date.update(date_str.as_bytes());
-            let k_date_digest = k_date.finalize().into_bytes();
-            #[cfg(test)]
-            debug!("k_date_digest: {:?}", k_date_digest.as_bstr());
-            // k_region
-            let mut k_region = HmacSha256::new_from_slice(k_date_digest.as_slice())?;
-            k_region.update(region_name.as_bytes());
-            let k_region_digest = k_region.finalize().into_bytes();
-            #[cfg(test)]
-            debug!("k_region_digest: {:?}", k_region_digest.as_bstr());
-            // k_service
-            let mut k_service = HmacSha256::new_from_slice(k_region_digest.as_slice())?;
-            k_service.update("odps".as_bytes());
-            let k_service_digest = k_service.finalize().into_bytes();
-            #[cfg(test)]
-            debug!("k_service_digest: {:?}", k_service_digest.as_bstr());
-            // signature_key
-            let mut signature_key = HmacSha256::new_from_slice(k_service_digest.as_slice())?;
-            signature_key.update("aliyun_v4_request".as_bytes());
-            let v4_signature_key_digest = signature_key.finalize().into_bytes();
-            #[cfg(test)]
-            debug!(
-                "v4_signature_key_digest: {:?}",
-                v4_signature_key_digest.as_bstr()
-            );
-            // signature
-            let mut signature = HmacSha1::new_from_slice(v4_signature_key_digest.as_slice())?;
-            signature.update(canonical_str.as_bytes());
-            let sig_str = general_purpose::STANDARD
-                .encode(signature.finalize().into_bytes().as_slice())
-                .to_string();
-            Ok(format!("ODPS {}:{}", credential, sig_str))
-        } else {
-            let mut signature = HmacSha1::new_from_slice(self.secret_key.as_bytes())?;
-            signature.update(canonical_str.as_bytes());
-            let sig_str = general_purpose::STANDARD
-                .encode(signature.finalize().into_bytes().as_slice())
-                .to_string();
-            Ok(format!("ODPS {}:{}", self.access_id, sig_str))
-        }
+        let date_str = Utc::now().format("%Y%m%d").to_string();
+        self.calc_auth_str_at(canonical_str, region_name, &date_str)
     }
 }
 
@@ -229,9 +253,76 @@ pub fn test_account<'a>() -> AliyunAccount<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::odps::account::{SignRequest, test_account};
+    use crate::odps::account::{AliyunAccount, SignRequest, test_account};
     use reqwest::Client;
 
+    #[test]
+    fn test_calc_auth_str_fixture() {
+        let account = AliyunAccount::new("test-access-id", "test-secret-key");
+        let mut request = Client::new()
+            .get("https://service.cn-hangzhou.maxcompute.aliyun.com/api/projects/test_project")
+            .header("Date", "Tue, 03 Mar 2026 21:42:52 GMT")
+            .build()
+            .unwrap();
+        let canonical_str = AliyunAccount::build_canonical_str(
+            request.headers_mut(),
+            "GET",
+            "/projects/test_project",
+            "",
+        )
+        .unwrap();
+        assert_eq!(
+            canonical_str,
+            "GET\n\n\nTue, 03 Mar 2026 21:42:52 GMT\n/projects/test_project"
+        );
+        // 预先按v4签名规则独立计算的结果
+        assert_eq!(
+            account
+                .calc_auth_str_at(&canonical_str, Some("cn-hangzhou"), "20260303")
+                .unwrap(),
+            "ODPS test-access-id/20260303/cn-hangzhou/odps/aliyun_v4_request:\
+             B+tZLGxyWVUbq7gjAEYJG8EL460="
+        );
+        assert_eq!(
+            account.calc_auth_str_at(&canonical_str, None, "20260303").unwrap(),
+            "ODPS test-access-id:wb342R5ggsPaZiJzf3q4JeLmNJc="
+        );
+    }
+
+    #[test]
+    fn test_sign_request_fixture() {
+        let account = AliyunAccount::new("test-access-id", "test-secret-key");
+        let endpoint = "https://service.cn-hangzhou.maxcompute.aliyun.com/api";
+        // 签名日期取自 Date 头而不是当前日期
+        let mut request = Client::new()
+            .get(format!("{}/projects/test_project", endpoint))
+            .header("Date", "Tue, 03 Mar 2026 21:42:52 GMT")
+            .build()
+            .unwrap();
+        account
+            .sign_request(&mut request, endpoint, Some("cn-hangzhou"))
+            .unwrap();
+        assert_eq!(
+            request.headers()["Authorization"],
+            "ODPS test-access-id/20260303/cn-hangzhou/odps/aliyun_v4_request:\
+             B+tZLGxyWVUbq7gjAEYJG8EL460="
+        );
+
+        // 没有 Date 头时补上RFC1123格式的当前时间
+        let mut request = Client::new()
+            .get(format!("{}/projects/test_project", endpoint))
+            .build()
+            .unwrap();
+        account.sign_request(&mut request, endpoint, None).unwrap();
+        let date = request.headers()["Date"].to_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc2822(date).is_ok(), "{}", date);
+        assert!(date.ends_with(" GMT"));
+        assert!(request.headers()["Authorization"]
+            .to_str()
+            .unwrap()
+            .starts_with("ODPS test-access-id:"));
+    }
+
     #[tokio::test]
     async fn test_build_canonical_str() {
         let account = test_account();
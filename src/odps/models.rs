@@ -1,4 +1,6 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 ///
 /// ## 用于存放一些Tunnel相关的返回
@@ -143,4 +145,145 @@ pub struct UploadedBlock{
     #[serde(rename = "Version")]
     pub version: u64,
 
-}
\ No newline at end of file
+}
+
+///
+/// 项目元数据, GET /projects/test_dat_maxcompute 的返回结果(XML)
+/// ```xml
+/// <?xml version="1.0" encoding="UTF-8"?>
+/// <Project>
+///     <Name>test_dat_maxcompute</Name>
+///     <Type>managed</Type>
+///     <Comment></Comment>
+///     <State>AVAILABLE</State>
+///     <ProjectGroupName>group_1</ProjectGroupName>
+///     <Properties>
+///         <Property>
+///             <Name>odps.schema.evolution.enable</Name>
+///             <Value>true</Value>
+///         </Property>
+///     </Properties>
+///     <Owner>ALIYUN$xxx@aliyun.com</Owner>
+///     <CreationTime>Wed, 04 Feb 2026 10:00:00 GMT</CreationTime>
+///     <LastModifiedTime>Wed, 04 Feb 2026 10:00:00 GMT</LastModifiedTime>
+/// </Project>
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OdpsProject {
+    pub name: String,
+    pub project_type: Option<String>,
+    pub comment: Option<String>,
+    pub state: Option<String>,
+    pub owner: Option<String>,
+    pub project_group_name: Option<String>,
+    pub creation_time: Option<String>,
+    pub last_modified_time: Option<String>,
+    pub properties: BTreeMap<String, String>,
+    /// 请求时指定的schema, 未指定时为None
+    pub schema_name: Option<String>,
+}
+
+impl OdpsProject {
+    /// 解析返回的XML, 没有 Project/Name 节点时返回None
+    pub fn from_xml(xml: &str) -> Option<Self> {
+        let project = xml_element(xml, "Project")?;
+        // Property 中也有 Name 节点, 先取出属性再解析项目本身的字段
+        let properties_block = Regex::new(r"(?s)<(Extended)?Properties>.*?</(Extended)?Properties>")
+            .ok()?;
+        let fields = properties_block.replace_all(&project, "");
+        let property = Regex::new(r"(?s)<Property>\s*<Name>(.*?)</Name>\s*<Value>(.*?)</Value>")
+            .ok()?;
+        let properties = xml_element(&project, "Properties")
+            .map(|block| {
+                property
+                    .captures_iter(&block)
+                    .map(|c| (xml_unescape(&c[1]), xml_unescape(&c[2])))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let field = |tag: &str| {
+            xml_element(&fields, tag)
+                .filter(|v| !v.is_empty())
+                .map(|v| xml_unescape(&v))
+        };
+        Some(Self {
+            name: field("Name")?,
+            project_type: field("Type"),
+            comment: field("Comment"),
+            state: field("State"),
+            owner: field("Owner"),
+            project_group_name: field("ProjectGroupName"),
+            creation_time: field("CreationTime"),
+            last_modified_time: field("LastModifiedTime"),
+            properties,
+            schema_name: None,
+        })
+    }
+}
+
+/// 第一个 tag 节点的原始内容(未反转义), 不支持同名嵌套; 只对最终取出的叶子节点反转义
+fn xml_element(xml: &str, tag: &str) -> Option<String> {
+    let regex = Regex::new(&format!(r"(?s)<{0}>(.*?)</{0}>", tag)).ok()?;
+    regex.captures(xml).map(|c| c[1].trim().to_string())
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_odps_project_from_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Project>
+    <Name>test_dat_maxcompute</Name>
+    <Type>managed</Type>
+    <Comment></Comment>
+    <State>AVAILABLE</State>
+    <Properties>
+        <Property>
+            <Name>odps.schema.evolution.enable</Name>
+            <Value>true</Value>
+        </Property>
+        <Property><Name>odps.timezone</Name><Value>Asia/Shanghai</Value></Property>
+    </Properties>
+    <ExtendedProperties>
+        <Property><Name>storage</Name><Value>1024</Value></Property>
+    </ExtendedProperties>
+    <Owner>ALIYUN$dev&amp;ops@aliyun.com</Owner>
+    <CreationTime>Wed, 04 Feb 2026 10:00:00 GMT</CreationTime>
+</Project>"#;
+        let project = OdpsProject::from_xml(xml).unwrap();
+        assert_eq!(project.name, "test_dat_maxcompute");
+        assert_eq!(project.project_type.as_deref(), Some("managed"));
+        assert_eq!(project.comment, None);
+        assert_eq!(project.owner.as_deref(), Some("ALIYUN$dev&ops@aliyun.com"));
+        assert_eq!(project.last_modified_time, None);
+        assert_eq!(project.properties.len(), 2);
+        assert_eq!(project.properties["odps.timezone"], "Asia/Shanghai");
+        assert!(OdpsProject::from_xml("<Error><Code>NoSuchObject</Code></Error>").is_none());
+    }
+
+    #[test]
+    fn test_odps_project_from_xml_unescapes_once() {
+        // Comment 中转义后的 <Name> 不能被当成节点, &amp;lt; 只反转义一次
+        let xml = r#"<Project>
+    <Comment>&lt;Name&gt;fake&lt;/Name&gt; a &amp;lt; b</Comment>
+    <Name>real&amp;name</Name>
+    <Properties>
+        <Property><Name>k&amp;lt;</Name><Value>&amp;gt;v</Value></Property>
+    </Properties>
+</Project>"#;
+        let project = OdpsProject::from_xml(xml).unwrap();
+        assert_eq!(project.name, "real&name");
+        assert_eq!(project.comment.as_deref(), Some("<Name>fake</Name> a &lt; b"));
+        assert_eq!(project.properties["k&lt;"], "&gt;v");
+    }
+}
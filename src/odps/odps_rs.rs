@@ -3,7 +3,8 @@ use crate::odps::account::SignRequest;
 use crate::odps::async_odps_arrow_writer::AsyncOdpsArrowWriter;
 use crate::odps::constants::insert_default_tunnel_header;
 use crate::odps::models::{
-    TunnelDownloadSession, TunnelTableSchema, TunnelUploadSession, TunnelUploadedBlocks,
+    OdpsProject, TunnelDownloadSession, TunnelTableSchema, TunnelUploadSession,
+    TunnelUploadedBlocks,
 };
 use crate::odps::odps_arrow_reader::OdpsArrowReader;
 use crate::odps::odps_arrow_writer::OdpsArrowWriter;
@@ -15,7 +16,7 @@ use bytes::Bytes;
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error, info, warn};
 use reqwest::header::HeaderMap;
-use reqwest::{Body, Method};
+use reqwest::{Body, Method, Url};
 use std::cmp::{max, min};
 use std::fs::File;
 use std::io::{Read, Write};
@@ -86,6 +87,41 @@ where
         Ok(response.text().await?)
     }
 
+    ///
+    /// 获取项目元数据, 请求带 sign_request 计算的 Authorization;
+    /// project_name 为None时使用创建 Odps 时的项目, schema_name 以 curr_schema 参数传给服务端
+    pub async fn get_project(
+        &self,
+        project_name: Option<&str>,
+        schema_name: Option<&str>,
+    ) -> Result<OdpsProject, GenerationError> {
+        let project_name = project_name.unwrap_or(self.project_name);
+        let mut url = Url::parse(&format!("{}/projects/{}", self.endpoint, project_name))?;
+        if let Some(schema_name) = schema_name {
+            url.query_pairs_mut().append_pair("curr_schema", schema_name);
+        }
+        let response = self
+            .rest_client
+            .request(url.as_str(), Method::GET, self.endpoint, None, None)
+            .await?;
+        let status = response.status();
+        let content = response.text().await?;
+        debug!("Project text: {}", content);
+        if !status.is_success() {
+            return Err(GenerationError::OdpsResponseError {
+                status: status.as_u16(),
+                body: content,
+            });
+        }
+        let mut project =
+            OdpsProject::from_xml(&content).ok_or(GenerationError::OdpsResponseError {
+                status: status.as_u16(),
+                body: content,
+            })?;
+        project.schema_name = schema_name.map(str::to_string);
+        Ok(project)
+    }
+
     /// - headers:
     /// ```json
     /// {
@@ -387,7 +423,8 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::odps::account::test_account;
+    use crate::dashscope::mock_server::spawn_http_server;
+    use crate::odps::account::{AliyunAccount, test_account};
     use arrow::array::record_batch;
     use log::info;
     use std::fs::File;
@@ -408,6 +445,37 @@ mod tests {
         println!("{}", logview_host);
     }
 
+    #[tokio::test]
+    async fn test_get_project() {
+        let targets = Arc::new(std::sync::Mutex::new(vec![]));
+        let received = Arc::clone(&targets);
+        let endpoint = spawn_http_server(move |target| {
+            received.lock().unwrap().push(target.to_string());
+            if target.contains("/tunnel") {
+                return (200, "dt.cn-hangzhou.maxcompute.aliyun.com".to_string());
+            }
+            let xml = "<Project><Name>test_dat_maxcompute</Name><State>AVAILABLE</State></Project>";
+            (200, xml.to_string())
+        })
+        .await;
+        let account = AliyunAccount::new("test-access-id", "test-secret-key");
+        let odps = Odps::new(account, &endpoint, "test_dat_maxcompute").await;
+        let project = odps.get_project(None, None).await.unwrap();
+        assert_eq!(project.name, "test_dat_maxcompute");
+        assert_eq!(project.schema_name, None);
+        // schema 名称按查询参数编码
+        let project = odps.get_project(None, Some("my schema&x")).await.unwrap();
+        assert_eq!(project.schema_name.as_deref(), Some("my schema&x"));
+        let targets = targets.lock().unwrap();
+        assert_eq!(
+            targets[1..],
+            [
+                "/projects/test_dat_maxcompute",
+                "/projects/test_dat_maxcompute?curr_schema=my+schema%26x",
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_create_download_session() {
         let account = test_account();